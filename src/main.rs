@@ -1,32 +1,283 @@
-use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand};
 
 mod simulations;
 
-enum SimulationType {
-    Main,
-    Grid,
+use simulations::field::FIELD_FILE;
+use simulations::grid_file::{COLLISION_GRID_FILE, JOINT_GRID_FILE};
+use simulations::main::PRESETS_FILE;
+use simulations::nt4::NT_PORT;
+use simulations::robot::ROBOT_FILE;
+
+/// 2D simulator for the 2025 elevator and arm
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Options for `main`, which runs when no subcommand is given
+    #[command(flatten)]
+    main: MainArgs,
 }
 
-impl SimulationType {
-    fn from_str(s: &str) -> Option<Self> {
-        match s {
-            "main" => Some(Self::Main),
-            "grid" => Some(Self::Grid),
-            _ => None,
-        }
+#[derive(Subcommand)]
+enum Command {
+    /// Interactive sim with presets, controllers, NetworkTables and HALSim
+    Main(MainArgs),
+    /// Sweep the arm workspace and save the collision grid
+    Grid(GridArgs),
+    /// Play back elevator and arm positions from a WPILOG file
+    Replay(ReplayArgs),
+    /// Check the robot config, presets, collision grid and field against each other
+    Validate(ValidateArgs),
+    /// Write the presets as a Java enum for the robot project
+    Export(ExportArgs),
+}
+
+#[derive(Args)]
+struct RobotArgs {
+    /// Robot description file
+    #[arg(long, value_name = "FILE", default_value = ROBOT_FILE)]
+    robot_config: String,
+}
+
+#[derive(Args)]
+struct PresetArgs {
+    /// Preset positions file
+    #[arg(long, value_name = "FILE", default_value = PRESETS_FILE)]
+    presets: String,
+}
+
+#[derive(Args)]
+struct GridFileArgs {
+    /// Collision grid file
+    #[arg(long, value_name = "FILE", default_value = COLLISION_GRID_FILE)]
+    grid_file: String,
+}
+
+#[derive(Args)]
+struct JointGridArgs {
+    /// Joint space grid used to plan paths, from `grid --cspace`
+    #[arg(long, value_name = "FILE", default_value = JOINT_GRID_FILE)]
+    joint_grid_file: String,
+}
+
+#[derive(Args)]
+struct FieldArgs {
+    /// Field element placement file
+    #[arg(long, value_name = "FILE", default_value = FIELD_FILE)]
+    field: String,
+}
+
+#[derive(Args)]
+struct SafetyArgs {
+    /// Clearance in inches targets and planned paths must keep from a
+    /// collision. Needs grids with clearance values.
+    #[arg(long, value_name = "INCHES", default_value_t = 0.0, value_parser = parse_margin)]
+    safety_margin: f32,
+}
+
+#[derive(Args)]
+struct MainArgs {
+    #[command(flatten)]
+    robot: RobotArgs,
+    #[command(flatten)]
+    presets: PresetArgs,
+    #[command(flatten)]
+    grid: GridFileArgs,
+    #[command(flatten)]
+    joint_grid: JointGridArgs,
+    #[command(flatten)]
+    field: FieldArgs,
+    #[command(flatten)]
+    safety: SafetyArgs,
+    /// Run without a window, driven over NetworkTables or HALSim
+    #[arg(long)]
+    headless: bool,
+    /// Record telemetry to <PATH>.wpilog and <PATH>.csv
+    #[arg(long, value_name = "PATH")]
+    log: Option<PathBuf>,
+    /// Port the NetworkTables server listens on
+    #[arg(long, value_name = "PORT", default_value_t = NT_PORT)]
+    nt_port: u16,
+}
+
+#[derive(Args)]
+struct GridArgs {
+    #[command(flatten)]
+    robot: RobotArgs,
+    /// Output file [default: collision_grid.bin, or cspace_grid.bin with --cspace]
+    #[arg(long, value_name = "FILE")]
+    grid_file: Option<String>,
+    /// Cell size in sim units (centimeters), or the arm angle step in degrees
+    /// with --cspace [default: 2, or 1 with --cspace]
+    #[arg(long, value_parser = parse_resolution)]
+    resolution: Option<f32>,
+    /// Elevator step of the joint space grid in sim units [default: 1]
+    #[arg(long, value_name = "UNITS", value_parser = parse_resolution, requires = "cspace")]
+    height_resolution: Option<f32>,
+    /// Sweep without a window and exit when done
+    #[arg(long)]
+    headless: bool,
+    /// Sweep joint space (arm angle against elevator height) instead
+    #[arg(long)]
+    cspace: bool,
+    /// Split cells on the collision boundary down to this size in sim units
+    #[arg(long, value_name = "UNITS", value_parser = parse_resolution, conflicts_with = "cspace")]
+    min_resolution: Option<f32>,
+    /// Time the headless sweep on one thread and on every core instead of
+    /// saving a grid [default resolution: half an inch]
+    #[arg(long, conflicts_with_all = ["cspace", "min_resolution"])]
+    benchmark: bool,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Log recorded by the robot or by `main --log`
+    file: PathBuf,
+    #[command(flatten)]
+    robot: RobotArgs,
+    #[command(flatten)]
+    grid: GridFileArgs,
+    /// Elevator height entry in meters [default: first entry ending in Elevator/Position]
+    #[arg(long, value_name = "NAME")]
+    elevator_entry: Option<String>,
+    /// Arm angle entry in radians [default: first entry ending in Arm/Angle]
+    #[arg(long, value_name = "NAME")]
+    arm_entry: Option<String>,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    #[command(flatten)]
+    robot: RobotArgs,
+    #[command(flatten)]
+    presets: PresetArgs,
+    #[command(flatten)]
+    grid: GridFileArgs,
+    #[command(flatten)]
+    joint_grid: JointGridArgs,
+    #[command(flatten)]
+    field: FieldArgs,
+    #[command(flatten)]
+    safety: SafetyArgs,
+    /// Seed for the random arm tip positions the grid is spot checked at
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    #[command(flatten)]
+    presets: PresetArgs,
+    /// Java file to write
+    #[arg(default_value = "ArmPreset.java")]
+    output: String,
+}
+
+fn parse_resolution(value: &str) -> Result<f32, String> {
+    let resolution: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if resolution.is_finite() && resolution > 0.0 {
+        Ok(resolution)
+    } else {
+        Err("must be a positive number".to_string())
+    }
+}
+
+fn parse_margin(value: &str) -> Result<f32, String> {
+    let margin: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if margin.is_finite() && margin >= 0.0 {
+        Ok(margin)
+    } else {
+        Err("must be zero or a positive number".to_string())
+    }
+}
+
+fn exit_code(success: bool) -> ExitCode {
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
     }
 }
 
-fn main() {
-    let sim_type = env::args()
-        .nth(1)
-        .and_then(|arg| SimulationType::from_str(&arg))
-        .unwrap_or(SimulationType::Main);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    let mut app = match sim_type {
-        SimulationType::Main => simulations::main::run(),
-        SimulationType::Grid => simulations::grid::run(),
+    let mut app = match cli.command.unwrap_or(Command::Main(cli.main)) {
+        Command::Main(args) => simulations::main::run(simulations::main::MainOptions {
+            robot_config: args.robot.robot_config,
+            presets: args.presets.presets,
+            grid_file: args.grid.grid_file,
+            joint_grid_file: args.joint_grid.joint_grid_file,
+            field: args.field.field,
+            safety_margin: args.safety.safety_margin,
+            headless: args.headless,
+            log: args.log,
+            nt_port: args.nt_port,
+        }),
+        Command::Grid(args) => {
+            if args.benchmark {
+                return exit_code(simulations::grid::benchmark(
+                    &args.robot.robot_config,
+                    args.resolution,
+                ));
+            }
+            let default_file = if args.cspace {
+                JOINT_GRID_FILE
+            } else {
+                COLLISION_GRID_FILE
+            };
+            let options = simulations::grid::GridOptions {
+                robot_config: args.robot.robot_config,
+                grid_file: args.grid_file.unwrap_or(default_file.to_string()),
+                resolution: args.resolution,
+                height_resolution: args.height_resolution,
+                headless: args.headless,
+                joint_space: args.cspace,
+                min_resolution: args.min_resolution,
+            };
+            match simulations::grid::run(options) {
+                Some(app) => app,
+                None => return ExitCode::FAILURE,
+            }
+        }
+        Command::Replay(args) => {
+            let options = simulations::main::ReplayOptions {
+                path: args.file,
+                robot_config: args.robot.robot_config,
+                grid_file: args.grid.grid_file,
+                elevator_entry: args.elevator_entry,
+                arm_entry: args.arm_entry,
+            };
+            match simulations::main::run_replay(options) {
+                Some(app) => app,
+                None => return ExitCode::FAILURE,
+            }
+        }
+        Command::Validate(args) => {
+            return exit_code(simulations::main::validate(
+                simulations::main::ValidateOptions {
+                    robot_config: args.robot.robot_config,
+                    presets: args.presets.presets,
+                    grid_file: args.grid.grid_file,
+                    joint_grid_file: args.joint_grid.joint_grid_file,
+                    field: args.field.field,
+                    safety_margin: args.safety.safety_margin,
+                    seed: args.seed,
+                },
+            ));
+        }
+        Command::Export(args) => {
+            return exit_code(simulations::main::export_presets(
+                &args.presets.presets,
+                &args.output,
+            ));
+        }
     };
 
     app.run();
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,480 @@
+use std::fs;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::simulations::robot::{RobotConfig, ELEVATOR, GAME_PIECE, INTAKE, SIM_UNITS_PER_INCH};
+
+pub const FIELD_FILE: &str = "field.ron";
+const BUILTIN_FIELD: &str = include_str!("../../field.ron");
+
+// Field element geometry from the 2025 game manual, in inches. Each element
+// is described in its own frame: x runs away from the robot starting at the
+// face nearest it, y runs up from the carpet.
+const BRANCH_RADIUS: f32 = 0.83; // 1.66 in PVC
+const BRANCH_ANGLE: f32 = 35.0; // Degrees from horizontal for L2 to L4
+const BRANCH_LENGTH: f32 = 12.0; // Angled section of L2 and L3
+const REEF_BASE: [f32; 2] = [14.0, 18.0]; // Trough depth and top height
+const REEF_POLE_X: f32 = 10.0;
+const L2_HEIGHT: f32 = 31.875;
+const L3_HEIGHT: f32 = 47.625;
+const L4_HEIGHT: f32 = 72.0;
+const L4_BEND: Vec2 = Vec2::new(4.0, 62.0); // Where L4 turns vertical
+const STATION_MOUTH_HEIGHT: f32 = 37.5;
+const STATION_CHUTE_ANGLE: f32 = 54.5;
+const STATION_CHUTE_LENGTH: f32 = 18.0;
+const STATION_CHUTE_RADIUS: f32 = 0.5;
+const PROCESSOR_OPENING: [f32; 2] = [7.0, 27.0]; // Bottom and top
+const PROCESSOR_HEIGHT: f32 = 50.0;
+const BARGE_SIZE: [f32; 2] = [12.0, 74.0]; // Depth and net height
+const WALL_THICKNESS: f32 = 2.0;
+
+/// How close (inches) a held piece's center must get to a scoring location
+const BRANCH_TOLERANCE: f32 = 3.0;
+const OPENING_TOLERANCE: f32 = 5.0;
+const BARGE_TOLERANCE: f32 = 8.0;
+/// Coral slides this far down a branch from its tip
+const CORAL_ON_BRANCH: f32 = 5.0;
+/// Center of a coral lying in the trough
+const CORAL_IN_TROUGH: Vec2 = Vec2::new(4.0, REEF_BASE[1] + 2.25);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ElementKind {
+    Reef,
+    CoralStation,
+    Processor,
+    Barge,
+}
+
+impl ElementKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Reef => "reef",
+            Self::CoralStation => "coral station",
+            Self::Processor => "processor",
+            Self::Barge => "barge",
+        }
+    }
+
+    /// Parts in the element frame, in inches
+    fn parts(self) -> Vec<FieldPart> {
+        let angled_branch = |name: &str, height: f32| {
+            let root = Vec2::new(
+                REEF_POLE_X,
+                height - BRANCH_LENGTH * BRANCH_ANGLE.to_radians().sin(),
+            );
+            FieldPart::pipe(name, root, branch_tip(height), BRANCH_RADIUS)
+        };
+        let l4_root = Vec2::new(
+            REEF_POLE_X,
+            L4_BEND.y - (REEF_POLE_X - L4_BEND.x) * BRANCH_ANGLE.to_radians().tan(),
+        );
+        let chute = Vec2::from_angle(STATION_CHUTE_ANGLE.to_radians());
+
+        match self {
+            Self::Reef => vec![
+                FieldPart::cuboid("reef trough", Vec2::ZERO, Vec2::from(REEF_BASE)),
+                FieldPart::pipe(
+                    "reef pole",
+                    Vec2::new(REEF_POLE_X, REEF_BASE[1]),
+                    l4_root,
+                    BRANCH_RADIUS,
+                ),
+                angled_branch("reef L2 branch", L2_HEIGHT),
+                angled_branch("reef L3 branch", L3_HEIGHT),
+                FieldPart::pipe("reef L4 branch", l4_root, L4_BEND, BRANCH_RADIUS),
+                FieldPart::pipe(
+                    "reef L4 tip",
+                    L4_BEND,
+                    Vec2::new(L4_BEND.x, L4_HEIGHT),
+                    BRANCH_RADIUS,
+                ),
+            ],
+            Self::CoralStation => vec![
+                FieldPart::cuboid(
+                    "coral station wall",
+                    Vec2::ZERO,
+                    Vec2::new(WALL_THICKNESS, STATION_MOUTH_HEIGHT),
+                ),
+                FieldPart::pipe(
+                    "coral station chute",
+                    Vec2::new(0.0, STATION_MOUTH_HEIGHT),
+                    Vec2::new(0.0, STATION_MOUTH_HEIGHT) + chute * STATION_CHUTE_LENGTH,
+                    STATION_CHUTE_RADIUS,
+                ),
+            ],
+            Self::Processor => vec![
+                FieldPart::cuboid(
+                    "processor wall",
+                    Vec2::ZERO,
+                    Vec2::new(WALL_THICKNESS, PROCESSOR_OPENING[0]),
+                ),
+                FieldPart::cuboid(
+                    "processor wall",
+                    Vec2::new(0.0, PROCESSOR_OPENING[1]),
+                    Vec2::new(WALL_THICKNESS, PROCESSOR_HEIGHT),
+                ),
+            ],
+            Self::Barge => vec![FieldPart::cuboid(
+                "barge",
+                Vec2::ZERO,
+                Vec2::from(BARGE_SIZE),
+            )],
+        }
+    }
+}
+
+/// End of an angled reef branch, which rises towards the robot from the pole
+fn branch_tip(height: f32) -> Vec2 {
+    Vec2::new(
+        REEF_POLE_X - BRANCH_LENGTH * BRANCH_ANGLE.to_radians().cos(),
+        height,
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Side {
+    /// Where the arm points at angle 0
+    Front,
+    Back,
+}
+
+/// Scoring location a preset should reach
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ScoringTarget {
+    L1,
+    L2,
+    L3,
+    L4,
+    CoralStation,
+    Processor,
+    Barge,
+}
+
+impl ScoringTarget {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::L1 => "L1 trough",
+            Self::L2 => "L2 branch",
+            Self::L3 => "L3 branch",
+            Self::L4 => "L4 branch",
+            Self::CoralStation => "coral station chute",
+            Self::Processor => "processor opening",
+            Self::Barge => "barge net",
+        }
+    }
+
+    pub fn element(self) -> ElementKind {
+        match self {
+            Self::L1 | Self::L2 | Self::L3 | Self::L4 => ElementKind::Reef,
+            Self::CoralStation => ElementKind::CoralStation,
+            Self::Processor => ElementKind::Processor,
+            Self::Barge => ElementKind::Barge,
+        }
+    }
+
+    /// Algae goes to the processor and barge, coral everywhere else
+    pub fn uses_algae(self) -> bool {
+        matches!(self, Self::Processor | Self::Barge)
+    }
+
+    /// Where the held piece's center belongs and how far off it may be, in
+    /// inches in the element frame
+    fn location(self) -> (Vec2, f32) {
+        let down_branch = Vec2::from_angle(-BRANCH_ANGLE.to_radians());
+        let on_branch = |height: f32| branch_tip(height) + down_branch * CORAL_ON_BRANCH;
+        match self {
+            Self::L1 => (CORAL_IN_TROUGH, OPENING_TOLERANCE),
+            Self::L2 => (on_branch(L2_HEIGHT), BRANCH_TOLERANCE),
+            Self::L3 => (on_branch(L3_HEIGHT), BRANCH_TOLERANCE),
+            Self::L4 => (
+                Vec2::new(L4_BEND.x, L4_HEIGHT - CORAL_ON_BRANCH),
+                BRANCH_TOLERANCE,
+            ),
+            Self::CoralStation => (Vec2::new(-4.0, STATION_MOUTH_HEIGHT), OPENING_TOLERANCE),
+            Self::Processor => (
+                Vec2::new(
+                    WALL_THICKNESS,
+                    (PROCESSOR_OPENING[0] + PROCESSOR_OPENING[1]) / 2.0,
+                ),
+                OPENING_TOLERANCE,
+            ),
+            Self::Barge => (
+                Vec2::new(BARGE_SIZE[0] / 2.0, BARGE_SIZE[1] + 10.0),
+                BARGE_TOLERANCE,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Placement {
+    pub kind: ElementKind,
+    /// Inches beyond the bumpers
+    pub distance: f32,
+    pub side: Side,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TargetDescription {
+    pub preset: String,
+    pub target: ScoringTarget,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FieldDescription {
+    pub elements: Vec<Placement>,
+    #[serde(default)]
+    pub show: Vec<ElementKind>,
+    #[serde(default)]
+    pub targets: Vec<TargetDescription>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FieldShape {
+    Cuboid {
+        center: Vec2,
+        half_extents: Vec2,
+    },
+    /// Round pipe or plate edge between two points
+    Pipe {
+        from: Vec2,
+        to: Vec2,
+        radius: f32,
+    },
+}
+
+/// One rigid piece of a field element
+#[derive(Debug, Clone)]
+pub struct FieldPart {
+    pub name: String,
+    pub shape: FieldShape,
+}
+
+impl FieldPart {
+    fn cuboid(name: &str, min: Vec2, max: Vec2) -> Self {
+        Self {
+            name: name.to_string(),
+            shape: FieldShape::Cuboid {
+                center: (min + max) / 2.0,
+                half_extents: (max - min) / 2.0,
+            },
+        }
+    }
+
+    fn pipe(name: &str, from: Vec2, to: Vec2, radius: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            shape: FieldShape::Pipe { from, to, radius },
+        }
+    }
+
+    /// Applies `place` to every point, which must be a translation plus an
+    /// optional mirror, and scales by `scale`
+    fn placed(&self, place: impl Fn(Vec2) -> Vec2, scale: f32) -> Self {
+        let shape = match self.shape {
+            FieldShape::Cuboid {
+                center,
+                half_extents,
+            } => FieldShape::Cuboid {
+                center: place(center),
+                half_extents: half_extents * scale,
+            },
+            FieldShape::Pipe { from, to, radius } => FieldShape::Pipe {
+                from: place(from),
+                to: place(to),
+                radius: radius * scale,
+            },
+        };
+        Self {
+            name: self.name.clone(),
+            shape,
+        }
+    }
+
+    /// Collider centered on `position()`
+    pub fn collider(&self) -> Collider {
+        match self.shape {
+            FieldShape::Cuboid { half_extents, .. } => {
+                Collider::cuboid(half_extents.x, half_extents.y)
+            }
+            FieldShape::Pipe { from, to, radius } => {
+                let center = (from + to) / 2.0;
+                Collider::capsule(from - center, to - center, radius)
+            }
+        }
+    }
+
+    pub fn position(&self) -> Vec2 {
+        match self.shape {
+            FieldShape::Cuboid { center, .. } => center,
+            FieldShape::Pipe { from, to, .. } => (from + to) / 2.0,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        let position = self.position();
+        Transform::from_xyz(position.x, position.y, 0.0)
+    }
+}
+
+/// Field geometry around the robot in sim units, in the same world frame as
+/// `RobotConfig`. The carpet and the robot's frame come from the robot's
+/// chassis.
+#[derive(Resource)]
+pub struct FieldConfig {
+    /// Carpet height
+    pub floor: f32,
+    /// Frame perimeter, measured from the elevator centerline
+    pub frame_front: f32,
+    pub frame_back: f32,
+    pub bumper: f32,
+    /// Distances converted to sim units
+    pub placements: Vec<Placement>,
+    pub show: Vec<ElementKind>,
+    pub targets: Vec<TargetDescription>,
+}
+
+impl FieldConfig {
+    /// Loads the field from disk, falling back to the copy built into the
+    /// binary if the file is missing or invalid. `None` if the robot has no
+    /// chassis to stand the field around.
+    pub fn load_or_default(path: &str, robot: &RobotConfig) -> Option<Self> {
+        match Self::load(path, robot) {
+            Ok(field) => {
+                println!("Loaded field from {}", path);
+                Some(field)
+            }
+            Err(e) => {
+                println!("Failed to load field from {}: {}", path, e);
+                match Self::parse(BUILTIN_FIELD, robot) {
+                    Ok(field) => Some(field),
+                    Err(e) => {
+                        println!("Field disabled: {}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn load(path: &str, robot: &RobotConfig) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&contents, robot)
+    }
+
+    pub fn parse(contents: &str, robot: &RobotConfig) -> Result<Self, String> {
+        let description: FieldDescription = ron::from_str(contents).map_err(|e| e.to_string())?;
+        Self::from_description(&description, robot)
+    }
+
+    pub fn from_description(
+        description: &FieldDescription,
+        robot: &RobotConfig,
+    ) -> Result<Self, String> {
+        let chassis = robot
+            .chassis
+            .as_ref()
+            .ok_or("the robot description has no chassis to place the field around")?;
+        for (i, placement) in description.elements.iter().enumerate() {
+            if description.elements[..i]
+                .iter()
+                .any(|other| other.kind == placement.kind)
+            {
+                return Err(format!("{} is placed twice", placement.kind.name()));
+            }
+        }
+        let placed = |kind: ElementKind| {
+            description
+                .elements
+                .iter()
+                .any(|placement| placement.kind == kind)
+        };
+        for &kind in &description.show {
+            if !placed(kind) {
+                return Err(format!("{} is shown but not placed", kind.name()));
+            }
+        }
+        for target in &description.targets {
+            if !placed(target.target.element()) {
+                return Err(format!(
+                    "{} targets the {} but it is not placed",
+                    target.preset,
+                    target.target.element().name()
+                ));
+            }
+        }
+
+        Ok(Self {
+            floor: chassis.floor,
+            frame_front: chassis.frame_front,
+            frame_back: chassis.frame_back,
+            bumper: chassis.bumper_thickness,
+            placements: description
+                .elements
+                .iter()
+                .map(|placement| Placement {
+                    distance: placement.distance * SIM_UNITS_PER_INCH,
+                    ..*placement
+                })
+                .collect(),
+            show: description.show.clone(),
+            targets: description.targets.clone(),
+        })
+    }
+
+    /// Collision groups for field parts. They are obstacles like the
+    /// elevator, so the intake and game pieces hit them.
+    pub fn groups() -> CollisionGroups {
+        CollisionGroups::new(ELEVATOR, INTAKE | GAME_PIECE)
+    }
+
+    /// Maps a point in an element frame (inches) into the world
+    fn element_to_world(&self, placement: &Placement) -> impl Fn(Vec2) -> Vec2 {
+        let (face, direction) = match placement.side {
+            Side::Front => (self.frame_front, 1.0),
+            Side::Back => (self.frame_back, -1.0),
+        };
+        let start = face + self.bumper + placement.distance;
+        let floor = self.floor;
+        move |local: Vec2| {
+            let local = local * SIM_UNITS_PER_INCH;
+            Vec2::new(direction * (start + local.x), floor + local.y)
+        }
+    }
+
+    fn placement(&self, kind: ElementKind) -> Option<&Placement> {
+        self.placements
+            .iter()
+            .find(|placement| placement.kind == kind)
+    }
+
+    /// World geometry of a placed element, `None` if it isn't placed
+    pub fn element(&self, kind: ElementKind) -> Option<Vec<FieldPart>> {
+        let place = self.element_to_world(self.placement(kind)?);
+        Some(
+            kind.parts()
+                .iter()
+                .map(|part| part.placed(&place, SIM_UNITS_PER_INCH))
+                .collect(),
+        )
+    }
+
+    /// Elements the main sim spawns. The floor is part of the robot's
+    /// chassis.
+    pub fn shown_parts(&self) -> Vec<FieldPart> {
+        self.show
+            .iter()
+            .flat_map(|&kind| self.element(kind).unwrap_or_default())
+            .collect()
+    }
+
+    /// World point a target's held piece should reach and the allowed miss,
+    /// both in sim units
+    pub fn target_location(&self, target: ScoringTarget) -> Option<(Vec2, f32)> {
+        let place = self.element_to_world(self.placement(target.element())?);
+        let (point, tolerance) = target.location();
+        Some((place(point), tolerance * SIM_UNITS_PER_INCH))
+    }
+}
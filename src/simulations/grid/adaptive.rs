@@ -0,0 +1,229 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use bevy::prelude::*;
+use rayon::prelude::*;
+
+use super::components::*;
+use super::setup_graphics;
+use crate::simulations::grid_file::QuadNode;
+use crate::simulations::robot::RobotConfig;
+
+/// Tip workspace sweep that splits cells along the collision boundary. Each
+/// cell is sampled at its center and split into quarters while the intake's
+/// clearance there is less than half the cell's diagonal. Clearance changes no
+/// faster than the intake moves, so a cell that isn't split lies wholly on one
+/// side of the boundary. Cells still on the boundary at the minimum size count
+/// as colliding.
+pub fn run(robot: RobotConfig, grid_state: AdaptiveGridState, headless: bool) -> App {
+    let mut app = App::new();
+    if headless {
+        app.add_plugins(MinimalPlugins)
+            .add_systems(Update, exit_when_completed);
+    } else {
+        app.add_plugins(DefaultPlugins).add_systems(
+            Startup,
+            (setup_graphics, draw_adaptive_grid).after(sweep_adaptive_grid),
+        );
+    }
+    app.insert_resource(grid_state)
+        .insert_resource(robot)
+        .add_systems(Startup, sweep_adaptive_grid);
+    app
+}
+
+fn sweep_adaptive_grid(mut grid_state: ResMut<AdaptiveGridState>, robot: Res<RobotConfig>) {
+    let world = IntakeWorld::new(&robot);
+    let clearance_at = |tip: Vec2| world.clearance(tip);
+
+    // Rows are refined in parallel, each cell with its clearance and rules
+    // flag sampled at the lower left corner like the regular grid
+    let state = &*grid_state;
+    let rows: Vec<Vec<_>> = (0..state.cells.len())
+        .into_par_iter()
+        .map(|y_idx| {
+            (0..state.cells[y_idx].len())
+                .map(|x_idx| {
+                    let corner = state.corner(x_idx, y_idx);
+                    let center = corner + Vec2::splat(state.step_size / 2.0);
+                    (
+                        refine(center, state.step_size, state.min_cell_size, &clearance_at),
+                        world.clearance(corner),
+                        world.breaks_rules(&robot, corner),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    for (y_idx, row) in rows.into_iter().enumerate() {
+        for (x_idx, (cell, clearance, breaks_rules)) in row.into_iter().enumerate() {
+            grid_state.cells[y_idx][x_idx] = cell;
+            grid_state.clearance_grid[y_idx][x_idx] = clearance;
+            if let Some(rules_grid) = &mut grid_state.rules_grid {
+                rules_grid[y_idx][x_idx] = breaks_rules;
+            }
+        }
+    }
+    grid_state.completed = true;
+    grid_state.finish();
+}
+
+/// Cell of `size` around `center`, split while the collision boundary may
+/// pass through it
+fn refine(
+    center: Vec2,
+    size: f32,
+    min_size: f32,
+    clearance_at: &(impl Fn(Vec2) -> f32 + Sync),
+) -> QuadNode {
+    let clearance = clearance_at(center);
+    if clearance.abs() >= size * FRAC_1_SQRT_2 {
+        return QuadNode::Leaf(clearance < 0.0);
+    }
+    if size / 2.0 < min_size {
+        return QuadNode::Leaf(true);
+    }
+    let quarter = size / 4.0;
+    QuadNode::Split(Box::new(
+        [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y)| {
+            refine(
+                center + Vec2::new(x, y) * quarter,
+                size / 2.0,
+                min_size,
+                clearance_at,
+            )
+        }),
+    ))
+}
+
+/// Draws each leaf of the grid at its size, with the robot's fixed parts
+fn draw_adaptive_grid(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    grid_state: Res<AdaptiveGridState>,
+    robot: Res<RobotConfig>,
+) {
+    let fixed_material = materials.add(Color::linear_rgb(0.5, 0.5, 0.5));
+    for part in robot.fixed_parts() {
+        commands.spawn((
+            Mesh2d(meshes.add(part.shape.mesh())),
+            MeshMaterial2d(fixed_material.clone()),
+            part.pose.transform(),
+        ));
+    }
+
+    let cell = meshes.add(Rectangle::new(1.0, 1.0));
+    let collision_material = materials.add(Color::linear_rgb(0.8, 0.2, 0.2));
+    let safe_material = materials.add(Color::linear_rgb(0.2, 0.8, 0.2));
+    let step = grid_state.step_size;
+
+    for (y_idx, row) in grid_state.cells.iter().enumerate() {
+        for (x_idx, node) in row.iter().enumerate() {
+            let corner = grid_state.corner(x_idx, y_idx);
+            for (u, v, size, collides) in node.leaves() {
+                let center = corner + (Vec2::new(u, v) + size / 2.0) * step;
+                // Leave a gap between leaves so the splits show
+                let scale = size * step * 0.9;
+                commands.spawn((
+                    Mesh2d(cell.clone()),
+                    MeshMaterial2d(if collides {
+                        collision_material.clone()
+                    } else {
+                        safe_material.clone()
+                    }),
+                    Transform::from_xyz(center.x, center.y, -1.0)
+                        .with_scale(Vec3::new(scale, scale, 1.0)),
+                    GridMarker,
+                ));
+            }
+        }
+    }
+}
+
+fn exit_when_completed(grid_state: Res<AdaptiveGridState>, mut exit: EventWriter<AppExit>) {
+    if grid_state.completed {
+        exit.send(AppExit::Success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 10.0;
+    const MIN_SIZE: f32 = 1.0;
+
+    /// Signed distance to a circle of obstacle around the origin
+    fn clearance_at(point: Vec2) -> f32 {
+        point.length() - RADIUS
+    }
+
+    /// Calls `check` with every node in the tree, its center and its size
+    fn walk(
+        node: &QuadNode,
+        center: Vec2,
+        size: f32,
+        check: &mut impl FnMut(&QuadNode, Vec2, f32),
+    ) {
+        check(node, center, size);
+        if let QuadNode::Split(children) = node {
+            let quarter = size / 4.0;
+            let offsets = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)];
+            for (child, (x, y)) in children.iter().zip(offsets) {
+                walk(child, center + Vec2::new(x, y) * quarter, size / 2.0, check);
+            }
+        }
+    }
+
+    #[test]
+    fn splits_only_along_the_boundary() {
+        let (center, size) = (Vec2::new(4.0, 4.0), 32.0);
+        let root = refine(center, size, MIN_SIZE, &clearance_at);
+
+        let (mut splits, mut boundary_leaves) = (0, 0);
+        walk(&root, center, size, &mut |node, center, size| {
+            let clearance = clearance_at(center);
+            let on_boundary = clearance.abs() < size * FRAC_1_SQRT_2;
+            match *node {
+                QuadNode::Split(_) => {
+                    assert!(on_boundary, "split off the boundary at {}", center);
+                    splits += 1;
+                }
+                QuadNode::Leaf(collides) if on_boundary => {
+                    // Only cells that can't split any further stay on the
+                    // boundary, and they count as colliding
+                    assert_eq!(size, MIN_SIZE, "boundary leaf at {}", center);
+                    assert!(collides);
+                    boundary_leaves += 1;
+                }
+                QuadNode::Leaf(collides) => {
+                    // Wholly on one side, corners included
+                    for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                        let corner = center + Vec2::new(x, y) * size / 2.0;
+                        assert_eq!(clearance_at(corner) < 0.0, collides, "leaf at {}", center);
+                    }
+                }
+            }
+        });
+        assert!(splits > 0);
+        assert!(boundary_leaves > 0);
+    }
+
+    #[test]
+    fn leaves_cells_off_the_boundary_whole() {
+        assert_eq!(
+            refine(Vec2::new(100.0, 0.0), 32.0, MIN_SIZE, &clearance_at),
+            QuadNode::Leaf(false)
+        );
+        assert_eq!(
+            refine(Vec2::ZERO, 4.0, MIN_SIZE, &clearance_at),
+            QuadNode::Leaf(true)
+        );
+        // A boundary cell already at the minimum size isn't split
+        assert_eq!(
+            refine(Vec2::new(RADIUS, 0.0), MIN_SIZE, MIN_SIZE, &clearance_at),
+            QuadNode::Leaf(true)
+        );
+    }
+}
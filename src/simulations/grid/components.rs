@@ -1,9 +1,16 @@
 use bevy::prelude::*;
-use std::fs::File;
-use std::io::Write;
+use bevy_rapier2d::parry::query;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::math::{Isometry, Vector};
 
-const ARM_LENGTH: f32 = 39.37;
-const GRID_RESOLUTION: f32 = 2.0; // Step size for grid
+use crate::simulations::grid_file::{
+    GridFile, GridKind, Layer, LayerKind, QuadNode, Unit, MAX_TREE_DEPTH,
+};
+use crate::simulations::robot::{groups_interact, RobotConfig, SIM_UNITS_PER_INCH};
+
+pub const GRID_RESOLUTION: f32 = 2.0; // Default step size for grid
+pub const JOINT_ANGLE_RESOLUTION: f32 = 1.0; // Default arm angle step for joint grid (degrees)
+pub const JOINT_HEIGHT_RESOLUTION: f32 = 1.0; // Default elevator step for joint grid (units)
 
 #[derive(Component)]
 pub struct IntakeMarker;
@@ -22,15 +29,21 @@ pub struct GridState {
     pub max_y: f32,
     pub completed: bool,
     pub collision_grid: Vec<Vec<bool>>,
+    /// Signed distance from the intake to the nearest obstacle at each cell
+    pub clearance_grid: Vec<Vec<f32>>,
+    /// Cells where the intake breaks the extension or height limits, when
+    /// the robot has rules
+    pub rules_grid: Option<Vec<Vec<bool>>>,
+    /// Intake and obstacle colliders, built once for the whole sweep
+    pub world: IntakeWorld,
+    pub geometry_hash: u64,
+    /// Where the finished grid is saved
+    pub path: String,
 }
 
 impl GridState {
-    pub fn new() -> Self {
-        let min_x = -ARM_LENGTH - 5.0;
-        let max_x = ARM_LENGTH + 5.0;
-        let min_y = -13.65 - ARM_LENGTH;
-        let max_y = 31.75 + ARM_LENGTH;
-        let step_size = GRID_RESOLUTION;
+    pub fn new(robot: &RobotConfig, step_size: f32, path: &str) -> Self {
+        let [min_x, max_x, min_y, max_y] = workspace_bounds(robot);
 
         // Calculate grid dimensions
         let width = ((max_x - min_x) / step_size).ceil() as usize + 1;
@@ -46,9 +59,22 @@ impl GridState {
             max_y,
             completed: false,
             collision_grid: vec![vec![false; width]; height],
+            clearance_grid: vec![vec![f32::INFINITY; width]; height],
+            rules_grid: robot.rules.map(|_| vec![vec![false; width]; height]),
+            world: IntakeWorld::new(robot),
+            geometry_hash: robot.geometry_hash,
+            path: path.to_string(),
         }
     }
 
+    /// Arm tip position sampled for a grid cell
+    pub fn sample_at(&self, x_idx: usize, y_idx: usize) -> Vec2 {
+        Vec2::new(
+            self.min_x + x_idx as f32 * self.step_size,
+            self.min_y + y_idx as f32 * self.step_size,
+        )
+    }
+
     pub fn get_grid_indices(&self, x: f32, y: f32) -> Option<(usize, usize)> {
         let x_idx = ((x - self.min_x) / self.step_size).floor() as usize;
         let y_idx = ((y - self.min_y) / self.step_size).floor() as usize;
@@ -66,29 +92,474 @@ impl GridState {
         }
     }
 
-    pub fn save_to_file(&self) -> std::io::Result<()> {
-        let mut file = File::create("collision_grid.bin")?;
-
-        // Write grid dimensions
-        let width = self.collision_grid[0].len() as u32;
-        let height = self.collision_grid.len() as u32;
-        file.write_all(&width.to_le_bytes())?;
-        file.write_all(&height.to_le_bytes())?;
-
-        // Write grid boundaries
-        file.write_all(&self.min_x.to_le_bytes())?;
-        file.write_all(&self.max_x.to_le_bytes())?;
-        file.write_all(&self.min_y.to_le_bytes())?;
-        file.write_all(&self.max_y.to_le_bytes())?;
-        file.write_all(&self.step_size.to_le_bytes())?;
-
-        // Write collision data
-        for row in &self.collision_grid {
-            for &cell in row {
-                file.write_all(&[cell as u8])?;
+    /// Records the intake clearance at the current position
+    pub fn record_clearance(&mut self, clearance: f32) {
+        if let Some((x_idx, y_idx)) = self.get_grid_indices(self.current_x, self.current_y) {
+            self.clearance_grid[y_idx][x_idx] = clearance;
+        }
+    }
+
+    /// Marks the current position in the rules layer if the intake breaks
+    /// a limit there. Returns whether it does.
+    pub fn check_rules(&mut self, robot: &RobotConfig) -> bool {
+        let breaks = robot.rules.is_some_and(|rules| {
+            rules.intake_breaks(robot, self.intake_position(), self.intake_rotation())
+        });
+        let indices = self.get_grid_indices(self.current_x, self.current_y);
+        if let (true, Some(rules_grid), Some((x_idx, y_idx))) =
+            (breaks, self.rules_grid.as_mut(), indices)
+        {
+            rules_grid[y_idx][x_idx] = true;
+        }
+        breaks
+    }
+
+    /// Intake center for the current theoretical arm endpoint
+    pub fn intake_position(&self) -> Vec2 {
+        Vec2::new(self.current_x, self.current_y) + self.world.mount.0
+    }
+
+    pub fn intake_rotation(&self) -> f32 {
+        self.world.mount.1
+    }
+
+    /// Intake clearance for the current theoretical arm endpoint
+    pub fn intake_clearance(&self) -> f32 {
+        self.world
+            .clearance(Vec2::new(self.current_x, self.current_y))
+    }
+
+    /// Moves to the next grid position, marking the sweep completed after the last row
+    pub fn advance(&mut self) {
+        self.current_x += self.step_size;
+        if self.current_x > self.max_x {
+            self.current_x = self.min_x;
+            self.current_y += self.step_size;
+
+            if self.current_y > self.max_y {
+                self.completed = true;
             }
         }
+    }
+
+    /// Prints the grid summary and writes it to disk
+    pub fn finish(&self) {
+        println!(
+            "Grid check completed! Grid size: {}x{}",
+            self.collision_grid[0].len(),
+            self.collision_grid.len()
+        );
+        print_clearance_summary(&self.collision_grid, &self.clearance_grid);
+        if let Some(rules_grid) = &self.rules_grid {
+            print_rules_summary(rules_grid);
+        }
+
+        if let Err(e) = self.save_to_file() {
+            println!("Failed to save collision grid: {}", e);
+        } else {
+            println!("Collision grid saved to {}", self.path);
+        }
+    }
+
+    pub fn save_to_file(&self) -> std::io::Result<()> {
+        write_grid_file(
+            &self.path,
+            GridKind::Cartesian,
+            &self.collision_grid,
+            [self.min_x, self.max_x, self.min_y, self.max_y],
+            [self.step_size; 2],
+            self.geometry_hash,
+            extra_layers(&self.clearance_grid, self.rules_grid.as_ref()),
+        )
+    }
+}
+
+/// Collision map over the tip workspace whose cells are split into quarters
+/// along the collision boundary, down to a minimum size. Cell `(x_idx, y_idx)`
+/// covers the square from `min + idx * step_size` to the next sample.
+#[derive(Resource)]
+pub struct AdaptiveGridState {
+    pub step_size: f32,
+    /// Smallest cell a boundary cell is split down to
+    pub min_cell_size: f32,
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_y: f32,
+    pub max_y: f32,
+    pub completed: bool,
+    pub cells: Vec<Vec<QuadNode>>,
+    /// Signed distance from the intake to the nearest obstacle at each cell's
+    /// lower left corner, like the regular grid
+    pub clearance_grid: Vec<Vec<f32>>,
+    /// Cells whose lower left corner breaks the extension or height limits,
+    /// when the robot has rules
+    pub rules_grid: Option<Vec<Vec<bool>>>,
+    pub geometry_hash: u64,
+    /// Where the finished grid is saved
+    pub path: String,
+}
+
+impl AdaptiveGridState {
+    /// Fails if splitting a cell down to `min_cell_size` would nest deeper
+    /// than a grid file can hold
+    pub fn new(
+        robot: &RobotConfig,
+        step_size: f32,
+        min_cell_size: f32,
+        path: &str,
+    ) -> Result<Self, String> {
+        let smallest = step_size / (1u32 << MAX_TREE_DEPTH) as f32;
+        if min_cell_size < smallest {
+            return Err(format!(
+                "{} is below {} sim units, the smallest a {} unit cell can be split to",
+                min_cell_size, smallest, step_size
+            ));
+        }
+        let [min_x, max_x, min_y, max_y] = workspace_bounds(robot);
+        let width = ((max_x - min_x) / step_size).ceil() as usize + 1;
+        let height = ((max_y - min_y) / step_size).ceil() as usize + 1;
+
+        Ok(Self {
+            step_size,
+            min_cell_size,
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+            completed: false,
+            cells: vec![vec![QuadNode::Leaf(false); width]; height],
+            clearance_grid: vec![vec![f32::INFINITY; width]; height],
+            rules_grid: robot.rules.map(|_| vec![vec![false; width]; height]),
+            geometry_hash: robot.geometry_hash,
+            path: path.to_string(),
+        })
+    }
+
+    /// Lower left corner of a cell
+    pub fn corner(&self, x_idx: usize, y_idx: usize) -> Vec2 {
+        Vec2::new(
+            self.min_x + x_idx as f32 * self.step_size,
+            self.min_y + y_idx as f32 * self.step_size,
+        )
+    }
+
+    /// Whether each cell collides anywhere, for readers that ignore the
+    /// refinement
+    pub fn collision_grid(&self) -> Vec<Vec<bool>> {
+        self.cells
+            .iter()
+            .map(|row| row.iter().map(QuadNode::collides).collect())
+            .collect()
+    }
+
+    /// Prints the grid summary and writes it to disk
+    pub fn finish(&self) {
+        let leaves: usize = self
+            .cells
+            .iter()
+            .flatten()
+            .map(|cell| cell.leaves().len())
+            .sum();
+        let smallest = self
+            .cells
+            .iter()
+            .flatten()
+            .flat_map(QuadNode::leaves)
+            .map(|(_, _, size, _)| size * self.step_size)
+            .fold(self.step_size, f32::min);
+        println!(
+            "Adaptive grid completed! Grid size: {}x{}, {} cells down to {:.2} in",
+            self.cells[0].len(),
+            self.cells.len(),
+            leaves,
+            smallest / SIM_UNITS_PER_INCH
+        );
+        let collision_grid = self.collision_grid();
+        print_clearance_summary(&collision_grid, &self.clearance_grid);
+        if let Some(rules_grid) = &self.rules_grid {
+            print_rules_summary(rules_grid);
+        }
+
+        if let Err(e) = self.save_to_file(&collision_grid) {
+            println!("Failed to save collision grid: {}", e);
+        } else {
+            println!("Collision grid saved to {}", self.path);
+        }
+    }
+
+    fn save_to_file(&self, collision_grid: &[Vec<bool>]) -> std::io::Result<()> {
+        let mut layers = extra_layers(&self.clearance_grid, self.rules_grid.as_ref());
+        layers.push((LayerKind::Refinement, Layer::Trees(self.cells.clone())));
+        write_grid_file(
+            &self.path,
+            GridKind::Cartesian,
+            collision_grid,
+            [self.min_x, self.max_x, self.min_y, self.max_y],
+            [self.step_size; 2],
+            self.geometry_hash,
+            layers,
+        )
+    }
+}
+
+/// Collision map over joint space. Columns are arm angles in degrees and rows
+/// are elevator heights (prismatic joint position).
+#[derive(Resource)]
+pub struct JointGridState {
+    pub min_angle: f32,
+    pub max_angle: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Degrees between columns
+    pub angle_step: f32,
+    /// Sim units between rows
+    pub height_step: f32,
+    pub completed: bool,
+    pub collision_grid: Vec<Vec<bool>>,
+    /// Signed distance between the closest pair of links, or link and
+    /// chassis part, at each pose
+    pub clearance_grid: Vec<Vec<f32>>,
+    /// Poses that break the extension or height limits, when the robot has
+    /// rules
+    pub rules_grid: Option<Vec<Vec<bool>>>,
+    pub geometry_hash: u64,
+    /// Where the finished grid is saved
+    pub path: String,
+}
+
+impl JointGridState {
+    pub fn new(robot: &RobotConfig, angle_step: f32, height_step: f32, path: &str) -> Self {
+        let min_angle = -180.0;
+        let max_angle = 180.0;
+        let [min_height, max_height] = robot.elevator_limits();
+
+        let width = ((max_angle - min_angle) / angle_step).ceil() as usize + 1;
+        let height = ((max_height - min_height) / height_step).ceil() as usize + 1;
+
+        Self {
+            min_angle,
+            max_angle,
+            min_height,
+            max_height,
+            angle_step,
+            height_step,
+            completed: false,
+            collision_grid: vec![vec![false; width]; height],
+            clearance_grid: vec![vec![f32::INFINITY; width]; height],
+            rules_grid: robot.rules.map(|_| vec![vec![false; width]; height]),
+            geometry_hash: robot.geometry_hash,
+            path: path.to_string(),
+        }
+    }
+
+    /// Joint values (height, angle in degrees) sampled for a grid cell
+    pub fn pose_at(&self, x_idx: usize, y_idx: usize) -> (f32, f32) {
+        (
+            (self.min_height + y_idx as f32 * self.height_step).min(self.max_height),
+            (self.min_angle + x_idx as f32 * self.angle_step).min(self.max_angle),
+        )
+    }
+
+    pub fn save_to_file(&self) -> std::io::Result<()> {
+        write_grid_file(
+            &self.path,
+            GridKind::JointSpace,
+            &self.collision_grid,
+            [
+                self.min_angle,
+                self.max_angle,
+                self.min_height,
+                self.max_height,
+            ],
+            [self.angle_step, self.height_step],
+            self.geometry_hash,
+            extra_layers(&self.clearance_grid, self.rules_grid.as_ref()),
+        )
+    }
+}
+
+/// Intake and the parts it can hit, shared read-only by sweep workers
+pub struct IntakeWorld {
+    pub intake: Collider,
+    pub obstacles: Vec<(Collider, Isometry<f32>)>,
+    /// Offset from the arm tip to the intake center, and the intake rotation
+    pub mount: (Vec2, f32),
+}
+
+impl IntakeWorld {
+    pub fn new(robot: &RobotConfig) -> Self {
+        Self {
+            intake: robot.intake().shape.collider(),
+            obstacles: intake_obstacles(robot),
+            mount: robot.intake_mount(),
+        }
+    }
+
+    /// Intake pose with the arm tip at `tip`
+    pub fn isometry(&self, tip: Vec2) -> Isometry<f32> {
+        let position = tip + self.mount.0;
+        Isometry::new(Vector::new(position.x, position.y), self.mount.1)
+    }
+
+    pub fn collides(&self, tip: Vec2) -> bool {
+        let intake_pose = self.isometry(tip);
+        self.obstacles.iter().any(|(shape, pose)| {
+            query::intersection_test(&intake_pose, &*self.intake.raw, pose, &*shape.raw)
+                .unwrap_or(false)
+        })
+    }
+
+    pub fn clearance(&self, tip: Vec2) -> f32 {
+        intake_clearance(&self.intake, &self.isometry(tip), &self.obstacles)
+    }
+
+    /// Whether the intake breaks the extension or height limits
+    pub fn breaks_rules(&self, robot: &RobotConfig, tip: Vec2) -> bool {
+        robot
+            .rules
+            .is_some_and(|rules| rules.intake_breaks(robot, tip + self.mount.0, self.mount.1))
+    }
+}
+
+/// Area the arm tip can reach as `[min_x, max_x, min_y, max_y]`, with some
+/// room to the sides
+fn workspace_bounds(robot: &RobotConfig) -> [f32; 4] {
+    let arm_length = robot.arm_length();
+    let [elevator_min, elevator_max] = robot.elevator_limits();
+    [
+        -arm_length - 5.0,
+        arm_length + 5.0,
+        elevator_min - arm_length,
+        elevator_max + arm_length,
+    ]
+}
+
+/// Chassis and elevator parts the intake can hit, posed in the world
+pub fn intake_obstacles(robot: &RobotConfig) -> Vec<(Collider, Isometry<f32>)> {
+    robot
+        .fixed_parts()
+        .into_iter()
+        .filter(|part| groups_interact(part.groups, robot.intake().groups))
+        .map(|part| (part.shape.collider(), part.pose.isometry()))
+        .collect()
+}
+
+/// Smallest signed distance from the intake to any of the obstacles
+pub fn intake_clearance(
+    intake: &Collider,
+    pose: &Isometry<f32>,
+    obstacles: &[(Collider, Isometry<f32>)],
+) -> f32 {
+    obstacles
+        .iter()
+        .map(|(shape, obstacle)| signed_distance(pose, intake, obstacle, shape))
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Distance between two shapes, negative by the penetration depth when they
+/// overlap
+pub fn signed_distance(
+    pose1: &Isometry<f32>,
+    shape1: &Collider,
+    pose2: &Isometry<f32>,
+    shape2: &Collider,
+) -> f32 {
+    match query::contact(pose1, &*shape1.raw, pose2, &*shape2.raw, 0.0) {
+        Ok(Some(contact)) => contact.dist,
+        _ => query::distance(pose1, &*shape1.raw, pose2, &*shape2.raw).unwrap_or(f32::INFINITY),
+    }
+}
+
+/// Prints the smallest clearance of any cell that doesn't collide
+pub fn print_clearance_summary(collision_grid: &[Vec<bool>], clearance_grid: &[Vec<f32>]) {
+    let closest = collision_grid
+        .iter()
+        .flatten()
+        .zip(clearance_grid.iter().flatten())
+        .filter(|(&collides, _)| !collides)
+        .map(|(_, &clearance)| clearance)
+        .min_by(f32::total_cmp);
+    if let Some(closest) = closest.filter(|closest| closest.is_finite()) {
+        println!(
+            "  closest safe cell clears by {:.2} in",
+            closest / SIM_UNITS_PER_INCH
+        );
+    }
+}
+
+pub fn print_rules_summary(rules_grid: &[Vec<bool>]) {
+    let count = rules_grid
+        .iter()
+        .flatten()
+        .filter(|&&breaks| breaks)
+        .count();
+    println!("  {} cells break the extension or height limits", count);
+}
+
+fn write_grid_file(
+    path: &str,
+    kind: GridKind,
+    grid: &[Vec<bool>],
+    bounds: [f32; 4],
+    steps: [f32; 2],
+    geometry_hash: u64,
+    layers: Vec<(LayerKind, Layer)>,
+) -> std::io::Result<()> {
+    let x_unit = match kind {
+        GridKind::Cartesian => Unit::SimUnits,
+        GridKind::JointSpace => Unit::Degrees,
+    };
+    let file = GridFile {
+        version: crate::simulations::grid_file::FORMAT_VERSION,
+        kind,
+        x_unit,
+        y_unit: Unit::SimUnits,
+        min_x: bounds[0],
+        max_x: bounds[1],
+        min_y: bounds[2],
+        max_y: bounds[3],
+        step_x: steps[0],
+        step_y: steps[1],
+        geometry_hash,
+        source: format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        cells: grid.to_vec(),
+        layers,
+    };
+    file.save(path)
+}
+
+fn extra_layers(
+    clearance_grid: &[Vec<f32>],
+    rules_grid: Option<&Vec<Vec<bool>>>,
+) -> Vec<(LayerKind, Layer)> {
+    std::iter::once((LayerKind::Clearance, Layer::Values(clearance_grid.to_vec())))
+        .chain(rules_grid.map(|cells| (LayerKind::Rules, Layer::Flags(cells.clone()))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_a_min_cell_size_deeper_than_a_file_holds() {
+        let robot = RobotConfig::load(concat!(env!("CARGO_MANIFEST_DIR"), "/robot.ron")).unwrap();
+        let smallest = 2.0 / (1u32 << MAX_TREE_DEPTH) as f32;
+        assert!(AdaptiveGridState::new(&robot, 2.0, smallest, "unused.bin").is_ok());
+        assert!(AdaptiveGridState::new(&robot, 2.0, smallest / 2.0, "unused.bin").is_err());
+    }
+
+    #[test]
+    fn joint_grid_steps_angle_and_height_separately() {
+        let robot = RobotConfig::load(concat!(env!("CARGO_MANIFEST_DIR"), "/robot.ron")).unwrap();
+        let [min_height, max_height] = robot.elevator_limits();
+        let grid_state = JointGridState::new(&robot, 2.0, 0.5, "unused.bin");
 
-        Ok(())
+        assert_eq!(grid_state.collision_grid[0].len(), 181);
+        assert_eq!(
+            grid_state.collision_grid.len(),
+            ((max_height - min_height) / 0.5).ceil() as usize + 1
+        );
+        assert_eq!(grid_state.pose_at(3, 4), (min_height + 2.0, -174.0));
     }
 }
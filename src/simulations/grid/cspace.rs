@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy_rapier2d::parry::query;
+use bevy_rapier2d::prelude::*;
+
+use super::components::*;
+use crate::simulations::robot::RobotConfig;
+
+/// Configuration-space sweep over (elevator height, arm angle). Every pose is
+/// placed with forward kinematics and each pair from `swept_pairs` is tested:
+/// links whose collision groups interact, and every moving link against the
+/// elevator and the chassis parts.
+pub fn run(robot: RobotConfig, grid_state: JointGridState, headless: bool) -> App {
+    let mut app = App::new();
+    if headless {
+        app.add_plugins(MinimalPlugins)
+            .add_systems(Update, exit_when_completed);
+    } else {
+        app.add_plugins(DefaultPlugins)
+            .add_systems(Startup, draw_joint_grid.after(sweep_joint_grid));
+    }
+    app.insert_resource(grid_state)
+        .insert_resource(robot)
+        .add_systems(Startup, sweep_joint_grid);
+    app
+}
+
+fn sweep_joint_grid(mut grid_state: ResMut<JointGridState>, robot: Res<RobotConfig>) {
+    // Bodies are the links followed by the chassis parts
+    let chassis = robot.chassis_parts();
+    let shapes: Vec<Collider> = robot
+        .links
+        .iter()
+        .map(|link| link.shape)
+        .chain(chassis.iter().map(|part| part.shape))
+        .map(|shape| shape.collider())
+        .collect();
+    let pairs = robot.swept_pairs();
+    let mut colliding = vec![0usize; shapes.len()];
+
+    for y_idx in 0..grid_state.collision_grid.len() {
+        for x_idx in 0..grid_state.collision_grid[y_idx].len() {
+            let (joint, angle) = grid_state.pose_at(x_idx, y_idx);
+            // Rows are elevator joint positions, the kinematics take the
+            // height above the lowest one
+            let height = joint - robot.elevator_offset();
+            let poses = robot.forward_kinematics(height, angle.to_radians());
+            let pose = |body: usize| {
+                poses
+                    .get(body)
+                    .unwrap_or_else(|| &chassis[body - poses.len()].pose)
+                    .isometry()
+            };
+
+            let mut has_collision = false;
+            let mut clearance = f32::INFINITY;
+            for &(a, b) in &pairs {
+                let (pose_a, pose_b) = (pose(a), pose(b));
+                clearance =
+                    clearance.min(signed_distance(&pose_a, &shapes[a], &pose_b, &shapes[b]));
+                let hit =
+                    query::intersection_test(&pose_a, &*shapes[a].raw, &pose_b, &*shapes[b].raw)
+                        .unwrap_or(false);
+                if hit {
+                    colliding[a] += 1;
+                    colliding[b] += 1;
+                    has_collision = true;
+                }
+            }
+
+            grid_state.collision_grid[y_idx][x_idx] = has_collision;
+            grid_state.clearance_grid[y_idx][x_idx] = clearance;
+            if let (Some(rules), Some(rules_grid)) = (robot.rules, &mut grid_state.rules_grid) {
+                rules_grid[y_idx][x_idx] = !rules
+                    .check_pose(&robot, height, angle.to_radians())
+                    .is_empty();
+            }
+        }
+    }
+
+    grid_state.completed = true;
+    println!(
+        "Joint grid check completed! Grid size: {}x{}",
+        grid_state.collision_grid[0].len(),
+        grid_state.collision_grid.len()
+    );
+    let (colliding_links, colliding_parts) = colliding.split_at(robot.links.len());
+    for (link, &count) in robot.links.iter().zip(colliding_links) {
+        if count > 0 {
+            println!("  {} collides in {} cells", link.name, count);
+        }
+    }
+    for (part, &count) in chassis.iter().zip(colliding_parts) {
+        if count > 0 {
+            println!("  {} is hit in {} cells", part.name, count);
+        }
+    }
+    print_clearance_summary(&grid_state.collision_grid, &grid_state.clearance_grid);
+    if let Some(rules_grid) = &grid_state.rules_grid {
+        print_rules_summary(rules_grid);
+    }
+
+    if let Err(e) = grid_state.save_to_file() {
+        println!("Failed to save joint grid: {}", e);
+    } else {
+        println!("Joint grid saved to {}", grid_state.path);
+    }
+}
+
+/// Draws the joint grid with arm angle (degrees) on x and elevator height on y
+fn draw_joint_grid(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    grid_state: Res<JointGridState>,
+) {
+    commands.spawn((
+        Camera2d,
+        OrthographicProjection {
+            scale: 0.5,
+            ..OrthographicProjection::default_2d()
+        },
+    ));
+
+    let cell = meshes.add(Rectangle::new(
+        grid_state.angle_step,
+        grid_state.height_step,
+    ));
+    let collision_material = materials.add(Color::linear_rgb(0.8, 0.2, 0.2));
+    let safe_material = materials.add(Color::linear_rgb(0.2, 0.8, 0.2));
+
+    for (y_idx, row) in grid_state.collision_grid.iter().enumerate() {
+        for (x_idx, &cell_collides) in row.iter().enumerate() {
+            let (height, angle) = grid_state.pose_at(x_idx, y_idx);
+            commands.spawn((
+                Mesh2d(cell.clone()),
+                MeshMaterial2d(if cell_collides {
+                    collision_material.clone()
+                } else {
+                    safe_material.clone()
+                }),
+                Transform::from_xyz(angle, height, -1.0),
+                GridMarker,
+            ));
+        }
+    }
+}
+
+fn exit_when_completed(grid_state: Res<JointGridState>, mut exit: EventWriter<AppExit>) {
+    if grid_state.completed {
+        exit.send(AppExit::Success);
+    }
+}
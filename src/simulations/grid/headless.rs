@@ -0,0 +1,171 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use rayon::prelude::*;
+
+use super::components::*;
+use crate::simulations::robot::{RobotConfig, SIM_UNITS_PER_INCH};
+
+/// Cells per side of the tiles the sweep is split into
+const TILE_SIZE: usize = 32;
+
+/// Grid generation without a window or renderer. The whole sweep runs in a
+/// single startup pass directly against the collider shapes, with tiles of
+/// the grid spread across the CPU cores, then the app exits.
+pub fn run(robot: RobotConfig, grid_state: GridState) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(grid_state)
+        .insert_resource(robot)
+        .add_systems(Startup, sweep_grid)
+        .add_systems(Update, exit_when_completed);
+    app
+}
+
+fn sweep_grid(mut grid_state: ResMut<GridState>, robot: Res<RobotConfig>) {
+    sweep_tiles(&mut grid_state, &robot);
+    grid_state.finish();
+}
+
+/// Samples every cell, a tile at a time on rayon's thread pool, and merges
+/// the tiles back into the grids
+fn sweep_tiles(grid_state: &mut GridState, robot: &RobotConfig) {
+    let height = grid_state.collision_grid.len();
+    let width = grid_state.collision_grid[0].len();
+    let tiles: Vec<(usize, usize)> = (0..height)
+        .step_by(TILE_SIZE)
+        .flat_map(|y_idx| {
+            (0..width)
+                .step_by(TILE_SIZE)
+                .map(move |x_idx| (x_idx, y_idx))
+        })
+        .collect();
+
+    let state = &*grid_state;
+    let world = &state.world;
+    let samples: Vec<_> = tiles
+        .par_iter()
+        .map(|&(x_start, y_start)| {
+            let mut tile = Vec::with_capacity(TILE_SIZE * TILE_SIZE);
+            for y_idx in y_start..(y_start + TILE_SIZE).min(height) {
+                for x_idx in x_start..(x_start + TILE_SIZE).min(width) {
+                    let tip = state.sample_at(x_idx, y_idx);
+                    tile.push((
+                        x_idx,
+                        y_idx,
+                        world.collides(tip),
+                        world.clearance(tip),
+                        world.breaks_rules(robot, tip),
+                    ));
+                }
+            }
+            tile
+        })
+        .collect();
+
+    for (x_idx, y_idx, collides, clearance, breaks_rules) in samples.into_iter().flatten() {
+        grid_state.collision_grid[y_idx][x_idx] = collides;
+        grid_state.clearance_grid[y_idx][x_idx] = clearance;
+        if let Some(rules_grid) = &mut grid_state.rules_grid {
+            rules_grid[y_idx][x_idx] = breaks_rules;
+        }
+    }
+    grid_state.completed = true;
+}
+
+/// Times the sweep on one thread and on every core and checks that both
+/// produce the same grid. Nothing is saved. Returns whether they agree.
+pub fn benchmark(robot: &RobotConfig, resolution: f32) -> bool {
+    let sweep_on = |threads: usize| -> Result<(Duration, GridState), String> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let mut grid_state = GridState::new(robot, resolution, "");
+        let start = Instant::now();
+        pool.install(|| sweep_tiles(&mut grid_state, robot));
+        Ok((start.elapsed(), grid_state))
+    };
+
+    let threads = rayon::current_num_threads();
+    let (serial, parallel) = match (sweep_on(1), sweep_on(threads)) {
+        (Ok(serial), Ok(parallel)) => (serial, parallel),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("Failed to start the sweep threads: {}", e);
+            return false;
+        }
+    };
+
+    println!(
+        "Swept {}x{} cells at {:.2} in",
+        serial.1.collision_grid[0].len(),
+        serial.1.collision_grid.len(),
+        resolution / SIM_UNITS_PER_INCH
+    );
+    println!("  1 thread: {:.2} s", serial.0.as_secs_f32());
+    println!(
+        "  {} thread{}: {:.2} s, {:.1}x faster",
+        threads,
+        if threads == 1 { "" } else { "s" },
+        parallel.0.as_secs_f32(),
+        serial.0.as_secs_f32() / parallel.0.as_secs_f32()
+    );
+
+    let agree = serial.1.collision_grid == parallel.1.collision_grid
+        && serial.1.clearance_grid == parallel.1.clearance_grid
+        && serial.1.rules_grid == parallel.1.rules_grid;
+    if !agree {
+        println!("The parallel sweep disagrees with the single-threaded one");
+    }
+    agree
+}
+
+fn exit_when_completed(grid_state: Res<GridState>, mut exit: EventWriter<AppExit>) {
+    if grid_state.completed {
+        exit.send(AppExit::Success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulations::grid_file::{GridFile, GridKind, LayerKind};
+
+    /// Coarse enough to sweep in a moment, fine enough to have a boundary
+    const RESOLUTION: f32 = 3.0 * SIM_UNITS_PER_INCH;
+
+    fn sweep_on(robot: &RobotConfig, threads: usize, path: &str) -> GridState {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut grid_state = GridState::new(robot, RESOLUTION, path);
+        pool.install(|| sweep_tiles(&mut grid_state, robot));
+        grid_state
+    }
+
+    #[test]
+    fn threads_sweep_the_same_grid_and_it_loads_back() {
+        let robot = RobotConfig::load(concat!(env!("CARGO_MANIFEST_DIR"), "/robot.ron")).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("frc_2025_arm_sim_grid_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let serial = sweep_on(&robot, 1, path);
+        let parallel = sweep_on(&robot, 4, path);
+        assert!(serial.completed && parallel.completed);
+        assert!(serial.collision_grid.iter().flatten().any(|&cell| cell));
+        assert!(serial.collision_grid.iter().flatten().any(|&cell| !cell));
+        assert_eq!(serial.collision_grid, parallel.collision_grid);
+        assert_eq!(serial.clearance_grid, parallel.clearance_grid);
+        assert_eq!(serial.rules_grid, parallel.rules_grid);
+
+        serial.save_to_file().unwrap();
+        let loaded = GridFile::load_kind(path, GridKind::Cartesian);
+        let _ = std::fs::remove_file(path);
+        let loaded = loaded.unwrap();
+        assert!(loaded.check_geometry(robot.geometry_hash).is_ok());
+        assert_eq!(loaded.cells, serial.collision_grid);
+        assert_eq!(loaded.layer(LayerKind::Rules), serial.rules_grid.as_ref());
+    }
+}
@@ -1,12 +1,17 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+mod adaptive;
 mod components;
+mod cspace;
+mod headless;
 use components::*;
 
-const ELEVATOR: Group = Group::GROUP_1;
-const INTAKE: Group = Group::GROUP_2;
+use crate::simulations::robot::{RobotConfig, SIM_UNITS_PER_INCH};
+
 const STEPS_PER_FRAME: usize = 10;
+/// Cell size of the sweep benchmark, in inches
+const BENCHMARK_RESOLUTION: f32 = 0.5;
 
 #[derive(Component)]
 struct IntakeMaterial(Handle<ColorMaterial>);
@@ -17,47 +22,106 @@ struct BatchResources {
     safe_mesh: Handle<Mesh>,
     collision_material: Handle<ColorMaterial>,
     safe_material: Handle<ColorMaterial>,
+    /// Safe but breaking the extension or height limits
+    rules_material: Handle<ColorMaterial>,
+}
+
+pub struct GridOptions {
+    pub robot_config: String,
+    /// Output file
+    pub grid_file: String,
+    /// Overrides the default cell size, or the arm angle step in joint space
+    pub resolution: Option<f32>,
+    /// Overrides the default elevator step in joint space
+    pub height_resolution: Option<f32>,
+    pub headless: bool,
+    pub joint_space: bool,
+    /// Splits cells on the collision boundary down to this size
+    pub min_resolution: Option<f32>,
 }
 
-pub fn run() -> App {
+/// Times the headless sweep on one thread and on every core, see
+/// `headless::benchmark`. Defaults to half-inch cells.
+pub fn benchmark(robot_config: &str, resolution: Option<f32>) -> bool {
+    let robot = RobotConfig::load_or_default(robot_config);
+    headless::benchmark(
+        &robot,
+        resolution.unwrap_or(BENCHMARK_RESOLUTION * SIM_UNITS_PER_INCH),
+    )
+}
+
+/// App that sweeps the grid, `None` if the options can't make one
+pub fn run(options: GridOptions) -> Option<App> {
+    let robot = RobotConfig::load_or_default(&options.robot_config);
+    if options.joint_space {
+        let grid_state = JointGridState::new(
+            &robot,
+            options.resolution.unwrap_or(JOINT_ANGLE_RESOLUTION),
+            options.height_resolution.unwrap_or(JOINT_HEIGHT_RESOLUTION),
+            &options.grid_file,
+        );
+        return Some(cspace::run(robot, grid_state, options.headless));
+    }
+    let resolution = options.resolution.unwrap_or(GRID_RESOLUTION);
+    if let Some(min_resolution) = options.min_resolution {
+        return match AdaptiveGridState::new(&robot, resolution, min_resolution, &options.grid_file)
+        {
+            Ok(grid_state) => Some(adaptive::run(robot, grid_state, options.headless)),
+            Err(e) => {
+                println!("Invalid --min-resolution: {}", e);
+                None
+            }
+        };
+    }
+    let grid_state = GridState::new(&robot, resolution, &options.grid_file);
+    if options.headless {
+        return Some(headless::run(robot, grid_state));
+    }
+
     let mut app = App::new();
     app.add_plugins((
         DefaultPlugins,
         RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
     ))
-    .insert_resource(GridState::new())
+    .insert_resource(grid_state)
+    .insert_resource(robot)
     .add_systems(
         Startup,
         (setup_graphics, setup_bodies, setup_batch_resources),
     )
     .add_systems(Update, check_grid_position);
-    app
+    Some(app)
 }
 
 fn setup_bodies(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    robot: Res<RobotConfig>,
+    grid_state: Res<GridState>,
 ) {
-    // Spawn elevator sensor
-    commands.spawn((
-        Collider::cuboid(2.52, 48.25),
-        Sensor,
-        Mesh2d(meshes.add(Rectangle::new(5.04, 96.5))),
-        MeshMaterial2d(materials.add(Color::linear_rgb(0.5, 0.5, 0.5))),
-        Transform::from_xyz(0.0, 0.0, 0.0),
-        CollisionGroups::new(ELEVATOR, INTAKE),
-    ));
+    // Spawn sensors for the elevator and the chassis
+    let fixed_material = materials.add(Color::linear_rgb(0.5, 0.5, 0.5));
+    for part in robot.fixed_parts() {
+        commands.spawn((
+            part.shape.collider(),
+            Sensor,
+            Mesh2d(meshes.add(part.shape.mesh())),
+            MeshMaterial2d(fixed_material.clone()),
+            part.pose.transform(),
+            part.groups,
+        ));
+    }
 
     // Store the default material
     let default_material = materials.add(Color::linear_rgb(0.2, 0.8, 0.2));
 
     // Spawn intake with marker component and material reference
     commands.spawn((
-        Mesh2d(meshes.add(Rectangle::new(38.1, 13.35))),
+        Mesh2d(meshes.add(robot.intake().shape.mesh())),
         MeshMaterial2d(default_material.clone()),
         Transform::from_xyz(-20.0, -20.0, 1.0)
-            .with_rotation(Quat::from_rotation_z(std::f32::consts::PI / 4.0)),
+            .with_rotation(Quat::from_rotation_z(grid_state.intake_rotation())),
         IntakeMarker,
         IntakeMaterial(default_material),
     ));
@@ -73,6 +137,7 @@ fn setup_batch_resources(
         safe_mesh: meshes.add(Rectangle::new(1.0, 1.0)),
         collision_material: materials.add(Color::linear_rgb(0.8, 0.2, 0.2)),
         safe_material: materials.add(Color::linear_rgb(0.2, 0.8, 0.2)),
+        rules_material: materials.add(Color::linear_rgb(0.9, 0.8, 0.1)),
     };
     commands.insert_resource(batch_resources);
 }
@@ -90,6 +155,7 @@ fn check_grid_position(
     >,
     physics_context: ReadDefaultRapierContext,
     batch_resources: Res<BatchResources>,
+    robot: Res<RobotConfig>,
 ) {
     if grid_state.completed {
         return;
@@ -98,37 +164,29 @@ fn check_grid_position(
     for _ in 0..STEPS_PER_FRAME {
         let current_x = grid_state.current_x;
         let current_y = grid_state.current_y;
-        let max_x = grid_state.max_x;
-        let max_y = grid_state.max_y;
-        let min_x = grid_state.min_x;
-        let step_size = grid_state.step_size;
 
         if let Ok((mut transform, mut material, default_material)) = intake_query.get_single_mut() {
-            // Calculate intake position based on theoretical arm endpoint
-            // Offset the intake by 13 units at 45 degrees from the arm endpoint
-            let offset_distance = 13.0;
-            let offset_angle = std::f32::consts::PI / 4.0; // 45 degrees
-            
             // Position the intake relative to the theoretical arm endpoint
-            let intake_x = current_x + offset_distance * offset_angle.cos();
-            let intake_y = current_y + offset_distance * offset_angle.sin();
-            
-            transform.translation.x = intake_x;
-            transform.translation.y = intake_y;
+            let intake = grid_state.intake_position();
+            transform.translation.x = intake.x;
+            transform.translation.y = intake.y;
 
-            // Rest of the collision checking remains the same
             let mut has_collision = false;
             physics_context.intersections_with_shape(
-                transform.translation.truncate(),
-                transform.rotation.xyz().z,
-                &Collider::cuboid(19.05, 6.675),
-                QueryFilter::new().groups(CollisionGroups::new(INTAKE, ELEVATOR)),
+                intake,
+                grid_state.intake_rotation(),
+                &grid_state.world.intake,
+                QueryFilter::new().groups(robot.intake().groups),
                 |_entity| {
                     has_collision = true;
                     false
                 },
             );
 
+            let clearance = grid_state.intake_clearance();
+            grid_state.record_clearance(clearance);
+            let breaks_rules = grid_state.check_rules(&robot);
+
             // Update material based on collision state
             material.0 = if has_collision {
                 batch_resources.collision_material.clone()
@@ -145,6 +203,8 @@ fn check_grid_position(
                 }),
                 MeshMaterial2d(if has_collision {
                     batch_resources.collision_material.clone()
+                } else if breaks_rules {
+                    batch_resources.rules_material.clone()
                 } else {
                     batch_resources.safe_material.clone()
                 }),
@@ -156,33 +216,12 @@ fn check_grid_position(
                 grid_state.mark_collision(current_x, current_y);
             }
 
-            // Move to next position
-            grid_state.current_x += step_size;
-            if grid_state.current_x > max_x {
-                grid_state.current_x = min_x;
-                grid_state.current_y += step_size;
-
-                if grid_state.current_y > max_y {
-                    grid_state.completed = true;
-                    println!(
-                        "Grid check completed! Grid size: {}x{}",
-                        grid_state.collision_grid[0].len(),
-                        grid_state.collision_grid.len()
-                    );
-
-                    // Save grid to file
-                    if let Err(e) = grid_state.save_to_file() {
-                        println!("Failed to save collision grid: {}", e);
-                    } else {
-                        println!("Collision grid saved to collision_grid.bin");
-                    }
-                }
+            grid_state.advance();
+            if grid_state.completed {
+                grid_state.finish();
+                break;
             }
         }
-
-        if grid_state.completed {
-            break;
-        }
     }
 }
 
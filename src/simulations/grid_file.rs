@@ -0,0 +1,1099 @@
+//! On-disk format for collision grids.
+//!
+//! Version 4 layout (all values little endian):
+//!
+//! | field          | type                                      |
+//! |----------------|-------------------------------------------|
+//! | magic          | `b"FRCGRID\0"`                            |
+//! | version        | u16                                       |
+//! | kind           | u8 (0 = cartesian, 1 = joint space)       |
+//! | x/y units      | u8, u8 (0 = sim units, 1 = degrees)       |
+//! | encoding       | u8 (0 = bit packed, 1 = run length)       |
+//! | width, height  | u32, u32                                  |
+//! | bounds         | f32 min_x, max_x, min_y, max_y            |
+//! | steps          | f32 step_x, step_y                        |
+//! | geometry hash  | u64                                       |
+//! | source         | u16 length + UTF-8                        |
+//! | payload        | u32 length + cell data                    |
+//! | layer count    | u8                                        |
+//! | layers         | per layer: u8 layer kind (0 = rules,      |
+//! |                | 1 = clearance, 2 = refinement), u8        |
+//! |                | encoding (2 = f32 per cell, 3 = quadtree  |
+//! |                | per cell), u32 length + cell data         |
+//! | crc32          | u32 over every preceding byte             |
+//!
+//! Extra layers share the grid's dimensions and cell order. Rules layers hold
+//! a flag per cell, clearance layers a value per cell and refinement layers a
+//! quadtree per cell, written depth first with a byte per node (0 = safe
+//! leaf, 1 = colliding leaf, 2 = split into four children). Version 3 files
+//! (no refinement layers), version 2 files (flag layers only), version 1
+//! files (no layers) and version 0 files (no header, one byte per cell) are
+//! still readable.
+
+use std::fmt;
+use std::fs;
+
+/// Default paths, read by the main sim and written by `grid`
+pub const COLLISION_GRID_FILE: &str = "collision_grid.bin";
+pub const JOINT_GRID_FILE: &str = "cspace_grid.bin";
+
+const MAGIC: &[u8; 8] = b"FRCGRID\0";
+pub const FORMAT_VERSION: u16 = 4;
+const V0_HEADER_LEN: usize = 28;
+const MAX_CELLS: usize = 1 << 28;
+/// Deepest quadtree accepted, far below any useful cell size
+pub const MAX_TREE_DEPTH: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridKind {
+    /// Intake position in world coordinates
+    Cartesian,
+    /// Arm angle (x) against elevator height (y)
+    JointSpace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// Simulation world units (100 per meter)
+    SimUnits,
+    Degrees,
+}
+
+/// What an extra layer of cells records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    /// Positions that break the extension or height limits
+    Rules,
+    /// Signed distance in sim units from the robot to the nearest obstacle,
+    /// negative by the penetration depth where they overlap
+    Clearance,
+    /// Cells split down to a finer size along the collision boundary
+    Refinement,
+}
+
+/// Cells of an extra layer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layer {
+    Flags(Vec<Vec<bool>>),
+    Values(Vec<Vec<f32>>),
+    Trees(Vec<Vec<QuadNode>>),
+}
+
+/// Part of a refined cell: a leaf that collides or not, or four children in
+/// the order bottom left, bottom right, top left, top right
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuadNode {
+    Leaf(bool),
+    Split(Box<[QuadNode; 4]>),
+}
+
+impl QuadNode {
+    /// Whether any leaf collides
+    pub fn collides(&self) -> bool {
+        match self {
+            Self::Leaf(collides) => *collides,
+            Self::Split(children) => children.iter().any(Self::collides),
+        }
+    }
+
+    /// Whether the leaf at `(u, v)` collides, both in [0, 1) across the cell
+    pub fn collides_at(&self, u: f32, v: f32) -> bool {
+        match self {
+            Self::Leaf(collides) => *collides,
+            Self::Split(children) => {
+                let (right, top) = (u >= 0.5, v >= 0.5);
+                let half = |t: f32, upper: bool| if upper { t * 2.0 - 1.0 } else { t * 2.0 };
+                children[right as usize + 2 * top as usize]
+                    .collides_at(half(u, right), half(v, top))
+            }
+        }
+    }
+
+    /// Leaves as (u, v, size, collides), with the lower left corner and the
+    /// size as fractions of the cell
+    pub fn leaves(&self) -> Vec<(f32, f32, f32, bool)> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(0.0, 0.0, 1.0, &mut leaves);
+        leaves
+    }
+
+    fn collect_leaves(&self, u: f32, v: f32, size: f32, out: &mut Vec<(f32, f32, f32, bool)>) {
+        match self {
+            Self::Leaf(collides) => out.push((u, v, size, *collides)),
+            Self::Split(children) => {
+                let half = size / 2.0;
+                for (i, child) in children.iter().enumerate() {
+                    let (du, dv) = ((i % 2) as f32 * half, (i / 2) as f32 * half);
+                    child.collect_leaves(u + du, v + dv, half, out);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    BitPacked,
+    RunLength,
+    /// One f32 per cell, for value layers
+    Float,
+    /// A depth-first quadtree per cell, for refinement layers
+    Tree,
+}
+
+#[derive(Debug)]
+pub enum GridFileError {
+    Io(std::io::Error),
+    Truncated,
+    UnsupportedVersion(u16),
+    InvalidHeader(&'static str),
+    InvalidDimensions {
+        width: u32,
+        height: u32,
+    },
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// Run lengths add up to more cells than the grid has
+    RunOverflow {
+        cells: usize,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    KindMismatch {
+        expected: GridKind,
+        actual: GridKind,
+    },
+    GeometryMismatch {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for GridFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Truncated => write!(f, "file is truncated"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Self::InvalidHeader(field) => write!(f, "invalid header field: {}", field),
+            Self::InvalidDimensions { width, height } => {
+                write!(f, "invalid grid dimensions {}x{}", width, height)
+            }
+            Self::SizeMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "expected {} bytes of cell data, found {}",
+                    expected, actual
+                )
+            }
+            Self::RunOverflow { cells } => {
+                write!(f, "run lengths add up to more than {} cells", cells)
+            }
+            Self::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {:08x}, got {:08x})",
+                    expected, actual
+                )
+            }
+            Self::KindMismatch { expected, actual } => {
+                write!(f, "expected a {:?} grid, found {:?}", expected, actual)
+            }
+            Self::GeometryMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "grid was generated for different robot geometry (expected {:016x}, got {:016x})",
+                    expected, actual
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GridFileError {}
+
+impl From<std::io::Error> for GridFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+pub struct GridFile {
+    pub version: u16,
+    pub kind: GridKind,
+    pub x_unit: Unit,
+    pub y_unit: Unit,
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_y: f32,
+    pub max_y: f32,
+    pub step_x: f32,
+    pub step_y: f32,
+    /// Hash of the robot geometry the grid was generated from, 0 if unknown
+    pub geometry_hash: u64,
+    /// Free-form description of what produced the grid
+    pub source: String,
+    /// Row-major cells, `cells[y][x]`
+    pub cells: Vec<Vec<bool>>,
+    /// Extra layers over the same cells
+    pub layers: Vec<(LayerKind, Layer)>,
+}
+
+impl GridFile {
+    pub fn width(&self) -> usize {
+        self.cells.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Cells of an extra flag layer, if the file has it
+    pub fn layer(&self, kind: LayerKind) -> Option<&Vec<Vec<bool>>> {
+        self.layers.iter().find_map(|(layer, cells)| match cells {
+            Layer::Flags(cells) if *layer == kind => Some(cells),
+            _ => None,
+        })
+    }
+
+    /// Quadtrees of an extra refinement layer, if the file has it
+    pub fn trees(&self, kind: LayerKind) -> Option<&Vec<Vec<QuadNode>>> {
+        self.layers.iter().find_map(|(layer, cells)| match cells {
+            Layer::Trees(cells) if *layer == kind => Some(cells),
+            _ => None,
+        })
+    }
+
+    /// Cells of an extra value layer, if the file has it
+    pub fn values(&self, kind: LayerKind) -> Option<&Vec<Vec<f32>>> {
+        self.layers.iter().find_map(|(layer, cells)| match cells {
+            Layer::Values(cells) if *layer == kind => Some(cells),
+            _ => None,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (encoding, payload) = encode(&self.cells);
+
+        let mut out = Vec::with_capacity(payload.len() + 96);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.push(self.kind.to_byte());
+        out.push(self.x_unit.to_byte());
+        out.push(self.y_unit.to_byte());
+        out.push(encoding.to_byte());
+        out.extend_from_slice(&(self.width() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.height() as u32).to_le_bytes());
+        for value in [
+            self.min_x,
+            self.max_x,
+            self.min_y,
+            self.max_y,
+            self.step_x,
+            self.step_y,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&self.geometry_hash.to_le_bytes());
+        out.extend_from_slice(&(self.source.len() as u16).to_le_bytes());
+        out.extend_from_slice(self.source.as_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&payload);
+        out.push(self.layers.len() as u8);
+        for (kind, cells) in &self.layers {
+            let (encoding, payload) = match cells {
+                Layer::Flags(cells) => encode(cells),
+                Layer::Values(cells) => (Encoding::Float, encode_floats(cells)),
+                Layer::Trees(cells) => (Encoding::Tree, encode_trees(cells)),
+            };
+            out.push(kind.to_byte());
+            out.push(encoding.to_byte());
+            out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            out.extend_from_slice(&payload);
+        }
+        let crc = crc32fast::hash(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    pub fn load(path: &str) -> Result<Self, GridFileError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn load_kind(path: &str, kind: GridKind) -> Result<Self, GridFileError> {
+        let file = Self::load(path)?;
+        if file.kind != kind {
+            return Err(GridFileError::KindMismatch {
+                expected: kind,
+                actual: file.kind,
+            });
+        }
+        Ok(file)
+    }
+
+    /// Rejects grids generated from different robot geometry. Legacy files
+    /// carry no hash and are accepted.
+    pub fn check_geometry(&self, geometry_hash: u64) -> Result<(), GridFileError> {
+        if self.geometry_hash != 0 && self.geometry_hash != geometry_hash {
+            return Err(GridFileError::GeometryMismatch {
+                expected: geometry_hash,
+                actual: self.geometry_hash,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, GridFileError> {
+        if bytes.starts_with(MAGIC) {
+            Self::from_headered_bytes(bytes)
+        } else {
+            Self::from_v0_bytes(bytes)
+        }
+    }
+
+    /// Versions 1 to 4, which differ only in the extra layers
+    fn from_headered_bytes(bytes: &[u8]) -> Result<Self, GridFileError> {
+        if bytes.len() < MAGIC.len() + 4 {
+            return Err(GridFileError::Truncated);
+        }
+        let (body, crc_bytes) = bytes.split_at(bytes.len() - 4);
+        let expected = u32::from_le_bytes(crc_bytes.try_into().unwrap());
+        let actual = crc32fast::hash(body);
+        if expected != actual {
+            return Err(GridFileError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut reader = Reader::new(&body[MAGIC.len()..]);
+        let version = reader.u16()?;
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(GridFileError::UnsupportedVersion(version));
+        }
+        let kind = GridKind::from_byte(reader.u8()?)?;
+        let x_unit = Unit::from_byte(reader.u8()?)?;
+        let y_unit = Unit::from_byte(reader.u8()?)?;
+        let encoding = Encoding::from_byte(reader.u8()?)?;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let (width_usize, height_usize) = check_dimensions(width, height)?;
+        let min_x = reader.f32()?;
+        let max_x = reader.f32()?;
+        let min_y = reader.f32()?;
+        let max_y = reader.f32()?;
+        let step_x = reader.f32()?;
+        let step_y = reader.f32()?;
+        if [step_x, step_y]
+            .iter()
+            .any(|step| step.is_nan() || *step <= 0.0)
+        {
+            return Err(GridFileError::InvalidHeader("step size"));
+        }
+        let geometry_hash = reader.u64()?;
+        let source_len = reader.u16()? as usize;
+        let source = String::from_utf8(reader.take(source_len)?.to_vec())
+            .map_err(|_| GridFileError::InvalidHeader("source"))?;
+        let payload_len = reader.u32()? as usize;
+        let payload = reader.take(payload_len)?;
+        let cells = decode(encoding, payload, width_usize, height_usize)?;
+
+        let mut layers = Vec::new();
+        if version >= 2 {
+            for _ in 0..reader.u8()? {
+                let kind = LayerKind::from_byte(reader.u8()?)?;
+                let encoding = Encoding::from_byte(reader.u8()?)?;
+                let length = reader.u32()? as usize;
+                let payload = reader.take(length)?;
+                let cells = match (kind, encoding) {
+                    (LayerKind::Rules, Encoding::BitPacked | Encoding::RunLength) => {
+                        Layer::Flags(decode(encoding, payload, width_usize, height_usize)?)
+                    }
+                    (LayerKind::Clearance, Encoding::Float) => {
+                        Layer::Values(decode_floats(payload, width_usize, height_usize)?)
+                    }
+                    (LayerKind::Refinement, Encoding::Tree) if version >= 4 => {
+                        Layer::Trees(decode_trees(payload, width_usize, height_usize)?)
+                    }
+                    _ => return Err(GridFileError::InvalidHeader("layer encoding")),
+                };
+                layers.push((kind, cells));
+            }
+        }
+        if reader.remaining() != 0 {
+            return Err(GridFileError::SizeMismatch {
+                expected: reader.offset,
+                actual: reader.offset + reader.remaining(),
+            });
+        }
+
+        Ok(Self {
+            version,
+            kind,
+            x_unit,
+            y_unit,
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+            step_x,
+            step_y,
+            geometry_hash,
+            source,
+            cells,
+            layers,
+        })
+    }
+
+    /// Original headerless layout: width/height, five f32s, one byte per cell
+    fn from_v0_bytes(bytes: &[u8]) -> Result<Self, GridFileError> {
+        let mut reader = Reader::new(bytes);
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let (width_usize, height_usize) = check_dimensions(width, height)?;
+        let min_x = reader.f32()?;
+        let max_x = reader.f32()?;
+        let min_y = reader.f32()?;
+        let max_y = reader.f32()?;
+        let step_size = reader.f32()?;
+        if step_size.is_nan() || step_size <= 0.0 {
+            return Err(GridFileError::InvalidHeader("step size"));
+        }
+
+        let expected = width_usize * height_usize;
+        let actual = bytes.len() - V0_HEADER_LEN;
+        if actual != expected {
+            return Err(GridFileError::SizeMismatch { expected, actual });
+        }
+        let cells = bytes[V0_HEADER_LEN..]
+            .chunks(width_usize)
+            .map(|row| row.iter().map(|&cell| cell != 0).collect())
+            .collect();
+
+        Ok(Self {
+            version: 0,
+            kind: GridKind::Cartesian,
+            x_unit: Unit::SimUnits,
+            y_unit: Unit::SimUnits,
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+            step_x: step_size,
+            step_y: step_size,
+            geometry_hash: 0,
+            source: String::new(),
+            cells,
+            layers: Vec::new(),
+        })
+    }
+}
+
+impl GridKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Cartesian => 0,
+            Self::JointSpace => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, GridFileError> {
+        match byte {
+            0 => Ok(Self::Cartesian),
+            1 => Ok(Self::JointSpace),
+            _ => Err(GridFileError::InvalidHeader("kind")),
+        }
+    }
+}
+
+impl Unit {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::SimUnits => 0,
+            Self::Degrees => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, GridFileError> {
+        match byte {
+            0 => Ok(Self::SimUnits),
+            1 => Ok(Self::Degrees),
+            _ => Err(GridFileError::InvalidHeader("units")),
+        }
+    }
+}
+
+impl LayerKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Rules => 0,
+            Self::Clearance => 1,
+            Self::Refinement => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, GridFileError> {
+        match byte {
+            0 => Ok(Self::Rules),
+            1 => Ok(Self::Clearance),
+            2 => Ok(Self::Refinement),
+            _ => Err(GridFileError::InvalidHeader("layer kind")),
+        }
+    }
+}
+
+impl Encoding {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::BitPacked => 0,
+            Self::RunLength => 1,
+            Self::Float => 2,
+            Self::Tree => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, GridFileError> {
+        match byte {
+            0 => Ok(Self::BitPacked),
+            1 => Ok(Self::RunLength),
+            2 => Ok(Self::Float),
+            3 => Ok(Self::Tree),
+            _ => Err(GridFileError::InvalidHeader("encoding")),
+        }
+    }
+}
+
+/// FNV-1a hash used to tag grids with the geometry that produced them. Fields
+/// are fed in as little endian bytes and strings with their length, so the
+/// hash only changes when the geometry does.
+pub struct GeometryHasher(u64);
+
+impl Default for GeometryHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GeometryHasher {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+        self
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.bytes(&[value])
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub fn f32(&mut self, value: f32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn str(&mut self, value: &str) -> &mut Self {
+        self.bytes(&(value.len() as u32).to_le_bytes())
+            .bytes(value.as_bytes())
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn check_dimensions(width: u32, height: u32) -> Result<(usize, usize), GridFileError> {
+    let cells = (width as usize).checked_mul(height as usize);
+    match cells {
+        Some(cells) if cells > 0 && cells <= MAX_CELLS => Ok((width as usize, height as usize)),
+        _ => Err(GridFileError::InvalidDimensions { width, height }),
+    }
+}
+
+/// Whichever encoding is smaller for these cells
+fn encode(cells: &[Vec<bool>]) -> (Encoding, Vec<u8>) {
+    let bits = encode_bit_packed(cells);
+    let runs = encode_run_length(cells);
+    if runs.len() < bits.len() {
+        (Encoding::RunLength, runs)
+    } else {
+        (Encoding::BitPacked, bits)
+    }
+}
+
+fn decode(
+    encoding: Encoding,
+    payload: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<bool>>, GridFileError> {
+    match encoding {
+        Encoding::BitPacked => decode_bit_packed(payload, width, height),
+        Encoding::RunLength => decode_run_length(payload, width, height),
+        Encoding::Float | Encoding::Tree => Err(GridFileError::InvalidHeader("encoding")),
+    }
+}
+
+/// Row-major cells, one little endian f32 each
+fn encode_floats(cells: &[Vec<f32>]) -> Vec<u8> {
+    cells
+        .iter()
+        .flatten()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn decode_floats(
+    payload: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<f32>>, GridFileError> {
+    let expected = width * height * 4;
+    if payload.len() != expected {
+        return Err(GridFileError::SizeMismatch {
+            expected,
+            actual: payload.len(),
+        });
+    }
+    Ok(payload
+        .chunks(width * 4)
+        .map(|row| {
+            row.chunks(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect()
+        })
+        .collect())
+}
+
+/// Row-major cells, eight per byte, least significant bit first
+fn encode_bit_packed(cells: &[Vec<bool>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (i, cell) in cells.iter().flatten().enumerate() {
+        if i % 8 == 0 {
+            out.push(0);
+        }
+        if *cell {
+            *out.last_mut().unwrap() |= 1 << (i % 8);
+        }
+    }
+    out
+}
+
+fn decode_bit_packed(
+    payload: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<bool>>, GridFileError> {
+    let expected = (width * height).div_ceil(8);
+    if payload.len() != expected {
+        return Err(GridFileError::SizeMismatch {
+            expected,
+            actual: payload.len(),
+        });
+    }
+    Ok((0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let i = y * width + x;
+                    payload[i / 8] & (1 << (i % 8)) != 0
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Alternating run lengths as LEB128 varints, starting with a (possibly empty)
+/// run of safe cells
+fn encode_run_length(cells: &[Vec<bool>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut current = false;
+    let mut run = 0u64;
+    for &cell in cells.iter().flatten() {
+        if cell != current {
+            write_varint(&mut out, run);
+            current = cell;
+            run = 0;
+        }
+        run += 1;
+    }
+    write_varint(&mut out, run);
+    out
+}
+
+fn decode_run_length(
+    payload: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<bool>>, GridFileError> {
+    let total = width * height;
+    let mut flat = Vec::with_capacity(total);
+    let mut reader = Reader::new(payload);
+    let mut current = false;
+    while reader.remaining() > 0 {
+        let run = usize::try_from(reader.varint()?)
+            .ok()
+            .filter(|run| flat.len().checked_add(*run).is_some_and(|end| end <= total))
+            .ok_or(GridFileError::RunOverflow { cells: total })?;
+        flat.extend(std::iter::repeat_n(current, run));
+        current = !current;
+    }
+    if flat.len() != total {
+        return Err(GridFileError::SizeMismatch {
+            expected: total,
+            actual: flat.len(),
+        });
+    }
+    Ok(flat.chunks(width).map(|row| row.to_vec()).collect())
+}
+
+/// Row-major cells, each tree depth first
+fn encode_trees(cells: &[Vec<QuadNode>]) -> Vec<u8> {
+    fn encode_node(node: &QuadNode, out: &mut Vec<u8>) {
+        match node {
+            QuadNode::Leaf(collides) => out.push(*collides as u8),
+            QuadNode::Split(children) => {
+                out.push(2);
+                for child in children.iter() {
+                    encode_node(child, out);
+                }
+            }
+        }
+    }
+    let mut out = Vec::new();
+    for node in cells.iter().flatten() {
+        encode_node(node, &mut out);
+    }
+    out
+}
+
+fn decode_trees(
+    payload: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<QuadNode>>, GridFileError> {
+    fn decode_node(reader: &mut Reader, depth: usize) -> Result<QuadNode, GridFileError> {
+        match reader.u8()? {
+            0 => Ok(QuadNode::Leaf(false)),
+            1 => Ok(QuadNode::Leaf(true)),
+            2 if depth < MAX_TREE_DEPTH => {
+                let mut child = || decode_node(reader, depth + 1);
+                Ok(QuadNode::Split(Box::new([
+                    child()?,
+                    child()?,
+                    child()?,
+                    child()?,
+                ])))
+            }
+            _ => Err(GridFileError::InvalidHeader("quadtree node")),
+        }
+    }
+    let mut reader = Reader::new(payload);
+    let cells = (0..height)
+        .map(|_| {
+            (0..width)
+                .map(|_| decode_node(&mut reader, 0))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if reader.remaining() != 0 {
+        return Err(GridFileError::SizeMismatch {
+            expected: reader.offset,
+            actual: payload.len(),
+        });
+    }
+    Ok(cells)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], GridFileError> {
+        if count > self.remaining() {
+            return Err(GridFileError::Truncated);
+        }
+        let slice = &self.bytes[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], GridFileError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, GridFileError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, GridFileError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, GridFileError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, GridFileError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, GridFileError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn varint(&mut self) -> Result<u64, GridFileError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(GridFileError::InvalidHeader("run length"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset of the version in a headered file
+    const VERSION_OFFSET: usize = MAGIC.len();
+
+    fn grid(width: usize, height: usize, collides: impl Fn(usize, usize) -> bool) -> GridFile {
+        let cells: Vec<Vec<bool>> = (0..height)
+            .map(|y| (0..width).map(|x| collides(x, y)).collect())
+            .collect();
+        let clearance = (0..height)
+            .map(|y| (0..width).map(|x| x as f32 - y as f32).collect())
+            .collect();
+        let rules = (0..height)
+            .map(|y| (0..width).map(|x| x == y).collect())
+            .collect();
+        let mut trees = vec![vec![QuadNode::Leaf(false); width]; height];
+        trees[0][0] = QuadNode::Split(Box::new([
+            QuadNode::Leaf(true),
+            QuadNode::Leaf(false),
+            QuadNode::Split(Box::new([
+                QuadNode::Leaf(false),
+                QuadNode::Leaf(true),
+                QuadNode::Leaf(false),
+                QuadNode::Leaf(false),
+            ])),
+            QuadNode::Leaf(false),
+        ]));
+        GridFile {
+            version: FORMAT_VERSION,
+            kind: GridKind::Cartesian,
+            x_unit: Unit::SimUnits,
+            y_unit: Unit::SimUnits,
+            min_x: -10.0,
+            max_x: 10.0,
+            min_y: -5.0,
+            max_y: 5.0,
+            step_x: 0.5,
+            step_y: 0.5,
+            geometry_hash: 0x1234_5678_9abc_def0,
+            source: "test".to_string(),
+            cells,
+            layers: vec![
+                (LayerKind::Clearance, Layer::Values(clearance)),
+                (LayerKind::Rules, Layer::Flags(rules)),
+                (LayerKind::Refinement, Layer::Trees(trees)),
+            ],
+        }
+    }
+
+    /// Rewrites the trailing checksum after editing the bytes
+    fn reseal(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - 4);
+        let crc = crc32fast::hash(bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    fn assert_same(a: &GridFile, b: &GridFile) {
+        assert_eq!(a.version, b.version);
+        assert_eq!(a.kind, b.kind);
+        assert_eq!(a.x_unit, b.x_unit);
+        assert_eq!(a.y_unit, b.y_unit);
+        assert_eq!(
+            [a.min_x, a.max_x, a.min_y, a.max_y, a.step_x, a.step_y],
+            [b.min_x, b.max_x, b.min_y, b.max_y, b.step_x, b.step_y]
+        );
+        assert_eq!(a.geometry_hash, b.geometry_hash);
+        assert_eq!(a.source, b.source);
+        assert_eq!(a.cells, b.cells);
+        assert_eq!(a.layers, b.layers);
+    }
+
+    #[test]
+    fn round_trips_both_cell_encodings() {
+        // Scattered cells pack into bits, a solid block into runs
+        let scattered = grid(13, 7, |x, y| (x * 7 + y * 3) % 5 == 0);
+        let block = grid(40, 30, |x, y| (10..20).contains(&x) && y < 12);
+        assert_eq!(encode(&scattered.cells).0, Encoding::BitPacked);
+        assert_eq!(encode(&block.cells).0, Encoding::RunLength);
+
+        for file in [scattered, block] {
+            let read = GridFile::from_bytes(&file.to_bytes()).expect("valid file");
+            assert_same(&file, &read);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = grid(13, 7, |x, y| x == y).to_bytes();
+        for length in 0..bytes.len() {
+            assert!(
+                GridFile::from_bytes(&bytes[..length]).is_err(),
+                "accepted {} of {} bytes",
+                length,
+                bytes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_checksum_mismatch() {
+        let mut bytes = grid(13, 7, |x, y| x == y).to_bytes();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x01;
+        assert!(matches!(
+            GridFile::from_bytes(&bytes),
+            Err(GridFileError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        // Without the magic the bytes are read as a v0 file, whose size
+        // can't match its header
+        let mut bytes = grid(13, 7, |x, y| x == y).to_bytes();
+        bytes[0] = b'X';
+        assert!(GridFile::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [0u16, FORMAT_VERSION + 1] {
+            let mut bytes = grid(13, 7, |x, y| x == y).to_bytes();
+            bytes[VERSION_OFFSET..VERSION_OFFSET + 2].copy_from_slice(&version.to_le_bytes());
+            reseal(&mut bytes);
+            assert!(
+                matches!(
+                    GridFile::from_bytes(&bytes),
+                    Err(GridFileError::UnsupportedVersion(v)) if v == version
+                ),
+                "version {}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_refinement_before_version_4() {
+        let mut bytes = grid(13, 7, |x, y| x == y).to_bytes();
+        bytes[VERSION_OFFSET..VERSION_OFFSET + 2].copy_from_slice(&3u16.to_le_bytes());
+        reseal(&mut bytes);
+        assert!(matches!(
+            GridFile::from_bytes(&bytes),
+            Err(GridFileError::InvalidHeader("layer encoding"))
+        ));
+    }
+
+    #[test]
+    fn reads_v0_files() {
+        let (width, height) = (3u32, 2u32);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        for value in [-1.0f32, 1.0, -2.0, 2.0, 0.5] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[1, 0, 0, 0, 1, 1]);
+
+        let file = GridFile::from_bytes(&bytes).expect("valid v0 file");
+        assert_eq!(file.version, 0);
+        assert_eq!(file.kind, GridKind::Cartesian);
+        assert_eq!(
+            [file.min_x, file.max_x, file.min_y, file.max_y],
+            [-1.0, 1.0, -2.0, 2.0]
+        );
+        assert_eq!([file.step_x, file.step_y], [0.5, 0.5]);
+        assert_eq!(file.geometry_hash, 0);
+        assert_eq!(
+            file.cells,
+            vec![vec![true, false, false], vec![false, true, true]]
+        );
+        assert!(file.layers.is_empty());
+
+        // One cell short
+        assert!(matches!(
+            GridFile::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(GridFileError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_run_lengths_past_the_grid() {
+        let mut payload = Vec::new();
+        write_varint(&mut payload, 4);
+        write_varint(&mut payload, u64::MAX);
+        assert!(matches!(
+            decode_run_length(&payload, 3, 2),
+            Err(GridFileError::RunOverflow { cells: 6 })
+        ));
+
+        let mut payload = Vec::new();
+        write_varint(&mut payload, 4);
+        write_varint(&mut payload, 3);
+        assert!(matches!(
+            decode_run_length(&payload, 3, 2),
+            Err(GridFileError::RunOverflow { cells: 6 })
+        ));
+    }
+
+    #[test]
+    fn rejects_quadtrees_past_the_depth_limit() {
+        let payload = vec![2; MAX_TREE_DEPTH + 2];
+        assert!(matches!(
+            decode_trees(&payload, 1, 1),
+            Err(GridFileError::InvalidHeader("quadtree node"))
+        ));
+    }
+}
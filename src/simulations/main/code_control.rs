@@ -1,103 +1,143 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
 
 use crate::simulations::main::components::*;
-
-const ELEVATOR_OFFSET: f32 = -13.65;
-
-#[derive(Debug, Clone, Copy)]
-pub enum PresetPosition {
-    BottomLeft,
-    BottomRight,
-}
-
-impl PresetPosition {
-    pub fn height(&self) -> f32 {
-        match self {
-            Self::BottomLeft => 0.0,
-            Self::BottomRight => 0.0,
-        }
-    }
-
-    pub fn angle(&self) -> f32 {
-        match self {
-            Self::BottomLeft => 220.0_f32.to_radians(),
-            Self::BottomRight => -40.0_f32.to_radians(),
-        }
-    }
-}
+use crate::simulations::main::controllers::ArmControllers;
+use crate::simulations::main::game_pieces::IntakeRollers;
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::planner::*;
+use crate::simulations::main::presets::*;
+use crate::simulations::main::safety::*;
+use crate::simulations::main::sweep::{ContactCheck, Profile};
+use crate::simulations::robot::RobotConfig;
 
 #[derive(Resource)]
 pub struct TargetPosition {
     pub current: PresetPosition,
 }
 
-impl Default for TargetPosition {
-    fn default() -> Self {
-        Self {
-            current: PresetPosition::BottomRight,
-        }
-    }
-}
-
+#[allow(clippy::too_many_arguments)]
 pub fn update_code_motors(
     control_mode: Res<ControlMode>,
-    mut joints: Query<&mut ImpulseJoint>,
     transforms: Query<&Transform>,
     motor_joints: Res<MotorJoints>,
     keys: Res<ButtonInput<KeyCode>>,
+    presets: Res<Presets>,
     mut target: ResMut<TargetPosition>,
+    collision_grid: Option<Res<CollisionGrid>>,
+    joint_grid: Option<Res<JointGrid>>,
+    mut path: ResMut<PlannedPath>,
+    contact_check: Res<ContactCheck>,
+    mut warning: ResMut<SafetyWarning>,
+    rollers: Res<IntakeRollers>,
+    robot: Res<RobotConfig>,
+    mut controllers: ResMut<ArmControllers>,
 ) {
-    // Print current positions
-    if let Ok(transform) = transforms.get(motor_joints.elevator_body) {
-        println!("Elevator position: {:.2}", transform.translation.y);
-    }
-    if let Ok(transform) = transforms.get(motor_joints.arm_body) {
-        let angle_degrees = transform.rotation.to_euler(EulerRot::XYZ).2.to_degrees();
-        println!("Arm angle: {:.2} degrees", angle_degrees);
-    }
+    let current = current_arm_position(&transforms, &motor_joints, &robot);
 
     if matches!(*control_mode, ControlMode::CodeControl) {
         // Handle position switching
-        if keys.just_pressed(KeyCode::ArrowLeft) {
-            target.current = PresetPosition::BottomLeft;
-        } else if keys.just_pressed(KeyCode::ArrowRight) {
-            target.current = PresetPosition::BottomRight;
+        if let Some(preset) = presets
+            .presets
+            .iter()
+            .find(|preset| preset.key_code().is_some_and(|key| keys.just_pressed(key)))
+        {
+            target.current = preset.clone();
         }
 
-        // Update elevator position
-        if let Ok(mut joint) = joints.get_mut(motor_joints.elevator) {
-            joint.data.as_mut().set_motor_position(
-                JointAxis::LinX,
-                target.current.height() + ELEVATOR_OFFSET,
-                5000.0,
-                300.0,
-            );
-        }
+        let requested = ArmPosition {
+            height: target.current.height(),
+            arm_angle: target.current.angle(),
+        };
+        let (goal, mut message) = match check_target(
+            collision_grid.as_deref(),
+            &robot,
+            &requested,
+            rollers.held(),
+        ) {
+            TargetCheck::Safe => (requested, None),
+            TargetCheck::Clamped(clamped) => (
+                clamped,
+                Some(format!(
+                    "{} is unsafe, clamped arm to {:.1} degrees",
+                    target.current.name,
+                    clamped.arm_angle.to_degrees()
+                )),
+            ),
+            TargetCheck::Refused => {
+                // Leave the controllers on their previous goal
+                warning.set_if_neq(SafetyWarning(Some(format!(
+                    "{} is unsafe, target refused",
+                    target.current.name
+                ))));
+                return;
+            }
+        };
 
-        // Update arm position
-        if let Ok(mut joint) = joints.get_mut(motor_joints.arm) {
-            joint.data.as_mut().set_motor_position(
-                JointAxis::AngX,
-                target.current.angle(),
-                5000.0,
-                300.0,
-            );
-        }
+        // Follow a collision-free path to the goal when a joint grid is
+        // loaded, otherwise go straight there
+        let commanded = match current {
+            Some(current) => {
+                if path.preset.as_ref() != Some(&target.current) {
+                    path.preset = Some(target.current.clone());
+                    let waypoints = match joint_grid.as_deref() {
+                        Some(joint_grid) => {
+                            plan_path(joint_grid, &current, &goal).unwrap_or_default()
+                        }
+                        None => vec![goal],
+                    };
+                    // The grid cells are coarse, and without a grid only the
+                    // goal has been checked, so prove the way clear along
+                    // the controllers' profiles before following it
+                    let profile = Profile::of_controllers(&controllers);
+                    path.waypoints =
+                        match contact_check.sweep_path(&robot, &current, &waypoints, profile) {
+                            Some(contact) => {
+                                println!(
+                                    "Path to {} is not clear, {}",
+                                    target.current.name,
+                                    contact_check.describe(&contact, profile)
+                                );
+                                Default::default()
+                            }
+                            None => waypoints.into(),
+                        };
+                }
+                match path.next_waypoint(&current) {
+                    Some(waypoint) => waypoint,
+                    None => {
+                        message = Some(format!(
+                            "No collision-free path to {}, holding position",
+                            target.current.name
+                        ));
+                        warning.set_if_neq(SafetyWarning(message));
+                        return;
+                    }
+                }
+            }
+            None => goal,
+        };
+        warning.set_if_neq(SafetyWarning(message));
+
+        // The 50 Hz control loop drives the motors towards this
+        controllers.goal = Some(commanded);
     } else {
-        // Disable elevator motor
-        if let Ok(mut joint) = joints.get_mut(motor_joints.elevator) {
-            joint
-                .data
-                .as_mut()
-                .set_motor(JointAxis::LinX, 0.0, 0.0, 0.0, 0.0);
-        }
-        // Disable arm motor
-        if let Ok(mut joint) = joints.get_mut(motor_joints.arm) {
-            joint
-                .data
-                .as_mut()
-                .set_motor(JointAxis::AngX, 0.0, 0.0, 0.0, 0.0);
-        }
+        // The cursor sets the controllers' goal, so plan afresh from wherever
+        // it leaves the arm
+        path.preset = None;
+        path.waypoints.clear();
     }
 }
+
+/// Current elevator height and arm angle read back from the simulated bodies
+pub fn current_arm_position(
+    transforms: &Query<&Transform>,
+    motor_joints: &MotorJoints,
+    robot: &RobotConfig,
+) -> Option<ArmPosition> {
+    let carriage = transforms.get(motor_joints.elevator_body).ok()?;
+    let arm = transforms.get(motor_joints.arm_body).ok()?;
+    Some(ArmPosition {
+        height: robot.elevator_travel(carriage.translation.truncate()) - robot.elevator_offset(),
+        arm_angle: arm.rotation.to_euler(EulerRot::XYZ).2,
+    })
+}
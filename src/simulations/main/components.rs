@@ -1,8 +1,9 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
 
-pub const ELEVATOR: Group = Group::GROUP_1;
-pub const INTAKE: Group = Group::GROUP_2;
+use crate::simulations::grid_file::{GridFile, GridFileError, GridKind, LayerKind, QuadNode};
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::planner::wrap_angle;
+use crate::simulations::robot::RobotConfig;
 
 #[derive(Component)]
 pub struct IntakeMarker;
@@ -10,7 +11,7 @@ pub struct IntakeMarker;
 #[derive(Resource, Default)]
 pub struct MouseWorldPos(pub Vec2);
 
-#[derive(Resource, Default)]
+#[derive(Resource, Default, Debug)]
 pub enum ControlMode {
     #[default]
     CodeControl,
@@ -28,6 +29,17 @@ pub struct MotorJoints {
 #[derive(Resource)]
 pub struct CollisionGrid {
     pub grid: Vec<Vec<bool>>,
+    /// Arm tip positions where the intake breaks the extension or height
+    /// limits, if the grid has a rules layer
+    pub rules: Option<Vec<Vec<bool>>>,
+    /// Signed distance from the intake to the nearest obstacle for the arm
+    /// tip at each sample, if the grid has a clearance layer
+    pub clearance: Option<Vec<Vec<f32>>>,
+    /// Cells split along the collision boundary, if the grid was generated
+    /// with a minimum resolution
+    pub refinement: Option<Vec<Vec<QuadNode>>>,
+    /// Clearance in sim units a target must keep, zero to only avoid contact
+    pub safety_margin: f32,
     pub min_x: f32,
     pub max_x: f32,
     pub min_y: f32,
@@ -36,43 +48,300 @@ pub struct CollisionGrid {
 }
 
 impl CollisionGrid {
-    pub fn load_from_file(path: &str) -> std::io::Result<Self> {
-        use std::fs::File;
-        use std::io::Read;
-
-        let mut file = File::open(path)?;
-        let mut read_bytes = |count: usize| -> std::io::Result<Vec<u8>> {
-            let mut buffer = vec![0u8; count];
-            file.read_exact(&mut buffer)?;
-            Ok(buffer)
-        };
-
-        // Read dimensions
-        let width = u32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-        let height = u32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-
-        // Read boundaries
-        let min_x = f32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-        let max_x = f32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-        let min_y = f32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-        let max_y = f32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-        let step_size = f32::from_le_bytes(read_bytes(4)?.try_into().unwrap());
-
-        // Read grid data
-        let mut grid = vec![vec![false; width as usize]; height as usize];
-        for y in 0..height as usize {
-            for x in 0..width as usize {
-                grid[y][x] = read_bytes(1)?[0] != 0;
+    pub fn load_from_file(path: &str, robot: &RobotConfig) -> Result<Self, GridFileError> {
+        let file = GridFile::load_kind(path, GridKind::Cartesian)?;
+        file.check_geometry(robot.geometry_hash)?;
+        if file.step_x != file.step_y {
+            return Err(GridFileError::InvalidHeader("non-square cells"));
+        }
+        if file.version == 0 {
+            println!(
+                "{} uses the legacy v0 grid format, regenerate it with `grid`",
+                path
+            );
+        }
+
+        Ok(Self {
+            rules: file.layer(LayerKind::Rules).cloned(),
+            clearance: file.values(LayerKind::Clearance).cloned(),
+            refinement: file.trees(LayerKind::Refinement).cloned(),
+            safety_margin: 0.0,
+            grid: file.cells,
+            min_x: file.min_x,
+            max_x: file.max_x,
+            min_y: file.min_y,
+            max_y: file.max_y,
+            step_size: file.step_x,
+        })
+    }
+
+    /// Center of the cell at `(row, column)`
+    pub fn cell_center(&self, row: usize, column: usize) -> Vec2 {
+        Vec2::new(
+            self.min_x + (column as f32 + 0.5) * self.step_size,
+            self.min_y + (row as f32 + 0.5) * self.step_size,
+        )
+    }
+
+    /// Whether an arm tip position collides, looked up in the finest cell that
+    /// covers it. `None` outside the grid.
+    pub fn collides_at(&self, point: Vec2) -> Option<bool> {
+        let x = (point.x - self.min_x) / self.step_size;
+        let y = (point.y - self.min_y) / self.step_size;
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (column, row) = (x.floor() as usize, y.floor() as usize);
+        let collides = *self.grid.get(row)?.get(column)?;
+        Some(match &self.refinement {
+            Some(trees) => trees[row][column].collides_at(x.fract(), y.fract()),
+            None => collides,
+        })
+    }
+
+    /// Intake clearance for an arm tip position, interpolated between the
+    /// samples. `None` outside the grid or without a clearance layer.
+    pub fn tip_clearance(&self, point: Vec2) -> Option<f32> {
+        interpolate(
+            self.clearance.as_ref()?,
+            (point.x - self.min_x) / self.step_size,
+            (point.y - self.min_y) / self.step_size,
+        )
+    }
+
+    /// Whether an arm tip position keeps the safety margin. Always true
+    /// without a margin or a clearance layer.
+    pub fn keeps_margin(&self, point: Vec2) -> bool {
+        self.safety_margin <= 0.0
+            || self.clearance.is_none()
+            || self
+                .tip_clearance(point)
+                .is_some_and(|clearance| clearance >= self.safety_margin)
+    }
+
+    /// Distance from an arm tip position to the nearest collision, zero
+    /// inside one. Read from the clearance layer when the grid has one,
+    /// otherwise the distance to the nearest colliding sample, searched
+    /// outwards from the point. `None` if nothing in the grid collides.
+    pub fn distance_to_collision(&self, point: Vec2) -> Option<f32> {
+        if self.collides_at(point) == Some(true) {
+            return Some(0.0);
+        }
+        if let Some(clearance) = self.tip_clearance(point) {
+            return clearance.is_finite().then_some(clearance.max(0.0));
+        }
+
+        // Cells are sampled at their lower left corners, so sample (column,
+        // row) sits at those whole grid coordinates
+        let (width, height) = (self.grid[0].len(), self.grid.len());
+        let x = (point.x - self.min_x) / self.step_size;
+        let y = (point.y - self.min_y) / self.step_size;
+        let center_x = x.round().clamp(0.0, (width - 1) as f32) as usize;
+        let center_y = y.round().clamp(0.0, (height - 1) as f32) as usize;
+        let mut nearest: Option<f32> = None;
+        for ring in 0..width.max(height) {
+            // Every sample in this ring is at least this far away
+            let closest = (ring as f32 - 0.5).max(0.0) * self.step_size;
+            if nearest.is_some_and(|nearest| nearest <= closest) {
+                break;
+            }
+            for row in center_y.saturating_sub(ring)..=(center_y + ring).min(height - 1) {
+                let on_edge = row.abs_diff(center_y) == ring;
+                for column in center_x.saturating_sub(ring)..=(center_x + ring).min(width - 1) {
+                    if !on_edge && column.abs_diff(center_x) != ring || !self.grid[row][column] {
+                        continue;
+                    }
+                    let distance =
+                        Vec2::new(column as f32 - x, row as f32 - y).length() * self.step_size;
+                    nearest = Some(nearest.map_or(distance, |nearest| nearest.min(distance)));
+                }
+            }
+        }
+        nearest
+    }
+}
+
+/// Joint-space collision map. Columns are arm angles in degrees, rows are
+/// elevator joint positions.
+#[derive(Resource, Clone)]
+pub struct JointGrid {
+    pub grid: Vec<Vec<bool>>,
+    /// Signed distance between the closest pair of robot parts at each
+    /// sample, if the grid has a clearance layer
+    pub clearance: Option<Vec<Vec<f32>>>,
+    /// Clearance in sim units a path must keep, zero to only avoid contact
+    pub safety_margin: f32,
+    pub min_angle: f32,
+    pub max_angle: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+    pub angle_step: f32,
+    pub height_step: f32,
+    /// Joint position of preset height zero
+    pub elevator_offset: f32,
+}
+
+impl JointGrid {
+    pub fn load_from_file(path: &str, robot: &RobotConfig) -> Result<Self, GridFileError> {
+        let file = GridFile::load_kind(path, GridKind::JointSpace)?;
+        file.check_geometry(robot.geometry_hash)?;
+
+        // Enforced limits block poses like collisions do, so paths avoid them
+        let mut grid = file.cells.clone();
+        if let (true, Some(rules)) = (
+            robot.rules.is_some_and(|rules| rules.enforce),
+            file.layer(LayerKind::Rules),
+        ) {
+            for (cells, breaks) in grid.iter_mut().zip(rules) {
+                for (cell, &breaks) in cells.iter_mut().zip(breaks) {
+                    *cell |= breaks;
+                }
             }
         }
 
         Ok(Self {
             grid,
-            min_x,
-            max_x,
-            min_y,
-            max_y,
-            step_size,
+            clearance: file.values(LayerKind::Clearance).cloned(),
+            safety_margin: 0.0,
+            min_angle: file.min_x,
+            max_angle: file.max_x,
+            min_height: file.min_y,
+            max_height: file.max_y,
+            angle_step: file.step_x,
+            height_step: file.step_y,
+            elevator_offset: robot.elevator_offset(),
         })
     }
+
+    /// Clearance of the whole robot at a pose, interpolated between the
+    /// samples. `None` outside the grid or without a clearance layer.
+    pub fn clearance_at(&self, pose: &ArmPosition) -> Option<f32> {
+        let mut angle = wrap_angle(pose.arm_angle).to_degrees();
+        if angle < self.min_angle {
+            angle += 360.0;
+        }
+        interpolate(
+            self.clearance.as_ref()?,
+            (angle - self.min_angle) / self.angle_step,
+            (pose.height + self.elevator_offset - self.min_height) / self.height_step,
+        )
+    }
+}
+
+/// Bilinear interpolation between values sampled at whole cell coordinates,
+/// `None` outside the samples. Next to an infinite sample, where nothing can
+/// collide, the smallest neighbouring value is used instead.
+fn interpolate(values: &[Vec<f32>], x: f32, y: f32) -> Option<f32> {
+    let (width, height) = (values.first()?.len(), values.len());
+    if !(0.0..=(width - 1) as f32).contains(&x) || !(0.0..=(height - 1) as f32).contains(&y) {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let corners = [
+        values[y0][x0],
+        values[y0][x1],
+        values[y1][x0],
+        values[y1][x1],
+    ];
+    if corners.iter().any(|value| !value.is_finite()) {
+        return corners.into_iter().reduce(f32::min);
+    }
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    Some(lerp(
+        lerp(corners[0], corners[1], tx),
+        lerp(corners[2], corners[3], tx),
+        ty,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(cells: Vec<Vec<bool>>) -> CollisionGrid {
+        let (width, height) = (cells[0].len(), cells.len());
+        CollisionGrid {
+            grid: cells,
+            rules: None,
+            clearance: None,
+            refinement: None,
+            safety_margin: 0.0,
+            min_x: -10.0,
+            max_x: -10.0 + (width - 1) as f32 * 2.0,
+            min_y: 5.0,
+            max_y: 5.0 + (height - 1) as f32 * 2.0,
+            step_size: 2.0,
+        }
+    }
+
+    /// Distance to the nearest colliding sample, checking every cell
+    fn brute_force(grid: &CollisionGrid, point: Vec2) -> Option<f32> {
+        let samples = grid.grid.iter().enumerate().flat_map(|(row, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .filter(|(_, &collides)| collides)
+                .map(move |(column, _)| (row, column))
+        });
+        samples
+            .map(|(row, column)| {
+                let sample = Vec2::new(
+                    grid.min_x + column as f32 * grid.step_size,
+                    grid.min_y + row as f32 * grid.step_size,
+                );
+                point.distance(sample)
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn distance_to_collision_finds_the_nearest_sample() {
+        let mut cells = vec![vec![false; 12]; 9];
+        cells[2][3] = true;
+        cells[7][10] = true;
+        cells[8][0] = true;
+        let grid = grid(cells);
+
+        // Inside the colliding cell, beside it, and outside the grid
+        assert_eq!(grid.distance_to_collision(Vec2::new(-3.5, 9.5)), Some(0.0));
+        let beside = Vec2::new(-4.0, 7.0);
+        assert!((grid.distance_to_collision(beside).unwrap() - 2.0).abs() < 1e-5);
+        for y in 0..30 {
+            for x in 0..40 {
+                let point = Vec2::new(-14.0 + x as f32 * 0.9, 1.0 + y as f32 * 0.8);
+                if grid.collides_at(point) == Some(true) {
+                    continue;
+                }
+                let expected = brute_force(&grid, point).unwrap();
+                let distance = grid.distance_to_collision(point).unwrap();
+                assert!(
+                    (distance - expected).abs() < 1e-4,
+                    "{} from {:?}, expected {}",
+                    distance,
+                    point,
+                    expected
+                );
+            }
+        }
+
+        assert_eq!(
+            self::grid(vec![vec![false; 4]; 3]).distance_to_collision(Vec2::ZERO),
+            None
+        );
+    }
+
+    #[test]
+    fn distance_to_collision_prefers_the_clearance_layer() {
+        let mut grid = grid(vec![vec![false, false], vec![false, true]]);
+        grid.clearance = Some(vec![vec![3.0, 2.0], vec![2.0, -1.0]]);
+        assert_eq!(grid.distance_to_collision(Vec2::new(-10.0, 5.0)), Some(3.0));
+        assert_eq!(grid.distance_to_collision(Vec2::new(-9.0, 5.0)), Some(2.5));
+        // Outside the layer it falls back to the samples
+        assert!((grid.distance_to_collision(Vec2::new(-8.0, 9.0)).unwrap() - 2.0).abs() < 1e-5);
+
+        grid.clearance = Some(vec![vec![f32::INFINITY; 2]; 2]);
+        assert_eq!(grid.distance_to_collision(Vec2::new(-9.0, 6.0)), None);
+    }
 }
@@ -0,0 +1,319 @@
+//! Control primitives matching WPILib's `PIDController`, `ElevatorFeedforward`,
+//! `ArmFeedforward` and `TrapezoidProfile`, so gains tuned here carry over to
+//! the robot code unchanged.
+
+use crate::simulations::main::planner::wrap_angle;
+
+/// Robot code loop period in seconds
+pub const LOOP_PERIOD: f32 = 0.02;
+const MAX_VOLTAGE: f32 = 12.0;
+
+pub struct PidController {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    period: f32,
+    /// Wrap the error to (-PI, PI] for angles
+    continuous: bool,
+    integral: f32,
+    previous_error: Option<f32>,
+}
+
+impl PidController {
+    pub fn new(kp: f32, ki: f32, kd: f32, period: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            period,
+            continuous: false,
+            integral: 0.0,
+            previous_error: None,
+        }
+    }
+
+    /// Treats the input as an angle in radians, so the error takes the short
+    /// way around
+    pub fn enable_continuous_input(&mut self) {
+        self.continuous = true;
+    }
+
+    pub fn calculate(&mut self, measurement: f32, setpoint: f32) -> f32 {
+        let mut error = setpoint - measurement;
+        if self.continuous {
+            error = wrap_angle(error);
+        }
+
+        self.integral += error * self.period;
+        if self.ki != 0.0 {
+            // Keep the integral term from winding up past the supply voltage
+            let limit = MAX_VOLTAGE / self.ki.abs();
+            self.integral = self.integral.clamp(-limit, limit);
+        }
+        let derivative = self
+            .previous_error
+            .map_or(0.0, |previous| (error - previous) / self.period);
+        self.previous_error = Some(error);
+
+        self.kp * error + self.ki * self.integral + self.kd * derivative
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.previous_error = None;
+    }
+}
+
+/// Volts from velocity (m/s) and acceleration (m/s^2) plus a constant
+/// gravity term
+#[derive(Debug, Clone, Copy)]
+pub struct ElevatorFeedforward {
+    pub ks: f32,
+    pub kg: f32,
+    pub kv: f32,
+    pub ka: f32,
+}
+
+impl ElevatorFeedforward {
+    pub fn calculate(&self, velocity: f32, acceleration: f32) -> f32 {
+        self.ks * sign(velocity) + self.kg + self.kv * velocity + self.ka * acceleration
+    }
+}
+
+/// Volts from angular velocity and acceleration, with gravity scaled by the
+/// cosine of the arm angle (0 is horizontal)
+#[derive(Debug, Clone, Copy)]
+pub struct ArmFeedforward {
+    pub ks: f32,
+    pub kg: f32,
+    pub kv: f32,
+    pub ka: f32,
+}
+
+impl ArmFeedforward {
+    pub fn calculate(&self, position: f32, velocity: f32, acceleration: f32) -> f32 {
+        self.ks * sign(velocity)
+            + self.kg * position.cos()
+            + self.kv * velocity
+            + self.ka * acceleration
+    }
+}
+
+/// Like `signum`, but zero for zero so kS doesn't push a stopped mechanism
+fn sign(value: f32) -> f32 {
+    if value > 0.0 {
+        1.0
+    } else if value < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct State {
+    pub position: f32,
+    pub velocity: f32,
+}
+
+/// Velocity and acceleration limited motion towards a goal
+#[derive(Debug, Clone, Copy)]
+pub struct TrapezoidProfile {
+    pub max_velocity: f32,
+    pub max_acceleration: f32,
+}
+
+impl TrapezoidProfile {
+    /// Seconds to move `distance` from rest to rest
+    pub fn total_time(&self, distance: f32) -> f32 {
+        let acceleration_time = self.max_velocity / self.max_acceleration;
+        let full_speed_dist = distance - acceleration_time * self.max_velocity;
+        if full_speed_dist < 0.0 {
+            // Triangular, accelerating for half the distance
+            2.0 * (distance / self.max_acceleration).sqrt()
+        } else {
+            2.0 * acceleration_time + full_speed_dist / self.max_velocity
+        }
+    }
+
+    /// State `t` seconds after `current` on the way to `goal`
+    pub fn calculate(&self, t: f32, current: State, goal: State) -> State {
+        // Work in the positive direction and flip the result back
+        let direction = if current.position > goal.position {
+            -1.0
+        } else {
+            1.0
+        };
+        let flip = |state: State| State {
+            position: state.position * direction,
+            velocity: state.velocity * direction,
+        };
+        let mut current = flip(current);
+        let goal = flip(goal);
+        current.velocity = current.velocity.min(self.max_velocity);
+
+        let (max_v, max_a) = (self.max_velocity, self.max_acceleration);
+
+        // Extend the profile back to zero velocity at both ends
+        let cutoff_begin = current.velocity / max_a;
+        let cutoff_dist_begin = cutoff_begin * cutoff_begin * max_a / 2.0;
+        let cutoff_end = goal.velocity / max_a;
+        let cutoff_dist_end = cutoff_end * cutoff_end * max_a / 2.0;
+
+        let full_trapezoid_dist =
+            cutoff_dist_begin + (goal.position - current.position) + cutoff_dist_end;
+        let mut acceleration_time = max_v / max_a;
+        let mut full_speed_dist =
+            full_trapezoid_dist - acceleration_time * acceleration_time * max_a;
+
+        // Triangular profile when there's no room to reach full speed
+        if full_speed_dist < 0.0 {
+            acceleration_time = (full_trapezoid_dist / max_a).max(0.0).sqrt();
+            full_speed_dist = 0.0;
+        }
+
+        let end_accel = acceleration_time - cutoff_begin;
+        let end_full_speed = end_accel + full_speed_dist / max_v;
+        let end_decel = end_full_speed + acceleration_time - cutoff_end;
+
+        let mut result = current;
+        if t < end_accel {
+            result.velocity += t * max_a;
+            result.position += (current.velocity + t * max_a / 2.0) * t;
+        } else if t < end_full_speed {
+            result.velocity = max_v;
+            result.position +=
+                (current.velocity + end_accel * max_a / 2.0) * end_accel + max_v * (t - end_accel);
+        } else if t <= end_decel {
+            let time_left = end_decel - t;
+            result.velocity = goal.velocity + time_left * max_a;
+            result.position = goal.position - (goal.velocity + time_left * max_a / 2.0) * time_left;
+        } else {
+            result = goal;
+        }
+
+        flip(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn pid_wraps_continuous_error() {
+        // WPILib PIDControllerTest: 3 to -3 radians is 2PI - 6 the short way
+        let mut pid = PidController::new(1.0, 0.0, 0.0, LOOP_PERIOD);
+        pid.enable_continuous_input();
+        assert_close(pid.calculate(3.0, -3.0), 2.0 * PI - 6.0);
+        assert_close(pid.calculate(-3.0, 3.0), 6.0 - 2.0 * PI);
+
+        let mut pid = PidController::new(1.0, 0.0, 0.0, LOOP_PERIOD);
+        assert_close(pid.calculate(3.0, -3.0), -6.0);
+    }
+
+    #[test]
+    fn pid_integrates_and_differentiates_over_the_period() {
+        let mut pid = PidController::new(0.0, 0.5, 0.0, LOOP_PERIOD);
+        for _ in 0..3 {
+            pid.calculate(0.0, 1.0);
+        }
+        assert_close(pid.calculate(0.0, 1.0), 0.5 * 4.0 * LOOP_PERIOD);
+        pid.reset();
+        assert_close(pid.calculate(0.0, 1.0), 0.5 * LOOP_PERIOD);
+
+        let mut pid = PidController::new(0.0, 0.0, 0.1, LOOP_PERIOD);
+        pid.calculate(0.0, 1.0);
+        assert_close(pid.calculate(0.5, 1.0), 0.1 * -0.5 / LOOP_PERIOD);
+    }
+
+    #[test]
+    fn elevator_feedforward_matches_wpilib() {
+        // WPILib ElevatorFeedforwardTest
+        let feedforward = ElevatorFeedforward {
+            ks: 0.5,
+            kg: 1.0,
+            kv: 1.5,
+            ka: 2.0,
+        };
+        assert_close(feedforward.calculate(0.0, 0.0), 1.0);
+        assert_close(feedforward.calculate(2.0, 0.0), 4.5);
+        assert_close(feedforward.calculate(2.0, 1.0), 6.5);
+        assert_close(feedforward.calculate(-2.0, 1.0), -0.5);
+    }
+
+    #[test]
+    fn arm_feedforward_matches_wpilib() {
+        // WPILib ArmFeedforwardTest
+        let feedforward = ArmFeedforward {
+            ks: 0.5,
+            kg: 1.0,
+            kv: 1.5,
+            ka: 2.0,
+        };
+        assert_close(feedforward.calculate(PI / 3.0, 0.0, 0.0), 0.5);
+        assert_close(feedforward.calculate(PI / 3.0, 1.0, 0.0), 2.5);
+        assert_close(feedforward.calculate(PI / 3.0, 1.0, 2.0), 6.5);
+        assert_close(feedforward.calculate(PI / 3.0, -1.0, 2.0), 2.5);
+    }
+
+    #[test]
+    fn trapezoid_profile_follows_the_trapezoid() {
+        let profile = TrapezoidProfile {
+            max_velocity: 1.0,
+            max_acceleration: 1.0,
+        };
+        let rest = |position| State {
+            position,
+            velocity: 0.0,
+        };
+        assert_close(profile.total_time(3.0), 4.0);
+
+        for (t, position, velocity) in [
+            (0.5, 0.125, 0.5),
+            (2.0, 1.5, 1.0),
+            (3.5, 2.875, 0.5),
+            (5.0, 3.0, 0.0),
+        ] {
+            let state = profile.calculate(t, rest(0.0), rest(3.0));
+            assert_close(state.position, position);
+            assert_close(state.velocity, velocity);
+        }
+
+        let state = profile.calculate(0.5, rest(3.0), rest(0.0));
+        assert_close(state.position, 2.875);
+        assert_close(state.velocity, -0.5);
+    }
+
+    #[test]
+    fn trapezoid_profile_reaches_goal() {
+        // WPILib TrapezoidProfileTest: triangular when there's no room to
+        // reach full speed, stepped 10 ms at a time from the last state
+        let profile = TrapezoidProfile {
+            max_velocity: 1.75,
+            max_acceleration: 0.75,
+        };
+        let goal = State {
+            position: 3.0,
+            velocity: 0.0,
+        };
+        assert_close(profile.total_time(goal.position), 4.0);
+
+        let mut state = State::default();
+        for _ in 0..450 {
+            state = profile.calculate(0.01, state, goal);
+            assert!(state.velocity <= profile.max_velocity + 1e-4);
+        }
+        assert_eq!(state, goal);
+    }
+}
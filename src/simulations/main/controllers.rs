@@ -0,0 +1,323 @@
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::simulations::main::code_control::current_arm_position;
+use crate::simulations::main::components::*;
+use crate::simulations::main::control::{
+    ArmFeedforward, ElevatorFeedforward, PidController, State, TrapezoidProfile, LOOP_PERIOD,
+};
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::motors::JointMotor;
+use crate::simulations::main::planner::wrap_angle;
+use crate::simulations::robot::{RobotConfig, SIM_UNITS_PER_METER};
+
+pub const CONTROLLERS_FILE: &str = "controllers.ron";
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Gains for one joint in WPILib units: meters for the elevator, radians for
+/// the arm, volts for the output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JointGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub ks: f32,
+    pub kg: f32,
+    pub kv: f32,
+    pub ka: f32,
+    pub max_velocity: f32,
+    pub max_acceleration: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct GainsFile {
+    elevator: JointGains,
+    arm: JointGains,
+}
+
+impl Default for GainsFile {
+    fn default() -> Self {
+        Self {
+            elevator: JointGains {
+                kp: 60.0,
+                ki: 0.0,
+                kd: 0.0,
+                ks: 0.05,
+                kg: 0.13,
+                kv: 4.3,
+                ka: 0.03,
+                max_velocity: 1.5,
+                max_acceleration: 6.0,
+            },
+            arm: JointGains {
+                kp: 12.0,
+                ki: 0.0,
+                kd: 0.2,
+                ks: 0.05,
+                kg: 1.35,
+                kv: 1.2,
+                ka: 0.02,
+                max_velocity: 6.0,
+                max_acceleration: 20.0,
+            },
+        }
+    }
+}
+
+/// Profiled PID plus feedforward for one joint, like WPILib's
+/// `ProfiledPIDController` paired with a feedforward
+pub struct JointController {
+    pub gains: JointGains,
+    pid: PidController,
+    /// Profile state commanded on the previous loop, `None` until enabled
+    setpoint: Option<State>,
+}
+
+impl JointController {
+    fn new(gains: JointGains) -> Self {
+        Self {
+            gains,
+            pid: PidController::new(gains.kp, gains.ki, gains.kd, LOOP_PERIOD),
+            setpoint: None,
+        }
+    }
+
+    fn set_gains(&mut self, gains: JointGains) {
+        self.gains = gains;
+        self.pid.kp = gains.kp;
+        self.pid.ki = gains.ki;
+        self.pid.kd = gains.kd;
+    }
+
+    fn reset(&mut self) {
+        self.setpoint = None;
+        self.pid.reset();
+    }
+
+    /// Profile position commanded on the last loop, `None` while disabled
+    pub fn setpoint(&self) -> Option<f32> {
+        self.setpoint.map(|state| state.position)
+    }
+
+    pub fn profile(&self) -> TrapezoidProfile {
+        TrapezoidProfile {
+            max_velocity: self.gains.max_velocity,
+            max_acceleration: self.gains.max_acceleration,
+        }
+    }
+
+    /// Advances the profile one loop towards `goal` and returns the next
+    /// setpoint and its acceleration. The profile starts from the measured
+    /// position after a reset.
+    fn step(&mut self, measurement: f32, goal: f32) -> (State, f32) {
+        let setpoint = *self.setpoint.get_or_insert(State {
+            position: measurement,
+            velocity: 0.0,
+        });
+        let next = self.profile().calculate(
+            LOOP_PERIOD,
+            setpoint,
+            State {
+                position: goal,
+                velocity: 0.0,
+            },
+        );
+        self.setpoint = Some(next);
+        (next, (next.velocity - setpoint.velocity) / LOOP_PERIOD)
+    }
+
+    fn elevator_voltage(&mut self, measurement: f32, goal: f32) -> f32 {
+        let (next, acceleration) = self.step(measurement, goal);
+        let feedforward = ElevatorFeedforward {
+            ks: self.gains.ks,
+            kg: self.gains.kg,
+            kv: self.gains.kv,
+            ka: self.gains.ka,
+        };
+        self.pid.calculate(measurement, next.position)
+            + feedforward.calculate(next.velocity, acceleration)
+    }
+
+    fn arm_voltage(&mut self, measurement: f32, goal: f32) -> f32 {
+        // Keep the goal within half a turn of the setpoint so the profile
+        // takes the short way around
+        let reference = self.setpoint.map_or(measurement, |state| state.position);
+        let goal = reference + wrap_angle(goal - reference);
+        let (next, acceleration) = self.step(measurement, goal);
+        let feedforward = ArmFeedforward {
+            ks: self.gains.ks,
+            kg: self.gains.kg,
+            kv: self.gains.kv,
+            ka: self.gains.ka,
+        };
+        self.pid.calculate(measurement, next.position)
+            + feedforward.calculate(next.position, next.velocity, acceleration)
+    }
+}
+
+/// Elevator and arm controllers run by the 50 Hz control loop
+#[derive(Resource)]
+pub struct ArmControllers {
+    pub path: String,
+    modified: Option<SystemTime>,
+    pub elevator: JointController,
+    pub arm: JointController,
+    /// Pose the loop drives towards, set by code control or the cursor
+    pub goal: Option<ArmPosition>,
+}
+
+impl ArmControllers {
+    /// Loads gains from disk, falling back to built-in defaults if the file
+    /// is missing or invalid
+    pub fn load_or_default(path: &str) -> Self {
+        match Self::load(path) {
+            Ok(controllers) => {
+                println!("Loaded controller gains from {}", path);
+                controllers
+            }
+            Err(e) => {
+                println!("Failed to load controller gains from {}: {}", path, e);
+                Self::new(path, GainsFile::default(), None)
+            }
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let (gains, modified) = load_gains(path)?;
+        Ok(Self::new(path, gains, modified))
+    }
+
+    fn new(path: &str, gains: GainsFile, modified: Option<SystemTime>) -> Self {
+        let mut arm = JointController::new(gains.arm);
+        arm.pid.enable_continuous_input();
+        Self {
+            path: path.to_string(),
+            modified,
+            elevator: JointController::new(gains.elevator),
+            arm,
+            goal: None,
+        }
+    }
+}
+
+fn load_gains(path: &str) -> Result<(GainsFile, Option<SystemTime>), String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let gains: GainsFile = ron::from_str(&contents).map_err(|e| e.to_string())?;
+    for (name, joint) in [("elevator", &gains.elevator), ("arm", &gains.arm)] {
+        if joint.max_velocity <= 0.0 || joint.max_acceleration <= 0.0 {
+            return Err(format!("{} profile limits must be positive", name));
+        }
+    }
+    Ok((gains, modified))
+}
+
+/// Motor voltages commanded by robot code outside the sim, over
+/// NetworkTables or HALSim. A joint with a voltage here skips our controller.
+#[derive(Resource, Default)]
+pub struct ExternalVoltages {
+    pub elevator: Option<f32>,
+    pub arm: Option<f32>,
+}
+
+/// The robot control loop. Runs on the fixed 50 Hz schedule and writes motor
+/// voltages, which are held until the next loop like on the real robot.
+pub fn run_controllers(
+    control_mode: Res<ControlMode>,
+    mut controllers: ResMut<ArmControllers>,
+    transforms: Query<&Transform>,
+    motor_joints: Option<Res<MotorJoints>>,
+    robot: Res<RobotConfig>,
+    external: Res<ExternalVoltages>,
+    mut motors: Query<&mut JointMotor>,
+) {
+    let Some(motor_joints) = motor_joints else {
+        return;
+    };
+    let Some(current) = current_arm_position(&transforms, &motor_joints, &robot) else {
+        return;
+    };
+
+    // The cursor takes over from robot code
+    let external = match *control_mode {
+        ControlMode::CodeControl => (external.elevator, external.arm),
+        ControlMode::CursorFollow => (None, None),
+    };
+    let goal = controllers.goal;
+    let elevator_voltage = match (external.0, goal) {
+        (Some(voltage), _) => {
+            controllers.elevator.reset();
+            Some(voltage)
+        }
+        (None, Some(goal)) => Some(controllers.elevator.elevator_voltage(
+            current.height / SIM_UNITS_PER_METER,
+            goal.height / SIM_UNITS_PER_METER,
+        )),
+        (None, None) => {
+            controllers.elevator.reset();
+            None
+        }
+    };
+    let arm_voltage = match (external.1, goal) {
+        (Some(voltage), _) => {
+            controllers.arm.reset();
+            Some(voltage)
+        }
+        (None, Some(goal)) => Some(
+            controllers
+                .arm
+                .arm_voltage(current.arm_angle, goal.arm_angle),
+        ),
+        (None, None) => {
+            controllers.arm.reset();
+            None
+        }
+    };
+
+    for (joint, voltage) in [
+        (motor_joints.elevator, elevator_voltage),
+        (motor_joints.arm, arm_voltage),
+    ] {
+        if let (Ok(mut motor), Some(voltage)) = (motors.get_mut(joint), voltage) {
+            motor.enabled = true;
+            motor.voltage = voltage;
+        }
+    }
+}
+
+/// Polls the gains file and applies changes without resetting the
+/// controllers, so gains can be tuned while the arm moves
+pub fn reload_controller_gains(
+    time: Res<Time>,
+    mut elapsed: Local<Duration>,
+    mut controllers: ResMut<ArmControllers>,
+) {
+    *elapsed += time.delta();
+    if *elapsed < RELOAD_INTERVAL {
+        return;
+    }
+    *elapsed = Duration::ZERO;
+
+    let modified = fs::metadata(&controllers.path)
+        .and_then(|m| m.modified())
+        .ok();
+    if modified.is_none() || modified == controllers.modified {
+        return;
+    }
+    controllers.modified = modified;
+
+    match load_gains(&controllers.path) {
+        Ok((gains, _)) => {
+            println!(
+                "Reloaded controller gains: elevator {:?}, arm {:?}",
+                gains.elevator, gains.arm
+            );
+            controllers.elevator.set_gains(gains.elevator);
+            controllers.arm.set_gains(gains.arm);
+        }
+        Err(e) => println!("Failed to reload controller gains, keeping previous: {}", e),
+    }
+}
@@ -0,0 +1,275 @@
+use bevy::prelude::*;
+use bevy_rapier2d::parry::query;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::math::{Isometry, Vector};
+
+use crate::simulations::main::components::IntakeMarker;
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::robot::{
+    groups_interact, LinkPose, LinkShape, RobotConfig, ELEVATOR, GAME_PIECE, INTAKE,
+    SIM_UNITS_PER_INCH,
+};
+
+// 2025 game manual dimensions, in inches and kilograms
+const CORAL_LENGTH: f32 = 11.875;
+const CORAL_DIAMETER: f32 = 4.5;
+const CORAL_MASS: f32 = 0.68; // About 1.5 lb
+const ALGAE_DIAMETER: f32 = 16.25;
+const ALGAE_MASS: f32 = 0.68; // About 1.5 lb
+
+/// Pieces are dropped from this far (inches) above their hold pose, as if a
+/// human player fed them to the intake
+const FEED_GAP: f32 = 3.0;
+/// Sim units per second the rollers throw a piece out at
+const EJECT_SPEED: f32 = 150.0;
+/// Pieces that fall this far below the robot (sim units) are removed
+const LOST_HEIGHT: f32 = -1000.0;
+
+const SPAWN_CORAL_KEY: KeyCode = KeyCode::KeyC;
+const SPAWN_ALGAE_KEY: KeyCode = KeyCode::KeyA;
+const ROLLERS_KEY: KeyCode = KeyCode::KeyI;
+const EJECT_KEY: KeyCode = KeyCode::KeyO;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamePieceKind {
+    /// PVC pipe, a capsule seen from the side
+    Coral,
+    /// Inflated ball
+    Algae,
+}
+
+impl GamePieceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Coral => "coral",
+            Self::Algae => "algae",
+        }
+    }
+
+    pub fn collider(self) -> Collider {
+        let radius = self.radius();
+        match self {
+            Self::Coral => {
+                Collider::capsule_x((CORAL_LENGTH * SIM_UNITS_PER_INCH) / 2.0 - radius, radius)
+            }
+            Self::Algae => Collider::ball(radius),
+        }
+    }
+
+    fn radius(self) -> f32 {
+        let diameter = match self {
+            Self::Coral => CORAL_DIAMETER,
+            Self::Algae => ALGAE_DIAMETER,
+        };
+        diameter * SIM_UNITS_PER_INCH / 2.0
+    }
+
+    fn mass(self) -> f32 {
+        match self {
+            Self::Coral => CORAL_MASS,
+            Self::Algae => ALGAE_MASS,
+        }
+    }
+
+    /// Where the rollers hold the piece in the intake frame. The mouth is the
+    /// intake's +x end: coral sticks halfway out of it along the rollers and
+    /// algae sits against it.
+    pub fn hold_offset(self, robot: &RobotConfig) -> Vec2 {
+        let mouth = match robot.intake().shape {
+            LinkShape::Cuboid { half_extents } => half_extents.x,
+            LinkShape::Ball { radius } => radius,
+        };
+        match self {
+            Self::Coral => Vec2::new(mouth, 0.0),
+            Self::Algae => Vec2::new(mouth + self.radius(), 0.0),
+        }
+    }
+
+    /// World pose of the piece while the intake at `intake` holds it
+    pub fn held_pose(self, robot: &RobotConfig, intake: &LinkPose) -> Isometry<f32> {
+        let center = intake.transform_point(self.hold_offset(robot));
+        Isometry::new(Vector::new(center.x, center.y), intake.rotation)
+    }
+
+    /// Whether the piece, held by the intake at `pose`, overlaps any link or
+    /// chassis part it collides with. The intake itself is skipped since it
+    /// carries the piece.
+    pub fn collides_at(self, robot: &RobotConfig, pose: &ArmPosition) -> bool {
+        let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+        let piece_pose = self.held_pose(robot, &poses[robot.intake_index()]);
+        let piece = self.collider();
+        let groups = piece_groups();
+
+        let links = robot
+            .links
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != robot.intake_index())
+            .map(|(i, link)| (link.shape, link.groups, poses[i]));
+        let chassis = robot
+            .chassis_parts()
+            .iter()
+            .map(|part| (part.shape, part.groups, part.pose));
+        links.chain(chassis).any(|(shape, other, pose)| {
+            groups_interact(groups, other)
+                && query::intersection_test(
+                    &piece_pose,
+                    &*piece.raw,
+                    &pose.isometry(),
+                    &*shape.collider().raw,
+                )
+                .unwrap_or(false)
+        })
+    }
+}
+
+pub fn piece_groups() -> CollisionGroups {
+    CollisionGroups::new(GAME_PIECE, ELEVATOR | INTAKE | GAME_PIECE)
+}
+
+#[derive(Component)]
+pub struct GamePiece(pub GamePieceKind);
+
+/// Intake roller state. While running they grab the first piece that touches
+/// the intake and hold it with a fixed joint until it is ejected.
+#[derive(Resource, Default)]
+pub struct IntakeRollers {
+    pub running: bool,
+    held: Option<(Entity, GamePieceKind)>,
+}
+
+impl IntakeRollers {
+    pub fn held(&self) -> Option<GamePieceKind> {
+        self.held.map(|(_, kind)| kind)
+    }
+}
+
+/// Drops a piece onto the intake's mouth
+pub fn spawn_game_pieces(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    intake: Query<&Transform, With<IntakeMarker>>,
+    robot: Res<RobotConfig>,
+) {
+    let kind = if keys.just_pressed(SPAWN_CORAL_KEY) {
+        GamePieceKind::Coral
+    } else if keys.just_pressed(SPAWN_ALGAE_KEY) {
+        GamePieceKind::Algae
+    } else {
+        return;
+    };
+    let Ok(intake) = intake.get_single() else {
+        return;
+    };
+
+    let hold = intake.transform_point(kind.hold_offset(&robot).extend(0.0));
+    let translation = hold + Vec3::Y * (kind.radius() + FEED_GAP * SIM_UNITS_PER_INCH);
+    commands.spawn((
+        GamePiece(kind),
+        Name::new(kind.name()),
+        kind.collider(),
+        ColliderMassProperties::Mass(kind.mass()),
+        Transform::from_translation(translation),
+        RigidBody::Dynamic,
+        Sleeping::disabled(),
+        Velocity::default(),
+        ExternalImpulse::default(),
+        piece_groups(),
+        ActiveEvents::COLLISION_EVENTS,
+    ));
+    println!("Spawned {}", kind.name());
+}
+
+/// Toggles the rollers, grabs a touching piece while they run and ejects the
+/// held piece on command
+pub fn run_intake_rollers(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut rollers: ResMut<IntakeRollers>,
+    rapier_context: ReadDefaultRapierContext,
+    intake: Query<(Entity, &Transform), With<IntakeMarker>>,
+    mut pieces: Query<(&GamePiece, &mut Transform, &mut ExternalImpulse), Without<IntakeMarker>>,
+    robot: Res<RobotConfig>,
+) {
+    let Ok((intake, intake_transform)) = intake.get_single() else {
+        return;
+    };
+
+    // A held piece that was despawned is no longer held
+    if rollers
+        .held
+        .is_some_and(|(piece, _)| pieces.get(piece).is_err())
+    {
+        rollers.held = None;
+    }
+
+    if keys.just_pressed(ROLLERS_KEY) {
+        rollers.running = !rollers.running;
+        println!(
+            "Intake rollers {}",
+            if rollers.running { "on" } else { "off" }
+        );
+    }
+
+    if keys.just_pressed(EJECT_KEY) {
+        // Stop the rollers too, or they would grab the piece straight back
+        rollers.running = false;
+        if let Some((piece, kind)) = rollers.held.take() {
+            commands.entity(piece).remove::<ImpulseJoint>();
+            if let Ok((_, _, mut impulse)) = pieces.get_mut(piece) {
+                let direction = (intake_transform.rotation * Vec3::X).truncate();
+                impulse.impulse = direction * EJECT_SPEED * kind.mass();
+            }
+            println!("Ejected {}", kind.name());
+        }
+        return;
+    }
+
+    if !rollers.running || rollers.held.is_some() {
+        return;
+    }
+    let touching = rapier_context
+        .contact_pairs_with(intake)
+        .filter(|pair| pair.has_any_active_contact())
+        .map(|pair| {
+            if pair.collider1() == intake {
+                pair.collider2()
+            } else {
+                pair.collider1()
+            }
+        })
+        .find(|&other| pieces.contains(other));
+    let Some(piece) = touching else {
+        return;
+    };
+    let Ok((&GamePiece(kind), mut transform, _)) = pieces.get_mut(piece) else {
+        return;
+    };
+
+    // Snap the piece into the hold pose so the joint doesn't yank it there
+    let offset = kind.hold_offset(&robot);
+    *transform = Transform::from_translation(intake_transform.transform_point(offset.extend(0.0)))
+        .with_rotation(intake_transform.rotation);
+    let mut joint = FixedJointBuilder::new()
+        .local_anchor1(offset)
+        .local_anchor2(Vec2::ZERO)
+        .build();
+    joint.set_contacts_enabled(false);
+    commands
+        .entity(piece)
+        .insert(ImpulseJoint::new(intake, joint));
+    rollers.held = Some((piece, kind));
+    println!("Intake holding {}", kind.name());
+}
+
+/// Removes pieces that fell off the robot
+pub fn despawn_lost_pieces(
+    mut commands: Commands,
+    pieces: Query<(Entity, &Transform), With<GamePiece>>,
+) {
+    for (entity, transform) in pieces.iter() {
+        if transform.translation.y < LOST_HEIGHT {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Client for WPILib's HALSim WebSocket extension, so robot code running
+//! under `./gradlew simulateJava` with `halsim_ws_server` can drive the sim
+//! as its physical plant. Motor outputs are read from PWM or CAN motor
+//! devices and joint positions are written back as encoder readings.
+//!
+//! Protocol: https://github.com/wpilibsuite/allwpilib/blob/main/simulation/halsim_ws_core/doc/hardware_ws_api.md
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Map};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::Message;
+
+use crate::simulations::main::controllers::ExternalVoltages;
+use crate::simulations::main::motors::Battery;
+use crate::simulations::main::telemetry::SimState;
+use crate::simulations::robot::RobotConfig;
+
+pub const HALSIM_FILE: &str = "halsim.ron";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// Encoder period reported for a stopped joint, like a stalled real encoder
+const STOPPED_PERIOD: f64 = f64::MAX;
+
+/// Motor controller the robot code drives a joint with
+#[derive(Debug, Clone, Deserialize)]
+pub enum HalSimOutput {
+    /// PWM channel, reading `<speed`
+    Pwm(u32),
+    /// CAN motor device name, reading `<percentOutput`
+    CanMotor(String),
+}
+
+/// Sensor the sim writes the joint position to. Positions are meters above
+/// the elevator's lowest point or radians from horizontal, multiplied by the
+/// scale.
+#[derive(Debug, Clone, Deserialize)]
+pub enum HalSimSensor {
+    /// Quadrature encoder by index (the order the robot code creates them),
+    /// writing `>count` and `>period`
+    Encoder { index: u32, counts_per_unit: f32 },
+    /// CAN encoder device name, writing `>position` and `>velocity`
+    CanEncoder { device: String, scale: f32 },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HalSimJoint {
+    /// "elevator" or "arm", matching the robot description
+    pub joint: String,
+    pub output: HalSimOutput,
+    #[serde(default)]
+    pub sensor: Option<HalSimSensor>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HalSimConfig {
+    pub url: String,
+    pub joints: Vec<HalSimJoint>,
+}
+
+impl HalSimConfig {
+    pub fn load(path: &str, robot: &RobotConfig) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&contents, robot)
+    }
+
+    /// Reads the config from RON and checks each joint is mapped once
+    pub fn parse(contents: &str, robot: &RobotConfig) -> Result<Self, String> {
+        let config: Self = ron::from_str(contents).map_err(|e| e.to_string())?;
+
+        let joint_names = [
+            robot.elevator_joint().name.as_str(),
+            robot.arm_joint().name.as_str(),
+        ];
+        for joint in &config.joints {
+            if !joint_names.contains(&joint.joint.as_str()) {
+                return Err(format!(
+                    "unknown joint {}, expected one of {:?}",
+                    joint.joint, joint_names
+                ));
+            }
+            if config
+                .joints
+                .iter()
+                .filter(|j| j.joint == joint.joint)
+                .count()
+                > 1
+            {
+                return Err(format!("joint {} is mapped more than once", joint.joint));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Connection state shared with the client thread
+#[derive(Default)]
+struct Shared {
+    /// Latest value of each `(type, device, field)` sent by the robot code
+    values: HashMap<(String, String, String), f64>,
+    /// Queue to the socket while connected
+    outbox: Option<Sender<String>>,
+    /// Bumped on every connection so the sim resends all sensor values
+    connection: u64,
+}
+
+/// Connection to the robot code's HALSim server. Reconnects in the
+/// background, so the robot code can be started before or after the sim.
+#[derive(Resource)]
+pub struct HalSim {
+    config: HalSimConfig,
+    shared: Arc<Mutex<Shared>>,
+    /// Sensor messages sent on the current connection, to skip repeats
+    sent: HashMap<String, String>,
+    connection: u64,
+}
+
+impl HalSim {
+    pub fn start(config: HalSimConfig) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let url = config.url.clone();
+        let thread_shared = shared.clone();
+        thread::spawn(move || loop {
+            match run_connection(&url, &thread_shared) {
+                Ok(()) => println!("HALSim connection to {} closed", url),
+                // Refused connections just mean the robot code isn't up yet
+                Err(e) if e.contains("refused") => {}
+                Err(e) => println!("HALSim connection to {} failed: {}", url, e),
+            }
+            let mut shared = lock(&thread_shared);
+            shared.outbox = None;
+            shared.values.clear();
+            drop(shared);
+            thread::sleep(RECONNECT_INTERVAL);
+        });
+
+        Self {
+            config,
+            shared,
+            sent: HashMap::new(),
+            connection: 0,
+        }
+    }
+
+    fn value(&self, kind: &str, device: &str, field: &str) -> Option<f64> {
+        lock(&self.shared)
+            .values
+            .get(&(kind.to_string(), device.to_string(), field.to_string()))
+            .copied()
+    }
+
+    fn connected(&self) -> bool {
+        lock(&self.shared).outbox.is_some()
+    }
+
+    /// Motor output as a fraction of the battery voltage
+    fn output(&self, output: &HalSimOutput) -> Option<f64> {
+        match output {
+            HalSimOutput::Pwm(channel) => self.value("PWM", &channel.to_string(), "<speed"),
+            HalSimOutput::CanMotor(device) => self.value("CANMotor", device, "<percentOutput"),
+        }
+    }
+
+    /// Queues a message unless the same one was already sent on this
+    /// connection
+    fn send(&mut self, kind: &str, device: &str, data: Map<String, serde_json::Value>) {
+        let shared = lock(&self.shared);
+        let Some(outbox) = &shared.outbox else {
+            return;
+        };
+        if shared.connection != self.connection {
+            self.connection = shared.connection;
+            self.sent.clear();
+        }
+
+        let message = json!({ "type": kind, "device": device, "data": data }).to_string();
+        let key = format!("{}/{}", kind, device);
+        if self.sent.get(&key) == Some(&message) {
+            return;
+        }
+        let _ = outbox.send(message.clone());
+        self.sent.insert(key, message);
+    }
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn run_connection(url: &str, shared: &Arc<Mutex<Shared>>) -> Result<(), String> {
+    let request = url.into_client_request().map_err(|e| e.to_string())?;
+    let host = request.uri().host().unwrap_or("localhost").to_string();
+    let port = request.uri().port_u16().unwrap_or(80);
+    let stream = TcpStream::connect((host.as_str(), port)).map_err(|e| e.to_string())?;
+    let (mut socket, _) = tungstenite::client(request, stream).map_err(|e| e.to_string())?;
+    socket
+        .get_ref()
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|e| e.to_string())?;
+
+    let (outbox, inbox): (Sender<String>, Receiver<String>) = mpsc::channel();
+    {
+        let mut shared = lock(shared);
+        shared.outbox = Some(outbox);
+        shared.connection += 1;
+    }
+    println!("Connected to HALSim at {}", url);
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if let Err(e) = store_values(text.as_str(), shared) {
+                    println!("HALSim: ignoring message: {}", e);
+                }
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                return Ok(())
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+
+        for message in inbox.try_iter() {
+            socket
+                .write(Message::text(message))
+                .map_err(|e| e.to_string())?;
+        }
+        socket.flush().map_err(|e| e.to_string())?;
+    }
+}
+
+/// Records the numeric fields of a `{"type", "device", "data"}` message
+fn store_values(text: &str, shared: &Mutex<Shared>) -> Result<(), String> {
+    let message: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let kind = message["type"].as_str().ok_or("missing type")?;
+    let device = message["device"].as_str().unwrap_or_default();
+    let data = message["data"].as_object().ok_or("missing data")?;
+
+    let mut shared = lock(shared);
+    for (field, value) in data {
+        let number = match value {
+            serde_json::Value::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            value => value.as_f64(),
+        };
+        if let Some(number) = number {
+            shared.values.insert(
+                (kind.to_string(), device.to_string(), field.clone()),
+                number,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Turns the robot code's motor outputs into joint voltages. Runs after the
+/// NetworkTables inputs, so HALSim wins while it's connected.
+pub fn read_halsim_outputs(
+    halsim: Option<Res<HalSim>>,
+    robot: Res<RobotConfig>,
+    battery: Res<Battery>,
+    mut external: ResMut<ExternalVoltages>,
+) {
+    let Some(halsim) = halsim else {
+        return;
+    };
+    if !halsim.connected() {
+        return;
+    }
+
+    for joint in &halsim.config.joints {
+        // Outputs are zero until the robot code sets them, like a disabled
+        // robot
+        let output = halsim.output(&joint.output).unwrap_or(0.0) as f32;
+        let voltage = Some(output.clamp(-1.0, 1.0) * battery.voltage);
+        if joint.joint == robot.elevator_joint().name {
+            external.elevator = voltage;
+        } else {
+            external.arm = voltage;
+        }
+    }
+}
+
+/// Writes joint positions and the battery voltage back to the robot code
+pub fn write_halsim_sensors(
+    halsim: Option<ResMut<HalSim>>,
+    robot: Res<RobotConfig>,
+    state: Res<SimState>,
+) {
+    let Some(mut halsim) = halsim else {
+        return;
+    };
+
+    let joints = halsim.config.joints.clone();
+    for joint in &joints {
+        // Same units as the NetworkTables topics: meters and radians
+        let joint_state = if joint.joint == robot.elevator_joint().name {
+            state.elevator
+        } else {
+            state.arm
+        };
+        let (position, velocity) = (joint_state.position, joint_state.velocity);
+
+        if let HalSimOutput::CanMotor(device) = &joint.output {
+            let mut data = Map::new();
+            data.insert(">busVoltage".to_string(), json!(state.battery_voltage));
+            halsim.send("CANMotor", device, data);
+        }
+
+        let mut data = Map::new();
+        match &joint.sensor {
+            Some(HalSimSensor::Encoder {
+                index,
+                counts_per_unit,
+            }) => {
+                let rate = (velocity * counts_per_unit).abs() as f64;
+                let period = if rate > 0.0 {
+                    1.0 / rate
+                } else {
+                    STOPPED_PERIOD
+                };
+                data.insert(
+                    ">count".to_string(),
+                    json!((position * counts_per_unit).round() as i64),
+                );
+                data.insert(">period".to_string(), json!(period));
+                halsim.send("Encoder", &index.to_string(), data);
+            }
+            Some(HalSimSensor::CanEncoder { device, scale }) => {
+                data.insert(">position".to_string(), json!(position * scale));
+                data.insert(">velocity".to_string(), json!(velocity * scale));
+                halsim.send("CANEncoder", device, data);
+            }
+            None => {}
+        }
+    }
+
+    let mut data = Map::new();
+    data.insert(">vin_voltage".to_string(), json!(state.battery_voltage));
+    halsim.send("RoboRIO", "", data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robot() -> RobotConfig {
+        RobotConfig::parse(include_str!("../../../robot.ron")).unwrap()
+    }
+
+    fn value(shared: &Mutex<Shared>, kind: &str, device: &str, field: &str) -> Option<f64> {
+        lock(shared)
+            .values
+            .get(&(kind.to_string(), device.to_string(), field.to_string()))
+            .copied()
+    }
+
+    #[test]
+    fn stores_numeric_and_bool_fields() {
+        let shared = Mutex::new(Shared::default());
+        store_values(
+            r#"{"type": "PWM", "device": "0", "data": {"<speed": -0.25, "<init": true}}"#,
+            &shared,
+        )
+        .unwrap();
+        store_values(
+            r#"{"type": "CANMotor", "device": "SPARK MAX [2]",
+                "data": {"<percentOutput": 0.5, "<brakeMode": false, "<name": "arm"}}"#,
+            &shared,
+        )
+        .unwrap();
+
+        assert_eq!(value(&shared, "PWM", "0", "<speed"), Some(-0.25));
+        assert_eq!(value(&shared, "PWM", "0", "<init"), Some(1.0));
+        let arm = |field| value(&shared, "CANMotor", "SPARK MAX [2]", field);
+        assert_eq!(arm("<percentOutput"), Some(0.5));
+        assert_eq!(arm("<brakeMode"), Some(0.0));
+        assert_eq!(arm("<name"), None);
+
+        // A later message updates only the fields it carries
+        store_values(
+            r#"{"type": "PWM", "device": "0", "data": {"<speed": 1}}"#,
+            &shared,
+        )
+        .unwrap();
+        assert_eq!(value(&shared, "PWM", "0", "<speed"), Some(1.0));
+        assert_eq!(value(&shared, "PWM", "0", "<init"), Some(1.0));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let shared = Mutex::new(Shared::default());
+        assert!(store_values("not json", &shared).is_err());
+        assert_eq!(
+            store_values(r#"{"device": "0", "data": {"<speed": 1}}"#, &shared),
+            Err("missing type".to_string())
+        );
+        assert_eq!(
+            store_values(r#"{"type": "PWM", "device": "0"}"#, &shared),
+            Err("missing data".to_string())
+        );
+        assert!(lock(&shared).values.is_empty());
+    }
+
+    #[test]
+    fn loads_the_default_config() {
+        let config = HalSimConfig::parse(include_str!("../../../halsim.ron"), &robot()).unwrap();
+        assert_eq!(config.joints.len(), 2);
+    }
+
+    #[test]
+    fn rejects_unknown_and_duplicate_joints() {
+        let robot = robot();
+        let config = |joints: &str| {
+            HalSimConfig::parse(
+                &format!(
+                    r#"(url: "ws://localhost:3300/wpilibws", joints: [{}])"#,
+                    joints
+                ),
+                &robot,
+            )
+        };
+
+        assert!(config(r#"(joint: "arm", output: Pwm(1))"#).is_ok());
+        let unknown = config(r#"(joint: "wrist", output: Pwm(1))"#).unwrap_err();
+        assert!(unknown.contains("unknown joint wrist"), "{}", unknown);
+        let duplicate =
+            config(r#"(joint: "elevator", output: Pwm(0)), (joint: "elevator", output: Pwm(1))"#)
+                .unwrap_err();
+        assert!(
+            duplicate.contains("joint elevator is mapped more than once"),
+            "{}",
+            duplicate
+        );
+    }
+}
@@ -1,58 +1,312 @@
 use bevy::prelude::*;
 
-const ARM_LENGTH: f32 = 39.37;
-const INTAKE_OFFSET: f32 = 13.0;
-const ELEVATOR_MIN: f32 = -13.65;
-const ELEVATOR_OFFSET: f32 = -13.65;
+use crate::simulations::main::components::CollisionGrid;
+use crate::simulations::main::planner::wrap_angle;
+use crate::simulations::robot::{JointKind, RobotConfig};
 
+const CLAMP_SEARCH_STEP: f32 = 1.0; // Degrees
+/// How far (radians) a target intake rotation may be from the rotation the
+/// locked intake pivot holds
+const ROTATION_TOLERANCE: f32 = 0.01;
+/// Discriminants this far below zero, relative to the squared arm length,
+/// are rounding error at a level arm, where the two solutions meet
+const TANGENT_TOLERANCE: f32 = 1e-5;
+/// Solutions this far (sim units) past an elevator limit are rounding error
+/// and get clamped onto it
+const LIMIT_TOLERANCE: f32 = 1e-3;
+/// Solutions whose travel differs by less than this (sim units) are ranked by
+/// clearance instead
+const TRAVEL_TIE: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
 pub struct ArmPosition {
-    pub height: f32,      // Elevator height
-    pub arm_angle: f32,   // Angle in radians
+    pub height: f32,    // Elevator height
+    pub arm_angle: f32, // Angle in radians
+}
+
+/// One way of putting the intake on a target, ranked by `inverse_kinematics`
+#[derive(Debug, Clone, Copy)]
+pub struct IkSolution {
+    pub pose: ArmPosition,
+    /// Elevator travel plus the arc the arm tip sweeps, in sim units
+    pub travel: f32,
+    /// Arm tip distance to the nearest colliding grid cell, zero inside one
+    /// and infinite without a grid
+    pub clearance: f32,
+}
+
+/// Every pose that puts the intake center at `position`, best first.
+/// `rotation` optionally asks for an intake rotation too; the intake pivot
+/// is rotation locked, so only its mount rotation can be reached. Solutions
+/// clear of the grid come before colliding ones, then the least travel from
+/// `current` wins, with ties going to the most clearance. Without a current
+/// pose every solution has zero travel.
+pub fn inverse_kinematics(
+    robot: &RobotConfig,
+    position: Vec2,
+    rotation: Option<f32>,
+    current: Option<&ArmPosition>,
+    grid: Option<&CollisionGrid>,
+) -> Vec<IkSolution> {
+    let (mount, mount_rotation) = robot.intake_mount();
+    if rotation
+        .is_some_and(|rotation| wrap_angle(rotation - mount_rotation).abs() > ROTATION_TOLERANCE)
+    {
+        return Vec::new();
+    }
+
+    let mut solutions: Vec<IkSolution> = ArmPosition::solve(robot, position - mount)
+        .into_iter()
+        .map(|pose| IkSolution {
+            pose,
+            travel: current.map_or(0.0, |current| current.travel_to(&pose, robot)),
+            clearance: grid.map_or(f32::INFINITY, |grid| {
+                if pose.validate_with_grid(grid, robot) {
+                    let tip = robot.arm_tip(pose.joint_height(robot), pose.arm_angle);
+                    // Measured clearance when the grid has it, otherwise the
+                    // distance to the nearest colliding cell
+                    grid.tip_clearance(tip)
+                        .or_else(|| grid.distance_to_collision(tip))
+                        .unwrap_or(f32::INFINITY)
+                } else {
+                    0.0
+                }
+            }),
+        })
+        .collect();
+    solutions.sort_by(|a, b| {
+        (a.clearance <= 0.0)
+            .cmp(&(b.clearance <= 0.0))
+            .then_with(|| {
+                if (a.travel - b.travel).abs() < TRAVEL_TIE {
+                    b.clearance.total_cmp(&a.clearance)
+                } else {
+                    a.travel.total_cmp(&b.travel)
+                }
+            })
+    });
+    solutions
 }
 
 impl ArmPosition {
-    pub fn from_target(target: Vec2) -> Option<Self> {
-        // Adjust target position to account for intake offset
-        let adjusted_x = target.x;
-        let adjusted_y = target.y + INTAKE_OFFSET;
+    /// Best pose for an intake center target, see `inverse_kinematics`
+    pub fn from_target(
+        target: Vec2,
+        robot: &RobotConfig,
+        current: Option<&Self>,
+        grid: Option<&CollisionGrid>,
+    ) -> Option<Self> {
+        inverse_kinematics(robot, target, None, current, grid)
+            .first()
+            .map(|solution| solution.pose)
+    }
 
-        // Calculate distance from elevator to target
-        let distance = (adjusted_x.powi(2) + adjusted_y.powi(2)).sqrt();
+    /// Every pose within the elevator limits that puts the arm tip at `tip`.
+    /// The arm pivot rides the elevator axis, so the tip is a circle of arm
+    /// length around a point on that line: there are up to two solutions,
+    /// one on each side of the axis.
+    pub fn solve(robot: &RobotConfig, tip: Vec2) -> Vec<Self> {
+        let JointKind::Prismatic { axis, .. } = robot.elevator_joint().kind else {
+            unreachable!("validated when loading")
+        };
+        let [min, max] = robot.elevator_limits();
+        // Pivot at the bottom of the elevator, and the arm from pivot to tip
+        // at zero arm angle
+        let joint = robot.arm_joint();
+        let pivot =
+            robot.forward_kinematics(0.0, 0.0)[joint.parent].transform_point(joint.parent_anchor);
+        let arm = robot.arm_tip(min, 0.0) - pivot;
 
-        // Check if point is reachable
-        if distance > ARM_LENGTH {
-            return None;
+        // |offset - axis * t|² = |arm|² for travel t along the unit axis
+        let axis_length = axis.length();
+        let direction = axis / axis_length;
+        let offset = tip - pivot;
+        let along = offset.dot(direction);
+        let discriminant = along * along - offset.length_squared() + arm.length_squared();
+        if discriminant < -TANGENT_TOLERANCE * arm.length_squared() {
+            return Vec::new();
         }
+        let root = discriminant.max(0.0).sqrt();
+        let roots = if root == 0.0 {
+            vec![along]
+        } else {
+            vec![along - root, along + root]
+        };
 
-        // Calculate base angle using atan2
-        let base_angle = adjusted_y.atan2(adjusted_x);
+        roots
+            .into_iter()
+            .map(|distance| min + distance / axis_length)
+            .filter(|joint| (min - LIMIT_TOLERANCE..=max + LIMIT_TOLERANCE).contains(joint))
+            .map(|joint| {
+                let joint = joint.clamp(min, max);
+                let reach = tip - (pivot + axis * (joint - min));
+                Self {
+                    height: joint - robot.elevator_offset(),
+                    arm_angle: wrap_angle(reach.to_angle() - arm.to_angle()),
+                }
+            })
+            .collect()
+    }
 
-        // Calculate required elevator height
-        let height = adjusted_y - ARM_LENGTH * base_angle.sin();
-        
-        // Validate elevator height is within bounds
-        if height < ELEVATOR_MIN || height > 31.75 {
-            return None;
+    /// Elevator travel plus the arc the arm tip sweeps going to `other` the
+    /// short way round, in sim units
+    pub fn travel_to(&self, other: &Self, robot: &RobotConfig) -> f32 {
+        (other.height - self.height).abs()
+            + robot.arm_length() * wrap_angle(other.arm_angle - self.arm_angle).abs()
+    }
+
+    /// Elevator joint position for this pose
+    pub fn joint_height(&self, robot: &RobotConfig) -> f32 {
+        self.height + robot.elevator_offset()
+    }
+
+    pub fn validate_with_grid(&self, grid: &CollisionGrid, robot: &RobotConfig) -> bool {
+        // Calculate arm endpoint position
+        let Vec2 { x: arm_x, y: arm_y } = robot.arm_tip(self.joint_height(robot), self.arm_angle);
+
+        // Anything outside the scanned area is treated as unsafe
+        if !(grid.min_x..=grid.max_x).contains(&arm_x)
+            || !(grid.min_y..=grid.max_y).contains(&arm_y)
+        {
+            return false;
         }
 
-        Some(Self {
-            height: height - ELEVATOR_OFFSET,  // Adjust for elevator offset
-            arm_angle: base_angle,
+        // Check if position is within grid bounds, collision-free and clear
+        // by the safety margin
+        let tip = Vec2::new(arm_x, arm_y);
+        grid.collides_at(tip) == Some(false) && grid.keeps_margin(tip)
+    }
+
+    /// Closest pose at the same elevator height that `is_safe` accepts,
+    /// searching the arm angle outwards in both directions
+    pub fn nearest_safe(&self, is_safe: impl Fn(&Self) -> bool) -> Option<Self> {
+        let steps = (180.0 / CLAMP_SEARCH_STEP) as usize;
+        (1..=steps).find_map(|step| {
+            let delta = (step as f32 * CLAMP_SEARCH_STEP).to_radians();
+            [self.arm_angle + delta, self.arm_angle - delta]
+                .into_iter()
+                .map(|arm_angle| Self {
+                    height: self.height,
+                    arm_angle,
+                })
+                .find(|candidate| is_safe(candidate))
         })
     }
+}
 
-    pub fn validate_with_grid(&self, grid: &Vec<Vec<bool>>, min_x: f32, min_y: f32, step: f32) -> bool {
-        // Calculate arm endpoint position
-        let arm_x = ARM_LENGTH * self.arm_angle.cos();
-        let arm_y = (self.height + ELEVATOR_OFFSET) + ARM_LENGTH * self.arm_angle.sin();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sim units
+    const POSITION_TOLERANCE: f32 = 0.01;
+    const HEIGHT_TOLERANCE: f32 = 0.01;
+    /// Radians
+    const ANGLE_TOLERANCE: f32 = 1e-3;
+
+    fn robot() -> RobotConfig {
+        RobotConfig::parse(include_str!("../../../robot.ron"))
+            .expect("built-in robot description is valid")
+    }
+
+    fn intake_center(robot: &RobotConfig, pose: &ArmPosition) -> Vec2 {
+        robot.forward_kinematics(pose.height, pose.arm_angle)[robot.intake_index()].translation
+    }
+
+    fn pose(height: f32, degrees: f32) -> ArmPosition {
+        ArmPosition {
+            height,
+            arm_angle: degrees.to_radians(),
+        }
+    }
+
+    fn same_pose(a: &ArmPosition, b: &ArmPosition) -> bool {
+        (a.height - b.height).abs() < HEIGHT_TOLERANCE
+            && wrap_angle(a.arm_angle - b.arm_angle).abs() < ANGLE_TOLERANCE
+    }
+
+    /// Solves for the intake center `pose` reaches, checking that `pose` is
+    /// among the solutions and that every solution reaches the same place
+    fn round_trip(robot: &RobotConfig, pose: &ArmPosition) -> Vec<ArmPosition> {
+        let target = intake_center(robot, pose);
+        let solutions: Vec<ArmPosition> = inverse_kinematics(robot, target, None, None, None)
+            .into_iter()
+            .map(|solution| solution.pose)
+            .collect();
+        assert!(
+            solutions.iter().any(|solution| same_pose(solution, pose)),
+            "{:?} not among {:?}",
+            pose,
+            solutions
+        );
+        for solution in &solutions {
+            let reached = intake_center(robot, solution);
+            assert!(
+                reached.distance(target) < POSITION_TOLERANCE,
+                "{:?} reaches {:?} instead of {:?}",
+                solution,
+                reached,
+                target
+            );
+        }
+        solutions
+    }
+
+    #[test]
+    fn round_trips_both_arm_solutions() {
+        let robot = robot();
+        // The pivot rides a vertical axis, so the other solution mirrors the
+        // arm below level with the elevator higher by the arm's rise twice
+        let up = pose(5.0, 20.0);
+        let rise = 2.0 * robot.arm_length() * 20.0f32.to_radians().sin();
+        let down = pose(5.0 + rise, -20.0);
+
+        for start in [up, down] {
+            let solutions = round_trip(&robot, &start);
+            assert_eq!(solutions.len(), 2, "{:?}", solutions);
+            assert!(solutions.iter().any(|solution| same_pose(solution, &up)));
+            assert!(solutions.iter().any(|solution| same_pose(solution, &down)));
+        }
+    }
+
+    #[test]
+    fn round_trips_at_the_joint_limits() {
+        let robot = robot();
+        let [min, max] = robot.elevator_limits();
+        let top = max - min;
+
+        // At the bottom with the arm up, the other solution is in range
+        assert_eq!(round_trip(&robot, &pose(0.0, 30.0)).len(), 2);
+        // Its mirror would need the elevator below the bottom or above the
+        // top, so only the pose itself remains
+        assert_eq!(round_trip(&robot, &pose(0.0, -30.0)).len(), 1);
+        assert_eq!(round_trip(&robot, &pose(top, 30.0)).len(), 1);
+        // Pointing backwards works the same way
+        assert_eq!(round_trip(&robot, &pose(top, 150.0)).len(), 1);
+        assert_eq!(round_trip(&robot, &pose(0.0, -150.0)).len(), 1);
+    }
+
+    #[test]
+    fn unreachable_targets_have_no_solution() {
+        let robot = robot();
+        let [min, max] = robot.elevator_limits();
+        let level = intake_center(&robot, &pose(10.0, 0.0));
+        let solve = |target: Vec2, rotation: Option<f32>| {
+            inverse_kinematics(&robot, target, rotation, None, None)
+        };
 
-        // Convert to grid coordinates
-        let grid_x = ((arm_x - min_x) / step).floor() as usize;
-        let grid_y = ((arm_y - min_y) / step).floor() as usize;
+        // Further out than the arm reaches
+        assert!(solve(level + Vec2::new(1.0, 0.0), None).is_empty());
+        // Above the highest point the arm reaches at the top of the elevator
+        let highest = intake_center(&robot, &pose(max - min, 90.0));
+        assert!(solve(highest + Vec2::new(0.0, 1.0), None).is_empty());
+        // Below the lowest point it reaches at the bottom
+        let lowest = intake_center(&robot, &pose(0.0, -90.0));
+        assert!(solve(lowest - Vec2::new(0.0, 1.0), None).is_empty());
 
-        // Check if position is within grid bounds and collision-free
-        grid_y < grid.len() 
-            && grid_x < grid[0].len() 
-            && !grid[grid_y][grid_x]
+        // The intake pivot is locked, so only the mount rotation is reachable
+        let (_, rotation) = robot.intake_mount();
+        assert!(!solve(level, Some(rotation)).is_empty());
+        assert!(solve(level, Some(rotation + 0.5)).is_empty());
     }
 }
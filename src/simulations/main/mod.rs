@@ -1,39 +1,256 @@
 mod code_control;
 mod components;
+mod control;
+mod controllers;
+mod game_pieces;
+mod halsim;
+mod kinematics;
+mod motors;
+mod network;
 mod physics;
+mod planner;
+mod presets;
+mod replay;
+mod safety;
+mod sweep;
 mod systems;
-mod kinematics;
+mod telemetry;
+mod validate;
+
+use std::path::PathBuf;
+use std::time::Duration;
 
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::input::InputPlugin;
 use bevy::prelude::*;
+use bevy::scene::ScenePlugin;
 use bevy_rapier2d::prelude::*;
 
 use code_control::*;
 use components::*;
+use controllers::*;
+use game_pieces::*;
+use halsim::*;
+use motors::*;
+use network::*;
+use planner::PlannedPath;
+use presets::*;
+use safety::*;
+use sweep::ContactCheck;
 use systems::*;
+use telemetry::*;
+
+pub use presets::PRESETS_FILE;
+pub use replay::{run_replay, ReplayOptions};
+pub use validate::{validate, ValidateOptions};
+
+use crate::simulations::field::FieldConfig;
+use crate::simulations::nt4::NtServer;
+use crate::simulations::robot::{RobotConfig, SIM_UNITS_PER_INCH};
+
+/// Frame rate without a window to pace it, about a monitor's refresh rate
+const HEADLESS_FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+pub struct MainOptions {
+    pub robot_config: String,
+    pub presets: String,
+    pub grid_file: String,
+    pub joint_grid_file: String,
+    pub field: String,
+    /// Clearance in inches targets and paths must keep
+    pub safety_margin: f32,
+    /// No window, rendering or mouse control, for driving the sim from robot
+    /// code
+    pub headless: bool,
+    /// Base path for the WPILOG and CSV telemetry logs
+    pub log: Option<PathBuf>,
+    /// NetworkTables server port, 0 for any free port
+    pub nt_port: u16,
+}
+
+pub fn run(options: MainOptions) -> App {
+    let presets = Presets::load_or_default(&options.presets);
+    let target = TargetPosition {
+        current: presets.initial().clone(),
+    };
+
+    let robot = RobotConfig::load_or_default(&options.robot_config);
+    let halsim = match HalSimConfig::load(HALSIM_FILE, &robot) {
+        Ok(config) => {
+            println!("Waiting for HALSim robot code at {}", config.url);
+            Some(HalSim::start(config))
+        }
+        Err(e) => {
+            println!("HALSim disabled, failed to load {}: {}", HALSIM_FILE, e);
+            None
+        }
+    };
 
-pub fn run() -> App {
     let mut app = App::new();
-    app.add_plugins((
-        DefaultPlugins,
-        RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
-        RapierDebugRenderPlugin::default(),
-    ))
-    .init_resource::<MouseWorldPos>()
-    .init_resource::<ControlMode>()
-    .init_resource::<TargetPosition>()
-    .add_systems(Startup, (setup_graphics, physics::setup_physics))
-    .add_systems(
-        Update,
-        (
-            update_mouse_position,
-            apply_intake_force,
-            handle_control_mode,
-            update_code_motors,
+    if options.headless {
+        // Rapier needs transforms, hierarchy, assets and scenes. Input stays
+        // so the preset key systems can run.
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(HEADLESS_FRAME_TIME)),
+            TransformPlugin,
+            HierarchyPlugin,
+            InputPlugin,
+            AssetPlugin::default(),
+            ScenePlugin,
+        ));
+    } else {
+        app.add_plugins(DefaultPlugins);
+    }
+    app.add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+        .insert_resource(ArmControllers::load_or_default(CONTROLLERS_FILE))
+        .insert_resource(Time::<Fixed>::from_hz(50.0))
+        .init_resource::<ControlMode>()
+        .insert_resource(presets)
+        .insert_resource(target)
+        .init_resource::<SafetyWarning>()
+        .init_resource::<RulesViolation>()
+        .init_resource::<PlannedPath>()
+        .init_resource::<NetworkInputs>()
+        .init_resource::<ExternalVoltages>()
+        .init_resource::<SimState>()
+        .init_resource::<IntakeRollers>()
+        .add_systems(Startup, (physics::setup_physics, physics::setup_field))
+        .add_systems(FixedUpdate, run_controllers)
+        .add_systems(
+            Update,
+            (
+                reload_presets,
+                reload_controller_gains,
+                read_network_inputs.before(update_code_motors),
+                read_halsim_outputs.after(read_network_inputs),
+                update_code_motors,
+                apply_motor_forces.after(update_code_motors),
+                update_sim_state.after(apply_motor_forces),
+                publish_sim_state.after(update_sim_state),
+                write_halsim_sensors.after(update_sim_state),
+                record_telemetry.after(update_sim_state),
+                spawn_game_pieces,
+                run_intake_rollers.before(update_code_motors),
+                despawn_lost_pieces,
+                flag_rule_violations.before(update_sim_state),
+            ),
+        );
+    if !options.headless {
+        app.add_plugins(RapierDebugRenderPlugin::default())
+            .init_resource::<MouseWorldPos>()
+            .add_systems(
+                Startup,
+                (setup_graphics, setup_safety_text, setup_motor_text),
+            )
+            .add_systems(
+                Update,
+                (
+                    update_mouse_position,
+                    follow_cursor,
+                    handle_control_mode,
+                    update_safety_text,
+                    update_motor_text,
+                ),
+            );
+    }
+
+    // Moves are swept against the shown field elements before they are
+    // followed, and planned paths keep out of them
+    let field = FieldConfig::load_or_default(&options.field, &robot);
+    let shown = field
+        .as_ref()
+        .map(FieldConfig::shown_parts)
+        .unwrap_or_default();
+    let contact_check = ContactCheck::with_field(&robot, &shown, None);
+    load_grids(&mut app, &options, &robot, &contact_check);
+    app.insert_resource(contact_check);
+    if let Some(field) = field {
+        app.insert_resource(field);
+    }
+    app.insert_resource(robot);
+    if let Some(halsim) = halsim {
+        app.insert_resource(halsim);
+    }
+    if let Some(log) = open_telemetry_log(options.log) {
+        app.insert_resource(log);
+    }
+    match NtServer::start(options.nt_port) {
+        Ok(server) => {
+            println!("NetworkTables server listening on port {}", server.port());
+            app.insert_resource(server);
+        }
+        Err(e) => println!(
+            "Failed to start NetworkTables server on port {}: {}",
+            options.nt_port, e
         ),
-    );
+    }
     app
 }
 
+/// Loads the grids generated for this robot. Without them targets aren't
+/// checked and paths aren't planned.
+fn load_grids(
+    app: &mut App,
+    options: &MainOptions,
+    robot: &RobotConfig,
+    contact_check: &ContactCheck,
+) {
+    let margin = options.safety_margin * SIM_UNITS_PER_INCH;
+    match CollisionGrid::load_from_file(&options.grid_file, robot) {
+        Ok(mut grid) => {
+            grid.safety_margin = margin;
+            if margin > 0.0 && grid.clearance.is_none() {
+                println!("Collision grid has no clearance values, regenerate it to keep the safety margin");
+            }
+            println!(
+                "Loaded collision grid: {}x{}{}",
+                grid.grid[0].len(),
+                grid.grid.len(),
+                if grid.refinement.is_some() {
+                    ", refined along the collision boundary"
+                } else {
+                    ""
+                }
+            );
+            app.insert_resource(grid);
+        }
+        Err(e) => println!(
+            "Failed to load collision grid {}, targets will not be checked: {}\n  \
+             Generate it with `grid --headless`",
+            options.grid_file, e
+        ),
+    }
+
+    match JointGrid::load_from_file(&options.joint_grid_file, robot) {
+        Ok(mut grid) => {
+            grid.safety_margin = margin;
+            if margin > 0.0 && grid.clearance.is_none() {
+                println!(
+                    "Joint grid has no clearance values, regenerate it to keep the safety margin"
+                );
+            }
+            println!(
+                "Loaded joint grid: {}x{}",
+                grid.grid[0].len(),
+                grid.grid.len()
+            );
+            let blocked = contact_check.block_added(robot, &mut grid);
+            if blocked > 0 {
+                println!(
+                    "Blocked {} joint grid cells that reach into the field",
+                    blocked
+                );
+            }
+            app.insert_resource(grid);
+        }
+        Err(e) => println!(
+            "Failed to load joint grid {}, paths will not be planned: {}\n  \
+             Generate it with `grid --headless --cspace`",
+            options.joint_grid_file, e
+        ),
+    }
+}
+
 fn setup_graphics(mut commands: Commands) {
     commands.spawn((
         Camera2d,
@@ -43,3 +260,24 @@ fn setup_graphics(mut commands: Commands) {
         },
     ));
 }
+
+/// Writes the presets file as a Java enum for the robot project
+pub fn export_presets(presets_path: &str, path: &str) -> bool {
+    let presets = match Presets::load(presets_path) {
+        Ok(presets) => presets,
+        Err(e) => {
+            println!("Failed to load presets from {}: {}", presets_path, e);
+            return false;
+        }
+    };
+    match presets.export_java(path) {
+        Ok(()) => {
+            println!("Exported {} presets to {}", presets.presets.len(), path);
+            true
+        }
+        Err(e) => {
+            println!("Failed to export presets: {}", e);
+            false
+        }
+    }
+}
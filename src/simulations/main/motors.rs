@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::simulations::robot::{
+    BatteryDescription, ElevatorAssist, Motor, MotorType, GRAVITY, SIM_UNITS_PER_METER,
+};
+
+/// Brushless DC motor characteristics at its nominal voltage
+#[derive(Debug, Clone, Copy)]
+pub struct DcMotor {
+    pub nominal_voltage: f32,
+    pub stall_torque: f32, // N*m
+    pub stall_current: f32,
+    pub free_current: f32,
+    pub free_speed: f32, // rad/s
+}
+
+impl DcMotor {
+    pub const KRAKEN_X60: Self = Self {
+        nominal_voltage: 12.0,
+        stall_torque: 7.09,
+        stall_current: 366.0,
+        free_current: 2.0,
+        free_speed: 6000.0 * std::f32::consts::TAU / 60.0,
+    };
+
+    pub const NEO: Self = Self {
+        nominal_voltage: 12.0,
+        stall_torque: 2.6,
+        stall_current: 105.0,
+        free_current: 1.8,
+        free_speed: 5676.0 * std::f32::consts::TAU / 60.0,
+    };
+
+    pub fn from_type(motor: MotorType) -> Self {
+        match motor {
+            MotorType::KrakenX60 => Self::KRAKEN_X60,
+            MotorType::Neo => Self::NEO,
+        }
+    }
+
+    fn resistance(&self) -> f32 {
+        self.nominal_voltage / self.stall_current
+    }
+
+    /// Speed per volt of back-EMF
+    fn kv(&self) -> f32 {
+        self.free_speed / (self.nominal_voltage - self.resistance() * self.free_current)
+    }
+
+    /// Torque per amp
+    fn kt(&self) -> f32 {
+        self.stall_torque / self.stall_current
+    }
+
+    /// Current drawn at a motor speed (rad/s) and applied voltage
+    pub fn current(&self, speed: f32, voltage: f32) -> f32 {
+        (voltage - speed / self.kv()) / self.resistance()
+    }
+
+    pub fn torque(&self, current: f32) -> f32 {
+        current * self.kt()
+    }
+}
+
+/// Motors on a joint, driven from a commanded voltage. Attached to the joint
+/// entity next to its `ImpulseJoint`.
+#[derive(Component)]
+pub struct JointMotor {
+    pub name: String,
+    pub config: Motor,
+    pub dc_motor: DcMotor,
+    /// Slide direction in the parent body frame, `None` for revolute joints
+    pub axis: Option<Vec2>,
+    /// Commanded voltage, clamped to the battery voltage when applied
+    pub voltage: f32,
+    /// Coast with no current when disabled
+    pub enabled: bool,
+    pub applied_voltage: f32,
+    /// Stator current per motor
+    pub current: f32,
+    pub current_limited: bool,
+}
+
+impl JointMotor {
+    pub fn new(name: &str, config: Motor, axis: Option<Vec2>) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+            dc_motor: DcMotor::from_type(config.motor),
+            axis,
+            voltage: 0.0,
+            enabled: true,
+            applied_voltage: 0.0,
+            current: 0.0,
+            current_limited: false,
+        }
+    }
+
+    /// Updates the motor state for a joint speed (m/s or rad/s) and returns
+    /// the output force (N) or torque (N*m) on the joint
+    fn drive(&mut self, joint_speed: f32, battery_voltage: f32) -> f32 {
+        if !self.enabled {
+            self.applied_voltage = 0.0;
+            self.current = 0.0;
+            self.current_limited = false;
+            return 0.0;
+        }
+
+        let reduction = self.reduction();
+        let motor_speed = joint_speed * reduction;
+
+        self.applied_voltage = self.voltage.clamp(-battery_voltage, battery_voltage);
+        let current = self.dc_motor.current(motor_speed, self.applied_voltage);
+        let limit = self.config.current_limit;
+        self.current_limited = current.abs() > limit;
+        self.current = current.clamp(-limit, limit);
+
+        self.dc_motor.torque(self.current) * self.config.count as f32 * reduction
+    }
+
+    /// Reduction from the joint output to the motor shaft
+    fn reduction(&self) -> f32 {
+        self.config.gear_ratio / self.config.spool_radius.unwrap_or(1.0)
+    }
+
+    /// Voltage needed to hold a static load (N or N*m), i.e. the kG term of
+    /// a feedforward, and the voltage per unit of joint speed (kV)
+    pub fn feedforward_estimate(&self, load: f32) -> (f32, f32) {
+        let output_per_amp = self.dc_motor.kt() * self.config.count as f32 * self.reduction();
+        let kg = load / output_per_amp * self.dc_motor.resistance();
+        let kv = self.reduction() / self.dc_motor.kv();
+        (kg, kv)
+    }
+
+    /// Current drawn from the battery by all motors on the joint
+    fn supply_current(&self, battery_voltage: f32) -> f32 {
+        if battery_voltage <= 0.0 {
+            return 0.0;
+        }
+        (self.current * self.applied_voltage / battery_voltage).abs() * self.config.count as f32
+    }
+}
+
+/// Battery shared by all motors. The voltage sags with the current drawn on
+/// the previous update.
+#[derive(Resource)]
+pub struct Battery {
+    pub nominal_voltage: f32,
+    pub resistance: f32,
+    pub voltage: f32,
+    pub current: f32,
+}
+
+impl Battery {
+    pub fn new(description: BatteryDescription) -> Self {
+        Self {
+            nominal_voltage: description.voltage,
+            resistance: description.resistance,
+            voltage: description.voltage,
+            current: 0.0,
+        }
+    }
+
+    /// Sags the voltage for the total current drawn (A)
+    fn draw(&mut self, current: f32) {
+        self.current = current;
+        self.voltage = (self.nominal_voltage - self.resistance * current).max(0.0);
+    }
+}
+
+/// Passive force holding up the elevator carriage, on the elevator joint
+/// entity
+#[derive(Component)]
+pub struct JointAssist {
+    pub assist: ElevatorAssist,
+    /// Slide direction in the parent body frame
+    pub axis: Vec2,
+    previous_speed: f32,
+}
+
+impl JointAssist {
+    pub fn new(assist: ElevatorAssist, axis: Vec2) -> Self {
+        Self {
+            assist,
+            axis,
+            previous_speed: 0.0,
+        }
+    }
+
+    /// Force in newtons along the axis for the carriage speed (m/s)
+    fn force(&mut self, speed: f32, delta_seconds: f32) -> f32 {
+        let acceleration = if delta_seconds > 0.0 {
+            (speed - self.previous_speed) / delta_seconds
+        } else {
+            0.0
+        };
+        self.previous_speed = speed;
+
+        match self.assist {
+            ElevatorAssist::ConstantForceSpring { force } => force,
+            // The counterweight moves the opposite way, so its inertia resists
+            // the carriage accelerating
+            ElevatorAssist::Counterweight { mass } => mass * (GRAVITY - acceleration),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MotorStatusText;
+
+/// Applies each motor's force or torque to the bodies on its joint, with the
+/// reaction on the parent body. Elevator assists are added the same way.
+pub fn apply_motor_forces(
+    time: Res<Time>,
+    mut motors: Query<(&mut JointMotor, &ImpulseJoint, &Parent)>,
+    mut assists: Query<(&mut JointAssist, &ImpulseJoint, &Parent)>,
+    bodies: Query<(&Transform, Option<&Velocity>)>,
+    mut forces: Query<&mut ExternalForce>,
+    mut battery: ResMut<Battery>,
+) {
+    let battery_voltage = battery.voltage;
+    let mut applied: HashMap<Entity, ExternalForce> = HashMap::new();
+    let mut total_current = 0.0;
+
+    for (mut motor, joint, child) in motors.iter_mut() {
+        let (parent, child) = (joint.parent, child.get());
+        let Ok((parent_transform, parent_velocity)) = bodies.get(parent) else {
+            continue;
+        };
+        let Ok((_, Some(child_velocity))) = bodies.get(child) else {
+            continue;
+        };
+        let parent_velocity = parent_velocity.copied().unwrap_or_default();
+
+        let (child_force, parent_force) = match motor.axis {
+            Some(axis) => {
+                let axis = (parent_transform.rotation * axis.extend(0.0)).truncate();
+                let speed = (child_velocity.linvel - parent_velocity.linvel).dot(axis)
+                    / SIM_UNITS_PER_METER;
+                let force = axis * motor.drive(speed, battery_voltage) * SIM_UNITS_PER_METER;
+                (
+                    ExternalForce { force, torque: 0.0 },
+                    ExternalForce {
+                        force: -force,
+                        torque: 0.0,
+                    },
+                )
+            }
+            None => {
+                let speed = child_velocity.angvel - parent_velocity.angvel;
+                let torque =
+                    motor.drive(speed, battery_voltage) * SIM_UNITS_PER_METER * SIM_UNITS_PER_METER;
+                (
+                    ExternalForce {
+                        force: Vec2::ZERO,
+                        torque,
+                    },
+                    ExternalForce {
+                        force: Vec2::ZERO,
+                        torque: -torque,
+                    },
+                )
+            }
+        };
+
+        *applied.entry(child).or_default() += child_force;
+        *applied.entry(parent).or_default() += parent_force;
+        total_current += motor.supply_current(battery_voltage);
+    }
+
+    for (mut assist, joint, child) in assists.iter_mut() {
+        let (parent, child) = (joint.parent, child.get());
+        let Ok((parent_transform, parent_velocity)) = bodies.get(parent) else {
+            continue;
+        };
+        let Ok((_, Some(child_velocity))) = bodies.get(child) else {
+            continue;
+        };
+        let parent_velocity = parent_velocity.copied().unwrap_or_default();
+
+        let axis = (parent_transform.rotation * assist.axis.extend(0.0)).truncate();
+        let speed =
+            (child_velocity.linvel - parent_velocity.linvel).dot(axis) / SIM_UNITS_PER_METER;
+        let force = axis * assist.force(speed, time.delta_secs()) * SIM_UNITS_PER_METER;
+        *applied.entry(child).or_default() += ExternalForce { force, torque: 0.0 };
+        *applied.entry(parent).or_default() -= ExternalForce { force, torque: 0.0 };
+    }
+
+    for (entity, force) in applied {
+        if let Ok(mut external) = forces.get_mut(entity) {
+            *external = force;
+        }
+    }
+
+    battery.draw(total_current);
+}
+
+pub fn setup_motor_text(mut commands: Commands) {
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        },
+        MotorStatusText,
+    ));
+}
+
+/// Shows voltage and current per motor so an underpowered mechanism is
+/// visible as a motor sitting at its current limit
+pub fn update_motor_text(
+    motors: Query<&JointMotor>,
+    battery: Res<Battery>,
+    mut text_query: Query<&mut Text, With<MotorStatusText>>,
+) {
+    let mut status = format!(
+        "Battery: {:.2} V, {:.0} A",
+        battery.voltage, battery.current
+    );
+    for motor in motors.iter() {
+        status.push_str(&format!(
+            "\n{}: {:.2} V, {:.0} A{}",
+            motor.name,
+            motor.applied_voltage,
+            motor.current,
+            if motor.current_limited {
+                " (current limited)"
+            } else {
+                ""
+            }
+        ));
+    }
+
+    for mut text in text_query.iter_mut() {
+        if text.0 != status {
+            text.0 = status.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn rpm(speed: f32) -> f32 {
+        speed * 60.0 / std::f32::consts::TAU
+    }
+
+    #[test]
+    fn stalls_and_spins_free_at_the_datasheet_values() {
+        // (motor, stall torque N*m, stall current A, free speed RPM, free current A)
+        for (motor, stall_torque, stall_current, free_rpm, free_current) in [
+            (DcMotor::KRAKEN_X60, 7.09, 366.0, 6000.0, 2.0),
+            (DcMotor::NEO, 2.6, 105.0, 5676.0, 1.8),
+        ] {
+            let current = motor.current(0.0, 12.0);
+            assert_close(current, stall_current, 1e-3);
+            assert_close(motor.torque(current), stall_torque, 1e-4);
+
+            assert_close(motor.current(motor.free_speed, 12.0), free_current, 1e-3);
+            assert_close(rpm(motor.free_speed), free_rpm, 1e-2);
+        }
+    }
+
+    #[test]
+    fn clamps_to_the_current_limit() {
+        let config = Motor {
+            joint: 0,
+            motor: MotorType::KrakenX60,
+            count: 2,
+            gear_ratio: 10.0,
+            spool_radius: None,
+            current_limit: 40.0,
+        };
+        let mut motor = JointMotor::new("arm", config, None);
+        let kt = DcMotor::KRAKEN_X60.stall_torque / DcMotor::KRAKEN_X60.stall_current;
+
+        motor.voltage = 12.0;
+        let torque = motor.drive(0.0, 12.0);
+        assert!(motor.current_limited);
+        assert_close(motor.current, 40.0, 1e-4);
+        assert_close(torque, 40.0 * kt * 2.0 * 10.0, 1e-3);
+
+        motor.voltage = -12.0;
+        motor.drive(0.0, 12.0);
+        assert_close(motor.current, -40.0, 1e-4);
+
+        // Well under the limit near free speed
+        motor.voltage = 12.0;
+        motor.drive(DcMotor::KRAKEN_X60.free_speed / 10.0 * 0.99, 12.0);
+        assert!(!motor.current_limited);
+        assert!(motor.current.abs() < 40.0);
+
+        // The applied voltage is held to what the battery has left
+        motor.drive(0.0, 10.0);
+        assert_close(motor.applied_voltage, 10.0, 1e-6);
+
+        motor.enabled = false;
+        assert_eq!(motor.drive(0.0, 12.0), 0.0);
+        assert_eq!(motor.current, 0.0);
+    }
+
+    #[test]
+    fn battery_sags_with_current() {
+        let mut battery = Battery::new(BatteryDescription {
+            voltage: 12.5,
+            resistance: 0.02,
+        });
+        battery.draw(100.0);
+        assert_close(battery.voltage, 10.5, 1e-4);
+        assert_close(battery.current, 100.0, 1e-6);
+
+        battery.draw(1000.0);
+        assert_eq!(battery.voltage, 0.0);
+
+        battery.draw(0.0);
+        assert_close(battery.voltage, 12.5, 1e-6);
+    }
+}
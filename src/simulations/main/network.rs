@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+
+use crate::simulations::main::code_control::TargetPosition;
+use crate::simulations::main::controllers::ExternalVoltages;
+use crate::simulations::main::presets::PresetPosition;
+use crate::simulations::main::telemetry::{SimState, TELEMETRY_PREFIX};
+use crate::simulations::nt4::{NtServer, Value};
+use crate::simulations::robot::{SIM_UNITS_PER_INCH, SIM_UNITS_PER_METER};
+
+// Published by robot code. Setpoints go through the same safety checks and
+// planner as presets; voltages bypass the sim's controllers.
+const ELEVATOR_SETPOINT: &str = "/Sim/Elevator/Setpoint";
+const ARM_SETPOINT: &str = "/Sim/Arm/Setpoint";
+const ELEVATOR_VOLTAGE: &str = "/Sim/Elevator/Voltage";
+const ARM_VOLTAGE: &str = "/Sim/Arm/Voltage";
+
+const SETPOINT_PRESET_NAME: &str = "NetworkTables setpoint";
+
+/// Setpoints read from NetworkTables and applied to the target last, so
+/// preset keys still work until robot code sends a new one
+#[derive(Resource, Default)]
+pub struct NetworkInputs {
+    last_setpoint: (Option<f32>, Option<f32>),
+}
+
+pub fn read_network_inputs(
+    server: Option<Res<NtServer>>,
+    mut inputs: ResMut<NetworkInputs>,
+    mut external: ResMut<ExternalVoltages>,
+    mut target: ResMut<TargetPosition>,
+) {
+    let Some(server) = server else {
+        return;
+    };
+    let read = |name| server.get_f64(name).map(|value| value as f32);
+
+    // Voltages only apply while a client publishes them
+    external.elevator = read(ELEVATOR_VOLTAGE);
+    external.arm = read(ARM_VOLTAGE);
+
+    let setpoint = (read(ELEVATOR_SETPOINT), read(ARM_SETPOINT));
+    if setpoint == inputs.last_setpoint {
+        return;
+    }
+    inputs.last_setpoint = setpoint;
+
+    // A joint without a setpoint keeps its current target
+    let (height, angle) = setpoint;
+    if height.is_none() && angle.is_none() {
+        return;
+    }
+    target.current = PresetPosition {
+        name: SETPOINT_PRESET_NAME.to_string(),
+        height: height.map_or(target.current.height, |height| {
+            height * SIM_UNITS_PER_METER / SIM_UNITS_PER_INCH
+        }),
+        angle: angle.map_or(target.current.angle, f32::to_degrees),
+        key: String::new(),
+    };
+}
+
+pub fn publish_sim_state(server: Option<Res<NtServer>>, state: Res<SimState>) {
+    let Some(server) = server else {
+        return;
+    };
+    let topic = |name| format!("{}{}", TELEMETRY_PREFIX, name);
+
+    for (name, value) in state.numbers() {
+        if let Some(value) = value {
+            server.publish(&topic(name), Value::Double(value as f64));
+        }
+    }
+    for (name, value) in state.strings() {
+        server.publish(&topic(name), Value::String(value.to_string()));
+    }
+}
@@ -1,130 +1,150 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+use crate::simulations::field::FieldConfig;
 use crate::simulations::main::components::*;
-
-pub fn setup_physics(mut commands: Commands) {
-    // Load collision grid
-    if let Ok(grid) = CollisionGrid::load_from_file("collision_grid.bin") {
-        println!("Loaded collision grid: {}x{}", grid.grid[0].len(), grid.grid.len());
-        commands.insert_resource(grid);
-    } else {
-        println!("Failed to load collision grid!");
-    }
-
-    let elevator_body = commands
-        .spawn((
+use crate::simulations::main::motors::{Battery, JointAssist, JointMotor};
+use crate::simulations::robot::{JointKind, RobotConfig};
+
+/// Spawns the bodies and joints described by the robot config
+pub fn setup_physics(mut commands: Commands, robot: Res<RobotConfig>) {
+    let poses = robot.forward_kinematics(0.0, 0.0);
+    let bodies: Vec<Entity> = robot
+        .links
+        .iter()
+        .enumerate()
+        .map(|(i, link)| {
+            // Named so the telemetry log can report which links collide
+            let mut body = commands.spawn((
+                Name::new(link.name.clone()),
+                link.shape.collider(),
+                poses[i].transform(),
+                link.groups,
+                ActiveEvents::COLLISION_EVENTS,
+            ));
+            if i == robot.base {
+                body.insert(RigidBody::Fixed);
+            } else {
+                body.insert((
+                    RigidBody::Dynamic,
+                    Sleeping::disabled(),
+                    Damping {
+                        linear_damping: 0.5,
+                        angular_damping: 0.5,
+                    },
+                    Velocity::default(),
+                    ExternalForce::default(),
+                ));
+            }
+            if link.lock_rotation {
+                body.insert(LockedAxes::ROTATION_LOCKED);
+            }
+            if let Some(mass_properties) = link.mass_properties() {
+                body.insert(mass_properties);
+            }
+            if i == robot.intake_index() {
+                body.insert(IntakeMarker);
+            }
+            body.id()
+        })
+        .collect();
+
+    // The drivetrain and the floor never move, like the elevator tower
+    for part in robot.chassis_parts() {
+        commands.spawn((
+            Name::new(part.name.clone()),
+            part.shape.collider(),
+            part.pose.transform(),
+            part.groups,
             RigidBody::Fixed,
-            Collider::cuboid(2.52, 48.25),
-            Transform::from_xyz(0.0, 0.0, 0.0),
-            CollisionGroups::new(ELEVATOR, INTAKE),
-        ))
-        .id();
-
-    let carriage = commands
-        .spawn((
-            RigidBody::Dynamic,
-            Sleeping::disabled(),
-            Collider::ball(5.08),
-            Transform::from_xyz(5.08, -13.65, 0.0),
-            CollisionGroups::new(Group::NONE, Group::NONE),
-            Damping {
-                linear_damping: 0.5,
-                angular_damping: 0.5,
-            },
-            GravityScale(0.0),
-        ))
-        .id();
-
-    let arm = commands
-        .spawn((
-            RigidBody::Dynamic,
-            Sleeping::disabled(),
-            Collider::cuboid(19.685, 2.52),
-            Transform::from_xyz(24.765, -13.4, 0.0),
-            CollisionGroups::new(Group::NONE, Group::NONE),
-            Damping {
-                linear_damping: 0.5,
-                angular_damping: 0.5,
-            },
-            GravityScale(0.0),
-        ))
-        .id();
-
-    let intake_pivot = commands
-        .spawn((
-            RigidBody::Dynamic,
-            Sleeping::disabled(),
-            LockedAxes::ROTATION_LOCKED,
-            Collider::ball(5.08),
-            Transform::from_xyz(44.45, -13.4, 0.0)
-                .with_rotation(Quat::from_rotation_z(std::f32::consts::PI / 4.0)),
-            CollisionGroups::new(Group::NONE, Group::NONE),
-            Damping {
-                linear_damping: 0.5,
-                angular_damping: 0.5,
-            },
-            GravityScale(0.0),
-        ))
-        .id();
-
-    let intake = commands
-        .spawn((
-            RigidBody::Dynamic,
-            Sleeping::disabled(),
-            Collider::cuboid(19.05, 6.675),
-            Transform::from_xyz(35.258, -3.808, 0.0)
-                .with_rotation(Quat::from_rotation_z(std::f32::consts::PI / 4.0)),
-            CollisionGroups::new(INTAKE, ELEVATOR),
-            Damping {
-                linear_damping: 0.5,
-                angular_damping: 0.5,
-            },
-            GravityScale(0.0),
-            IntakeMarker,
-            ExternalForce::default(),
-        ))
-        .id();
-
-    let joint_elevator_carriage = PrismaticJointBuilder::new(Vec2::Y)
-        .local_anchor1(Vec2::new(0.0, 0.0))
-        .local_anchor2(Vec2::new(-5.08, 0.0))
-        .limits([-13.65, 31.75]);
-
-    let joint_carriage_arm = RevoluteJointBuilder::new()
-        .local_anchor1(Vec2::new(0.0, 0.0))
-        .local_anchor2(Vec2::new(-19.685, 0.0));
-
-    let joint_arm_pivot = RevoluteJointBuilder::new()
-        .local_anchor1(Vec2::new(19.685, 0.0))
-        .local_anchor2(Vec2::new(0.0, 0.0));
-
-    let joint_pivot_intake = FixedJointBuilder::new()
-        .local_anchor1(Vec2::new(0.0, 0.0))
-        .local_anchor2(Vec2::new(0.0, -13.0));
+            ActiveEvents::COLLISION_EVENTS,
+        ));
+    }
 
-    let elevator_joint = commands
-        .spawn(ImpulseJoint::new(elevator_body, joint_elevator_carriage))
-        .set_parent(carriage)
-        .id();
+    let joints: Vec<Entity> = robot
+        .joints
+        .iter()
+        .map(|joint| {
+            let data: TypedJoint = match joint.kind {
+                JointKind::Prismatic { axis, limits } => PrismaticJointBuilder::new(axis)
+                    .local_anchor1(joint.parent_anchor)
+                    .local_anchor2(joint.child_anchor)
+                    .limits(limits)
+                    .into(),
+                JointKind::Revolute { .. } => RevoluteJointBuilder::new()
+                    .local_anchor1(joint.parent_anchor)
+                    .local_anchor2(joint.child_anchor)
+                    .into(),
+                JointKind::Fixed => FixedJointBuilder::new()
+                    .local_anchor1(joint.parent_anchor)
+                    .local_anchor2(joint.child_anchor)
+                    .into(),
+            };
+            commands
+                .spawn(ImpulseJoint::new(bodies[joint.parent], data))
+                .set_parent(bodies[joint.child])
+                .id()
+        })
+        .collect();
+
+    for motor in &robot.motors {
+        let joint = &robot.joints[motor.joint];
+        let axis = match joint.kind {
+            JointKind::Prismatic { axis, .. } => Some(axis),
+            _ => None,
+        };
+        commands
+            .entity(joints[motor.joint])
+            .insert(JointMotor::new(&joint.name, *motor, axis));
+    }
+    commands.insert_resource(Battery::new(robot.battery));
+
+    let elevator = robot.elevator_joint_index();
+    let arm = robot.arm_joint_index();
+    if let (Some(assist), JointKind::Prismatic { axis, .. }) =
+        (robot.elevator_assist, robot.elevator_joint().kind)
+    {
+        commands
+            .entity(joints[elevator])
+            .insert(JointAssist::new(assist, axis));
+    }
 
-    let arm_joint = commands
-        .spawn(ImpulseJoint::new(carriage, joint_carriage_arm))
-        .set_parent(arm)
-        .id();
+    // Starting points for the controller gains
+    for (joint, load, unit) in [
+        (elevator, robot.elevator_gravity_load(), "m/s"),
+        (arm, robot.arm_gravity_torque(), "rad/s"),
+    ] {
+        if let Some(motor) = robot.motors.iter().find(|motor| motor.joint == joint) {
+            let motor = JointMotor::new(&robot.joints[joint].name, *motor, None);
+            let (kg, kv) = motor.feedforward_estimate(load);
+            println!(
+                "{} feedforward estimate: kG {:.3} V, kV {:.3} V/({})",
+                motor.name, kg, kv, unit
+            );
+        }
+    }
 
     commands.insert_resource(MotorJoints {
-        elevator: elevator_joint,
-        arm: arm_joint,
-        elevator_body: carriage,
-        arm_body: arm,
+        elevator: joints[elevator],
+        arm: joints[arm],
+        elevator_body: bodies[robot.joints[elevator].child],
+        arm_body: bodies[robot.joints[arm].child],
     });
+}
 
-    commands
-        .spawn(ImpulseJoint::new(arm, joint_arm_pivot))
-        .set_parent(intake_pivot);
-
-    commands
-        .spawn(ImpulseJoint::new(intake_pivot, joint_pivot_intake))
-        .set_parent(intake);
+/// Spawns the field elements the field config shows as fixed obstacles
+pub fn setup_field(mut commands: Commands, field: Option<Res<FieldConfig>>) {
+    let Some(field) = field else {
+        return;
+    };
+    for part in field.shown_parts() {
+        commands.spawn((
+            Name::new(part.name.clone()),
+            part.collider(),
+            part.transform(),
+            RigidBody::Fixed,
+            FieldConfig::groups(),
+            ActiveEvents::COLLISION_EVENTS,
+        ));
+    }
 }
@@ -0,0 +1,395 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+
+use crate::simulations::main::components::JointGrid;
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::presets::PresetPosition;
+
+const MAX_WAYPOINT_SPACING: usize = 6; // Grid cells between waypoints
+const WAYPOINT_HEIGHT_TOLERANCE: f32 = 1.5;
+const WAYPOINT_ANGLE_TOLERANCE: f32 = 4.0; // Degrees
+/// Paths are pushed away from poses with less clearance than this (sim
+/// units) when the joint grid has clearance values
+const PREFERRED_CLEARANCE: f32 = 7.5;
+/// Cost of a cell per sim unit of clearance short of the preferred
+/// clearance, relative to the cost of a one unit step
+const CLEARANCE_WEIGHT: f32 = 5.0;
+
+/// Waypoints the controller is following towards a preset
+#[derive(Resource, Default)]
+pub struct PlannedPath {
+    pub preset: Option<PresetPosition>,
+    pub waypoints: VecDeque<ArmPosition>,
+}
+
+impl PlannedPath {
+    /// Next pose to command, dropping intermediate waypoints already reached.
+    /// The final waypoint is kept so the goal stays commanded.
+    pub fn next_waypoint(&mut self, current: &ArmPosition) -> Option<ArmPosition> {
+        while self.waypoints.len() > 1 {
+            let next = self.waypoints[0];
+            let height_error = (next.height - current.height).abs();
+            let angle_error = wrap_angle(next.arm_angle - current.arm_angle).abs();
+            if height_error > WAYPOINT_HEIGHT_TOLERANCE
+                || angle_error > WAYPOINT_ANGLE_TOLERANCE.to_radians()
+            {
+                break;
+            }
+            self.waypoints.pop_front();
+        }
+        self.waypoints.front().copied()
+    }
+}
+
+/// Wraps an angle in radians to (-PI, PI]
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(TAU) - PI;
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+impl JointGrid {
+    /// Number of distinct angle columns. When the grid covers a full turn the
+    /// last column repeats the first one.
+    fn columns(&self) -> usize {
+        let width = self.grid[0].len();
+        if self.wraps() {
+            width - 1
+        } else {
+            width
+        }
+    }
+
+    fn wraps(&self) -> bool {
+        (self.max_angle - self.min_angle - 360.0).abs() < self.angle_step * 0.5
+    }
+
+    fn is_free(&self, x: i64, y: i64) -> bool {
+        let Some(x) = self.column(x) else {
+            return false;
+        };
+        y >= 0
+            && (y as usize) < self.grid.len()
+            && !self.grid[y as usize][x]
+            && self.clearance_of(x, y as usize) >= self.safety_margin.max(0.0)
+    }
+
+    /// Clearance at a cell, infinite without a clearance layer
+    fn clearance_of(&self, x: usize, y: usize) -> f32 {
+        self.clearance
+            .as_ref()
+            .map_or(f32::INFINITY, |clearance| clearance[y][x])
+    }
+
+    /// Extra cost of entering a cell close to a collision, so paths keep
+    /// their distance where they can
+    fn clearance_cost(&self, x: usize, y: usize) -> u32 {
+        let shortfall = (PREFERRED_CLEARANCE - self.clearance_of(x, y)).max(0.0);
+        (shortfall * CLEARANCE_WEIGHT * 100.0).round() as u32
+    }
+
+    fn column(&self, x: i64) -> Option<usize> {
+        let columns = self.columns() as i64;
+        if self.wraps() {
+            Some(x.rem_euclid(columns) as usize)
+        } else if (0..columns).contains(&x) {
+            Some(x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Nearest cell for a pose, if the pose lies inside the grid
+    fn cell_of(&self, pose: &ArmPosition) -> Option<(i64, i64)> {
+        let joint_height = pose.height + self.elevator_offset;
+        let margin = self.height_step * 0.5;
+        if joint_height < self.min_height - margin || joint_height > self.max_height + margin {
+            return None;
+        }
+
+        let mut angle = wrap_angle(pose.arm_angle).to_degrees();
+        if angle < self.min_angle {
+            angle += 360.0;
+        }
+        let x = ((angle - self.min_angle) / self.angle_step).round() as i64;
+        let y = ((joint_height - self.min_height) / self.height_step).round() as i64;
+        let y = y.clamp(0, self.grid.len() as i64 - 1);
+        Some((self.column(x)? as i64, y))
+    }
+
+    /// Pose at a (possibly unwrapped) cell coordinate
+    fn pose_at(&self, x: f32, y: f32) -> ArmPosition {
+        ArmPosition {
+            height: self.min_height + y * self.height_step - self.elevator_offset,
+            arm_angle: wrap_angle((self.min_angle + x * self.angle_step).to_radians()),
+        }
+    }
+
+    fn step_cost(&self, dx: i64, dy: i64) -> u32 {
+        let dx = dx as f32 * self.angle_step;
+        let dy = dy as f32 * self.height_step;
+        ((dx * dx + dy * dy).sqrt() * 100.0).round() as u32
+    }
+
+    fn heuristic(&self, from: (i64, i64), to: (i64, i64)) -> u32 {
+        let mut dx = (to.0 - from.0).abs();
+        if self.wraps() {
+            dx = dx.min(self.columns() as i64 - dx);
+        }
+        self.step_cost(dx, to.1 - from.1)
+    }
+
+    /// Marks the free cells whose pose `blocked` rejects as occupied and
+    /// returns how many there were. The grid only knows the robot, so this is
+    /// how paths are kept out of the field.
+    pub fn block(&mut self, blocked: impl Fn(&ArmPosition) -> bool) -> usize {
+        let mut count = 0;
+        for y in 0..self.grid.len() {
+            for x in 0..self.grid[y].len() {
+                if !self.grid[y][x] && blocked(&self.pose_at(x as f32, y as f32)) {
+                    self.grid[y][x] = true;
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Checks the straight joint-space segment between two unwrapped cells
+    fn segment_is_free(&self, from: (i64, i64), to: (i64, i64)) -> bool {
+        let samples = ((to.0 - from.0).abs().max((to.1 - from.1).abs()) * 2).max(1);
+        (0..=samples).all(|i| {
+            let t = i as f32 / samples as f32;
+            let x = from.0 as f32 + (to.0 - from.0) as f32 * t;
+            let y = from.1 as f32 + (to.1 - from.1) as f32 * t;
+            self.is_free(x.round() as i64, y.round() as i64)
+        })
+    }
+}
+
+/// A* search over the joint grid from the current pose to the goal. Returns
+/// waypoints ending exactly on the goal, or `None` if the goal is blocked or
+/// unreachable. The start cell is allowed to be occupied so the arm can always
+/// back out of a grazing contact.
+pub fn plan_path(
+    grid: &JointGrid,
+    start: &ArmPosition,
+    goal: &ArmPosition,
+) -> Option<Vec<ArmPosition>> {
+    let start_cell = grid.cell_of(start)?;
+    let goal_cell = grid.cell_of(goal)?;
+    if !grid.is_free(goal_cell.0, goal_cell.1) {
+        return None;
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<(i64, i64), (i64, i64)> = HashMap::new();
+    let mut cost_so_far: HashMap<(i64, i64), u32> = HashMap::new();
+    open.push(Reverse((grid.heuristic(start_cell, goal_cell), start_cell)));
+    cost_so_far.insert(start_cell, 0);
+
+    while let Some(Reverse((_, cell))) = open.pop() {
+        if cell == goal_cell {
+            break;
+        }
+        let cost = cost_so_far[&cell];
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if dx == 0 && dy == 0 || !grid.is_free(cell.0 + dx, cell.1 + dy) {
+                    continue;
+                }
+                let next = (grid.column(cell.0 + dx).unwrap() as i64, cell.1 + dy);
+                let next_cost = cost
+                    + grid.step_cost(dx, dy)
+                    + grid.clearance_cost(next.0 as usize, next.1 as usize);
+                if cost_so_far.get(&next).is_none_or(|&c| next_cost < c) {
+                    cost_so_far.insert(next, next_cost);
+                    came_from.insert(next, cell);
+                    open.push(Reverse((next_cost + grid.heuristic(next, goal_cell), next)));
+                }
+            }
+        }
+    }
+
+    if !cost_so_far.contains_key(&goal_cell) {
+        return None;
+    }
+
+    // Walk back to the start, then unwrap columns so steps across the
+    // +-180 degree seam stay continuous
+    let mut cells = vec![goal_cell];
+    while let Some(&previous) = came_from.get(cells.last().unwrap()) {
+        cells.push(previous);
+    }
+    cells.reverse();
+
+    let columns = grid.columns() as i64;
+    let mut unwrapped = vec![cells[0]];
+    for pair in cells.windows(2) {
+        let mut dx = pair[1].0 - pair[0].0;
+        if dx > 1 {
+            dx -= columns;
+        } else if dx < -1 {
+            dx += columns;
+        }
+        let last = *unwrapped.last().unwrap();
+        unwrapped.push((last.0 + dx, pair[1].1));
+    }
+
+    // Merge straight runs while keeping waypoints close enough that the two
+    // joints moving independently stay near the planned segment
+    let mut waypoints = Vec::new();
+    let mut anchor = 0;
+    while anchor < unwrapped.len() - 1 {
+        let mut next = anchor + 1;
+        while next + 1 < unwrapped.len()
+            && next + 1 - anchor <= MAX_WAYPOINT_SPACING
+            && grid.segment_is_free(unwrapped[anchor], unwrapped[next + 1])
+        {
+            next += 1;
+        }
+        let (x, y) = unwrapped[next];
+        waypoints.push(grid.pose_at(x as f32, y as f32));
+        anchor = next;
+    }
+
+    // Finish on the exact goal rather than its cell center
+    waypoints.pop();
+    waypoints.push(*goal);
+    Some(waypoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Joint grid over whole-degree and whole-unit steps with the elevator
+    /// joint at zero for preset height zero
+    fn grid(
+        cells: Vec<Vec<bool>>,
+        clearance: Option<Vec<Vec<f32>>>,
+        min_angle: f32,
+        angle_step: f32,
+    ) -> JointGrid {
+        let (width, height) = (cells[0].len(), cells.len());
+        JointGrid {
+            grid: cells,
+            clearance,
+            safety_margin: 0.0,
+            min_angle,
+            max_angle: min_angle + (width - 1) as f32 * angle_step,
+            min_height: 0.0,
+            max_height: (height - 1) as f32,
+            angle_step,
+            height_step: 1.0,
+            elevator_offset: 0.0,
+        }
+    }
+
+    fn pose(height: f32, degrees: f32) -> ArmPosition {
+        ArmPosition {
+            height,
+            arm_angle: degrees.to_radians(),
+        }
+    }
+
+    #[test]
+    fn routes_around_a_blocked_band() {
+        // Column 5 is blocked everywhere but the top two rows
+        let cells = (0..11)
+            .map(|y| (0..11).map(|x| x == 5 && y < 9).collect())
+            .collect();
+        let grid = grid(cells, None, 0.0, 10.0);
+
+        let path = plan_path(&grid, &pose(0.0, 0.0), &pose(0.0, 100.0)).unwrap();
+        let top = path.iter().map(|w| w.height).fold(0.0, f32::max);
+        assert!(top >= 9.0, "{:?}", path);
+        let last = path.last().unwrap();
+        assert_eq!((last.height, last.arm_angle), (0.0, 100f32.to_radians()));
+    }
+
+    #[test]
+    fn refuses_an_enclosed_goal() {
+        // Ring of blocked cells around (5, 5)
+        let cells = (0..11)
+            .map(|y: i32| {
+                (0..11)
+                    .map(|x: i32| (x - 5).abs().max((y - 5).abs()) == 1)
+                    .collect()
+            })
+            .collect();
+        let grid = grid(cells, None, 0.0, 10.0);
+
+        assert!(plan_path(&grid, &pose(0.0, 0.0), &pose(5.0, 50.0)).is_none());
+        assert!(plan_path(&grid, &pose(0.0, 0.0), &pose(0.0, 100.0)).is_some());
+    }
+
+    #[test]
+    fn wraps_around_at_180_degrees() {
+        // A full turn in 10 degree columns with 0 degrees blocked, so the
+        // only way from -30 to 30 degrees is through the back
+        let cells = (0..5).map(|_| (0..37).map(|x| x == 18).collect()).collect();
+        let grid = grid(cells, None, -180.0, 10.0);
+
+        let path = plan_path(&grid, &pose(2.0, -30.0), &pose(2.0, 30.0)).unwrap();
+        assert!(
+            path.iter()
+                .all(|w| w.arm_angle.abs() >= 30f32.to_radians() - 1e-4),
+            "{:?}",
+            path
+        );
+        assert!(
+            path.windows(2)
+                .any(|w| (w[1].arm_angle - w[0].arm_angle).abs() > PI),
+            "{:?}",
+            path
+        );
+
+        // Across the seam the short way stays near 180 degrees
+        let path = plan_path(&grid, &pose(2.0, 170.0), &pose(2.0, -170.0)).unwrap();
+        assert!(
+            path.iter()
+                .all(|w| w.arm_angle.abs() >= 160f32.to_radians()),
+            "{:?}",
+            path
+        );
+    }
+
+    #[test]
+    fn prefers_the_wider_corridor() {
+        // Columns 3 to 7 are blocked but for the bottom and top rows. The
+        // bottom corridor barely clears, the top one has room to spare.
+        let cells: Vec<Vec<bool>> = (0..11)
+            .map(|y| {
+                (0..11)
+                    .map(|x| (3..=7).contains(&x) && y > 0 && y < 10)
+                    .collect()
+            })
+            .collect();
+        let clearance = (0..11)
+            .map(|y| {
+                (0..11)
+                    .map(|x| {
+                        if (3..=7).contains(&x) && y == 0 {
+                            1.0
+                        } else {
+                            10.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let grid = grid(cells, Some(clearance), 0.0, 10.0);
+
+        let path = plan_path(&grid, &pose(5.0, 0.0), &pose(5.0, 100.0)).unwrap();
+        assert!(path.iter().all(|w| w.height > 0.0), "{:?}", path);
+        assert!(path.iter().any(|w| w.height == 10.0), "{:?}", path);
+    }
+}
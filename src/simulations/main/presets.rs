@@ -0,0 +1,312 @@
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::simulations::main::code_control::TargetPosition;
+use crate::simulations::robot::{SIM_UNITS_PER_INCH, SIM_UNITS_PER_METER};
+
+pub const PRESETS_FILE: &str = "presets.ron";
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A named arm pose. Height is the elevator travel above its lowest point in
+/// inches and angle is the arm angle in degrees, as written in the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresetPosition {
+    pub name: String,
+    pub height: f32,
+    pub angle: f32,
+    /// Bevy `KeyCode` name, e.g. "Digit1", "KeyS" or "ArrowLeft"
+    pub key: String,
+}
+
+impl PresetPosition {
+    /// Elevator travel in sim units
+    pub fn height(&self) -> f32 {
+        self.height * SIM_UNITS_PER_INCH
+    }
+
+    /// Arm angle in radians
+    pub fn angle(&self) -> f32 {
+        self.angle.to_radians()
+    }
+
+    pub fn key_code(&self) -> Option<KeyCode> {
+        parse_key_code(&self.key)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PresetFile {
+    presets: Vec<PresetPosition>,
+}
+
+#[derive(Resource)]
+pub struct Presets {
+    pub path: String,
+    pub presets: Vec<PresetPosition>,
+    modified: Option<SystemTime>,
+}
+
+impl Presets {
+    /// Loads presets from disk, falling back to the two original bottom
+    /// positions if the file is missing or invalid
+    pub fn load_or_default(path: &str) -> Self {
+        match Self::load(path) {
+            Ok(presets) => {
+                println!("Loaded {} presets from {}", presets.presets.len(), path);
+                presets
+            }
+            Err(e) => {
+                println!("Failed to load presets from {}: {}", path, e);
+                Self {
+                    path: path.to_string(),
+                    presets: default_presets(),
+                    modified: None,
+                }
+            }
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Ok(Self {
+            modified,
+            ..Self::parse(path, &contents)?
+        })
+    }
+
+    /// Presets from the contents of a presets file, `path` only names it
+    pub fn parse(path: &str, contents: &str) -> Result<Self, String> {
+        let file: PresetFile = ron::from_str(contents).map_err(|e| e.to_string())?;
+
+        if file.presets.is_empty() {
+            return Err("no presets defined".to_string());
+        }
+        for preset in &file.presets {
+            if preset.key_code().is_none() {
+                return Err(format!("unknown key {:?} for {}", preset.key, preset.name));
+            }
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            presets: file.presets,
+            modified: None,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PresetPosition> {
+        self.presets.iter().find(|preset| preset.name == name)
+    }
+
+    /// Preset the sim starts in: "Stow" if defined, otherwise the first one
+    pub fn initial(&self) -> &PresetPosition {
+        self.get("Stow").unwrap_or(&self.presets[0])
+    }
+
+    /// Writes the presets as a Java enum the robot project can include.
+    /// Heights are exported in meters and angles in degrees.
+    pub fn export_java(&self, path: &str) -> std::io::Result<()> {
+        let constants: Vec<String> = self
+            .presets
+            .iter()
+            .map(|preset| {
+                format!(
+                    "    {}({:.4}, {:.2})",
+                    java_constant_name(&preset.name),
+                    preset.height() / SIM_UNITS_PER_METER,
+                    preset.angle
+                )
+            })
+            .collect();
+
+        let source = format!(
+            "// Generated by frc_2025_arm_sim from {}
+// Do not edit by hand, change the presets file instead.
+
+public enum ArmPreset {{
+{};
+
+    public final double elevatorHeightMeters;
+    public final double armAngleDegrees;
+
+    ArmPreset(double elevatorHeightMeters, double armAngleDegrees) {{
+        this.elevatorHeightMeters = elevatorHeightMeters;
+        this.armAngleDegrees = armAngleDegrees;
+    }}
+}}
+",
+            self.path,
+            constants.join(",\n")
+        );
+
+        fs::write(path, source)
+    }
+}
+
+fn default_presets() -> Vec<PresetPosition> {
+    vec![
+        PresetPosition {
+            name: "BottomLeft".to_string(),
+            height: 0.0,
+            angle: 220.0,
+            key: "ArrowLeft".to_string(),
+        },
+        PresetPosition {
+            name: "BottomRight".to_string(),
+            height: 0.0,
+            angle: -40.0,
+            key: "ArrowRight".to_string(),
+        },
+    ]
+}
+
+/// "Coral Station" -> "CORAL_STATION"
+fn java_constant_name(name: &str) -> String {
+    let mut constant = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_alphanumeric() {
+            if c.is_uppercase() && i > 0 && !constant.ends_with('_') {
+                let previous = name.chars().nth(i - 1).unwrap_or(' ');
+                if previous.is_lowercase() {
+                    constant.push('_');
+                }
+            }
+            constant.push(c.to_ascii_uppercase());
+        } else if !constant.ends_with('_') {
+            constant.push('_');
+        }
+    }
+    if constant.starts_with(|c: char| c.is_ascii_digit()) {
+        constant.insert(0, '_');
+    }
+    constant
+}
+
+fn parse_key_code(name: &str) -> Option<KeyCode> {
+    let key = match name {
+        "ArrowLeft" => KeyCode::ArrowLeft,
+        "ArrowRight" => KeyCode::ArrowRight,
+        "ArrowUp" => KeyCode::ArrowUp,
+        "ArrowDown" => KeyCode::ArrowDown,
+        "Digit0" => KeyCode::Digit0,
+        "Digit1" => KeyCode::Digit1,
+        "Digit2" => KeyCode::Digit2,
+        "Digit3" => KeyCode::Digit3,
+        "Digit4" => KeyCode::Digit4,
+        "Digit5" => KeyCode::Digit5,
+        "Digit6" => KeyCode::Digit6,
+        "Digit7" => KeyCode::Digit7,
+        "Digit8" => KeyCode::Digit8,
+        "Digit9" => KeyCode::Digit9,
+        "KeyA" => KeyCode::KeyA,
+        "KeyB" => KeyCode::KeyB,
+        "KeyC" => KeyCode::KeyC,
+        "KeyD" => KeyCode::KeyD,
+        "KeyE" => KeyCode::KeyE,
+        "KeyF" => KeyCode::KeyF,
+        "KeyG" => KeyCode::KeyG,
+        "KeyH" => KeyCode::KeyH,
+        "KeyI" => KeyCode::KeyI,
+        "KeyJ" => KeyCode::KeyJ,
+        "KeyK" => KeyCode::KeyK,
+        "KeyL" => KeyCode::KeyL,
+        "KeyM" => KeyCode::KeyM,
+        "KeyN" => KeyCode::KeyN,
+        "KeyO" => KeyCode::KeyO,
+        "KeyP" => KeyCode::KeyP,
+        "KeyQ" => KeyCode::KeyQ,
+        "KeyR" => KeyCode::KeyR,
+        "KeyS" => KeyCode::KeyS,
+        "KeyT" => KeyCode::KeyT,
+        "KeyU" => KeyCode::KeyU,
+        "KeyV" => KeyCode::KeyV,
+        "KeyW" => KeyCode::KeyW,
+        "KeyX" => KeyCode::KeyX,
+        "KeyY" => KeyCode::KeyY,
+        "KeyZ" => KeyCode::KeyZ,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Polls the presets file and reloads it when it changes on disk. The active
+/// target picks up new values for a preset with the same name.
+pub fn reload_presets(
+    time: Res<Time>,
+    mut elapsed: Local<Duration>,
+    mut presets: ResMut<Presets>,
+    mut target: ResMut<TargetPosition>,
+) {
+    *elapsed += time.delta();
+    if *elapsed < RELOAD_INTERVAL {
+        return;
+    }
+    *elapsed = Duration::ZERO;
+
+    let modified = fs::metadata(&presets.path).and_then(|m| m.modified()).ok();
+    if modified.is_none() || modified == presets.modified {
+        return;
+    }
+
+    match Presets::load(&presets.path) {
+        Ok(reloaded) => {
+            println!("Reloaded {} presets", reloaded.presets.len());
+            if let Some(updated) = reloaded.get(&target.current.name) {
+                if *updated != target.current {
+                    target.current = updated.clone();
+                }
+            }
+            *presets = reloaded;
+        }
+        Err(e) => {
+            println!("Failed to reload presets, keeping previous: {}", e);
+            presets.modified = modified;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_inches_on_load_and_exports_meters() {
+        let presets = Presets::parse(
+            "presets.ron",
+            r#"(
+                presets: [
+                    (name: "Coral Station", height: 0.0, angle: 205.0, key: "ArrowLeft"),
+                    (name: "L4", height: 17.717, angle: 55.0, key: "Digit4"),
+                ],
+            )"#,
+        )
+        .unwrap();
+        let l4 = presets.get("L4").unwrap();
+        assert!((l4.height() - 17.717 * SIM_UNITS_PER_INCH).abs() < 1e-4);
+
+        let path = std::env::temp_dir().join(format!(
+            "frc_2025_arm_sim_presets_{}.java",
+            std::process::id()
+        ));
+        presets.export_java(path.to_str().unwrap()).unwrap();
+        let source = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(source.contains("    L4(0.4500, 55.00)"), "{}", source);
+        assert!(source.contains("    CORAL_STATION(0.0000, 205.00)"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let error = Presets::parse(
+            "presets.ron",
+            r#"(presets: [(name: "Stow", height: 0.0, angle: 0.0, key: "NotAKey")])"#,
+        )
+        .err()
+        .unwrap();
+        assert!(error.contains("NotAKey"), "{}", error);
+    }
+}
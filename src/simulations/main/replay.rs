@@ -0,0 +1,573 @@
+//! Plays back elevator and arm positions recorded by a robot in a WPILOG
+//! file. The links are posed kinematically along the log's timeline with the
+//! collision grid drawn underneath, so near misses from real matches can be
+//! found and stepped through.
+
+use std::f32::consts::{PI, TAU};
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::simulations::main::components::*;
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::sweep::ContactCheck;
+use crate::simulations::robot::{RobotConfig, SIM_UNITS_PER_INCH, SIM_UNITS_PER_METER};
+use crate::simulations::wpilog::{LogEntry, WpiLog};
+
+/// Entries matched by name suffix when none are given, which finds both the
+/// sim's own telemetry log and robot code logging under the same names
+const ELEVATOR_SUFFIX: &str = "Elevator/Position";
+const ARM_SUFFIX: &str = "Arm/Angle";
+/// Arm tip clearance from the collision grid reported as a near miss
+const NEAR_MISS_DISTANCE: f32 = 3.0 * SIM_UNITS_PER_INCH;
+/// Time between poses checked when the log is loaded
+const ANALYSIS_STEP: f64 = 0.02;
+const SCRUB_STEP: f64 = 1.0;
+const LONG_SCRUB_STEP: f64 = 10.0;
+const MIN_SPEED: f64 = 1.0 / 16.0;
+const MAX_SPEED: f64 = 16.0;
+
+pub struct ReplayOptions {
+    pub path: PathBuf,
+    pub robot_config: String,
+    pub grid_file: String,
+    /// Elevator height entry, meters above the lowest point
+    pub elevator_entry: Option<String>,
+    /// Arm angle entry, radians from horizontal
+    pub arm_entry: Option<String>,
+}
+
+/// Samples of one logged value, in seconds
+struct Series {
+    samples: Vec<(f64, f32)>,
+    /// Interpolate the short way around, for wrapped angles
+    wraps: bool,
+}
+
+impl Series {
+    fn from_entry(entry: &LogEntry, wraps: bool) -> Result<Self, String> {
+        let mut samples: Vec<(f64, f32)> = entry
+            .numbers()
+            .ok_or_else(|| format!("{} is a {}, not a number", entry.name, entry.type_name))?
+            .into_iter()
+            .map(|(time, value)| (time, value as f32))
+            .collect();
+        if samples.is_empty() {
+            return Err(format!("{} has no values", entry.name));
+        }
+
+        // Records aren't guaranteed to be in time order. Where several share
+        // a timestamp the last one logged wins, so no interval is zero long.
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        samples.dedup_by(|later, earlier| {
+            let same_time = later.0 == earlier.0;
+            if same_time {
+                *earlier = *later;
+            }
+            same_time
+        });
+        Ok(Self { samples, wraps })
+    }
+
+    /// Linearly interpolated value, holding the first and last samples
+    fn at(&self, time: f64) -> f32 {
+        let next = self.samples.partition_point(|&(t, _)| t <= time);
+        if next == 0 {
+            return self.samples[0].1;
+        }
+        if next == self.samples.len() {
+            return self.samples[next - 1].1;
+        }
+        let (t0, v0) = self.samples[next - 1];
+        let (t1, v1) = self.samples[next];
+        let mut delta = v1 - v0;
+        if self.wraps {
+            delta = (delta + PI).rem_euclid(TAU) - PI;
+        }
+        v0 + delta * ((time - t0) / (t1 - t0)) as f32
+    }
+}
+
+/// Something worth jumping to in the log
+struct ReplayEvent {
+    time: f64,
+    description: String,
+}
+
+#[derive(Resource)]
+pub struct Replay {
+    elevator: Series,
+    arm: Series,
+    start: f64,
+    end: f64,
+    time: f64,
+    speed: f64,
+    playing: bool,
+    events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    fn load(options: &ReplayOptions) -> Result<Self, String> {
+        let log = WpiLog::read(&options.path).map_err(|e| e.to_string())?;
+        let elevator = find_entry(&log, options.elevator_entry.as_deref(), ELEVATOR_SUFFIX)?;
+        let arm = find_entry(&log, options.arm_entry.as_deref(), ARM_SUFFIX)?;
+        println!("Replaying {} and {}", elevator.name, arm.name);
+
+        let elevator = Series::from_entry(elevator, false)?;
+        let arm = Series::from_entry(arm, true)?;
+        let start = elevator.samples[0].0.min(arm.samples[0].0);
+        let end = elevator.samples[elevator.samples.len() - 1]
+            .0
+            .max(arm.samples[arm.samples.len() - 1].0);
+        Ok(Self {
+            elevator,
+            arm,
+            start,
+            end,
+            time: start,
+            speed: 1.0,
+            playing: true,
+            events: Vec::new(),
+        })
+    }
+
+    /// Arm pose at a time in the log
+    fn pose_at(&self, time: f64) -> ArmPosition {
+        ArmPosition {
+            height: self.elevator.at(time) * SIM_UNITS_PER_METER,
+            arm_angle: self.arm.at(time),
+        }
+    }
+
+    /// Steps through the whole log recording link contacts and arm tip near
+    /// misses against the collision grid
+    fn find_events(
+        &mut self,
+        robot: &RobotConfig,
+        contact_check: &ContactCheck,
+        grid: Option<&CollisionGrid>,
+    ) {
+        let mut contacts: Vec<(usize, usize)> = Vec::new();
+        // Closest approach of the near miss in progress
+        let mut near_miss: Option<(f64, f32)> = None;
+        let steps = ((self.end - self.start) / ANALYSIS_STEP).ceil() as usize;
+        for step in 0..=steps {
+            let time = (self.start + step as f64 * ANALYSIS_STEP).min(self.end);
+            let pose = self.pose_at(time);
+            let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+
+            let touching = contact_check.touching(&poses);
+            for &(a, b) in touching.iter().filter(|pair| !contacts.contains(pair)) {
+                self.events.push(ReplayEvent {
+                    time,
+                    description: format!(
+                        "{} hit {}",
+                        contact_check.names[a], contact_check.names[b]
+                    ),
+                });
+            }
+            contacts = touching;
+
+            let distance = grid
+                .and_then(|grid| {
+                    grid.distance_to_collision(
+                        robot.arm_tip(pose.joint_height(robot), pose.arm_angle),
+                    )
+                })
+                .filter(|&distance| distance < NEAR_MISS_DISTANCE);
+            near_miss = match (near_miss, distance) {
+                (Some(closest), Some(distance)) if distance >= closest.1 => Some(closest),
+                (_, Some(distance)) => Some((time, distance)),
+                (Some((time, distance)), None) => {
+                    self.events.push(ReplayEvent {
+                        time,
+                        description: near_miss_description(distance),
+                    });
+                    None
+                }
+                (None, None) => None,
+            };
+        }
+        if let Some((time, distance)) = near_miss {
+            self.events.push(ReplayEvent {
+                time,
+                description: near_miss_description(distance),
+            });
+        }
+        self.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    fn seek(&mut self, time: f64) {
+        self.time = time.clamp(self.start, self.end);
+    }
+}
+
+fn near_miss_description(distance: f32) -> String {
+    if distance <= 0.0 {
+        "Arm tip in the collision grid".to_string()
+    } else {
+        format!(
+            "Arm tip {:.1} in from the collision grid",
+            distance / SIM_UNITS_PER_INCH
+        )
+    }
+}
+
+/// Entry by exact name, or the first numeric entry ending in `suffix`
+fn find_entry<'a>(
+    log: &'a WpiLog,
+    name: Option<&str>,
+    suffix: &str,
+) -> Result<&'a LogEntry, String> {
+    match name {
+        Some(name) => log
+            .entry(name)
+            .ok_or_else(|| format!("the log has no entry named {}", name)),
+        None => log
+            .entries
+            .iter()
+            .find(|entry| entry.name.ends_with(suffix) && entry.numbers().is_some())
+            .ok_or_else(|| format!("the log has no numeric entry ending in {}", suffix)),
+    }
+}
+
+/// Link drawn at its replayed pose, by index into the robot's links
+#[derive(Component)]
+struct ReplayLink(usize);
+
+#[derive(Component)]
+struct ReplayText;
+
+#[derive(Resource)]
+struct ContactMaterials {
+    normal: Handle<ColorMaterial>,
+    contact: Handle<ColorMaterial>,
+}
+
+pub fn run_replay(options: ReplayOptions) -> Option<App> {
+    let robot = RobotConfig::load_or_default(&options.robot_config);
+    let mut replay = match Replay::load(&options) {
+        Ok(replay) => replay,
+        Err(e) => {
+            println!("Failed to load {}: {}", options.path.display(), e);
+            return None;
+        }
+    };
+
+    let grid = match CollisionGrid::load_from_file(&options.grid_file, &robot) {
+        Ok(grid) => Some(grid),
+        Err(e) => {
+            println!(
+                "Failed to load collision grid, near misses will not be found: {}",
+                e
+            );
+            None
+        }
+    };
+    let contact_check = ContactCheck::new(&robot);
+    replay.find_events(&robot, &contact_check, grid.as_ref());
+    println!(
+        "{:.1} s of log, {} events",
+        replay.end - replay.start,
+        replay.events.len()
+    );
+    for event in &replay.events {
+        println!("  {:>8.2} s  {}", event.time, event.description);
+    }
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins)
+        .insert_resource(robot)
+        .insert_resource(replay)
+        .insert_resource(contact_check)
+        .add_systems(Startup, (setup_replay, setup_replay_text))
+        .add_systems(
+            Update,
+            (
+                replay_controls,
+                advance_replay.after(replay_controls),
+                pose_replay_links.after(advance_replay),
+                update_replay_text.after(advance_replay),
+            ),
+        );
+    if let Some(grid) = grid {
+        app.insert_resource(grid);
+    }
+    Some(app)
+}
+
+fn setup_replay(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    robot: Res<RobotConfig>,
+    grid: Option<Res<CollisionGrid>>,
+) {
+    commands.spawn((
+        Camera2d,
+        OrthographicProjection {
+            scale: 0.2,
+            ..OrthographicProjection::default_2d()
+        },
+    ));
+
+    let materials_by_state = ContactMaterials {
+        normal: materials.add(Color::linear_rgb(0.6, 0.6, 0.6)),
+        contact: materials.add(Color::linear_rgb(0.9, 0.2, 0.2)),
+    };
+    let poses = robot.forward_kinematics(0.0, 0.0);
+    for (i, link) in robot.links.iter().enumerate() {
+        commands.spawn((
+            Name::new(link.name.clone()),
+            Mesh2d(meshes.add(link.shape.mesh())),
+            MeshMaterial2d(materials_by_state.normal.clone()),
+            poses[i].transform(),
+            ReplayLink(i),
+        ));
+    }
+    for part in robot.chassis_parts() {
+        commands.spawn((
+            Name::new(part.name.clone()),
+            Mesh2d(meshes.add(part.shape.mesh())),
+            MeshMaterial2d(materials_by_state.normal.clone()),
+            part.pose.transform(),
+        ));
+    }
+    commands.insert_resource(materials_by_state);
+
+    // Colliding arm tip positions and those breaking the limits, under the
+    // robot
+    let Some(grid) = grid else {
+        return;
+    };
+    let cell = meshes.add(Rectangle::new(grid.step_size, grid.step_size));
+    let cell_material = materials.add(Color::linear_rgba(0.8, 0.2, 0.2, 0.3));
+    // Tips that break the extension or height limits but don't collide
+    let rules_material = materials.add(Color::linear_rgba(0.9, 0.8, 0.1, 0.3));
+    for (row, cells) in grid.grid.iter().enumerate() {
+        for (column, &collides) in cells.iter().enumerate() {
+            let breaks_rules = grid.rules.as_ref().is_some_and(|rules| rules[row][column]);
+            let material = if collides {
+                &cell_material
+            } else if breaks_rules {
+                &rules_material
+            } else {
+                continue;
+            };
+            let center = grid.cell_center(row, column);
+            commands.spawn((
+                Mesh2d(cell.clone()),
+                MeshMaterial2d(material.clone()),
+                Transform::from_xyz(center.x, center.y, -1.0),
+            ));
+        }
+    }
+}
+
+fn setup_replay_text(mut commands: Commands) {
+    commands.spawn((
+        Text::new(""),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        },
+        ReplayText,
+    ));
+}
+
+/// Space plays and pauses, left and right scrub (further with shift), up and
+/// down change the speed, N jumps to the next event and Home restarts
+fn replay_controls(keys: Res<ButtonInput<KeyCode>>, mut replay: ResMut<Replay>) {
+    if keys.just_pressed(KeyCode::Space) {
+        if replay.time >= replay.end {
+            replay.time = replay.start;
+        }
+        replay.playing = !replay.playing;
+    }
+
+    let step = if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        LONG_SCRUB_STEP
+    } else {
+        SCRUB_STEP
+    };
+    if keys.just_pressed(KeyCode::ArrowRight) {
+        let time = replay.time + step;
+        replay.seek(time);
+    }
+    if keys.just_pressed(KeyCode::ArrowLeft) {
+        let time = replay.time - step;
+        replay.seek(time);
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        let start = replay.start;
+        replay.seek(start);
+    }
+
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        replay.speed = (replay.speed * 2.0).min(MAX_SPEED);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        replay.speed = (replay.speed / 2.0).max(MIN_SPEED);
+    }
+
+    if keys.just_pressed(KeyCode::KeyN) {
+        // Wraps around to the first event after the last
+        let next = replay
+            .events
+            .iter()
+            .find(|event| event.time > replay.time + 1e-6)
+            .or(replay.events.first())
+            .map(|event| event.time);
+        if let Some(time) = next {
+            replay.seek(time);
+            replay.playing = false;
+        }
+    }
+}
+
+fn advance_replay(time: Res<Time>, mut replay: ResMut<Replay>) {
+    if !replay.playing {
+        return;
+    }
+    let next = replay.time + time.delta_secs_f64() * replay.speed;
+    replay.seek(next);
+    if replay.time >= replay.end {
+        replay.playing = false;
+    }
+}
+
+fn pose_replay_links(
+    replay: Res<Replay>,
+    robot: Res<RobotConfig>,
+    contact_check: Res<ContactCheck>,
+    materials: Res<ContactMaterials>,
+    mut links: Query<(
+        &ReplayLink,
+        &mut Transform,
+        &mut MeshMaterial2d<ColorMaterial>,
+    )>,
+) {
+    let pose = replay.pose_at(replay.time);
+    let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+    let touching = contact_check.touching(&poses);
+
+    for (link, mut transform, mut material) in links.iter_mut() {
+        *transform = poses[link.0].transform();
+        let in_contact = touching.iter().any(|&(a, b)| a == link.0 || b == link.0);
+        material.0 = if in_contact {
+            materials.contact.clone()
+        } else {
+            materials.normal.clone()
+        };
+    }
+}
+
+fn update_replay_text(
+    replay: Res<Replay>,
+    robot: Res<RobotConfig>,
+    grid: Option<Res<CollisionGrid>>,
+    mut text_query: Query<&mut Text, With<ReplayText>>,
+) {
+    let pose = replay.pose_at(replay.time);
+    let clearance = match grid.as_deref().and_then(|grid| {
+        grid.distance_to_collision(robot.arm_tip(pose.joint_height(&robot), pose.arm_angle))
+    }) {
+        Some(distance) => format!("{:.1} in", distance / SIM_UNITS_PER_INCH),
+        None => "unknown".to_string(),
+    };
+    let last_event = replay
+        .events
+        .iter()
+        .rev()
+        .find(|event| event.time <= replay.time + 1e-6)
+        .map_or(String::new(), |event| {
+            format!("\nLast event: {:.2} s {}", event.time, event.description)
+        });
+
+    for mut text in text_query.iter_mut() {
+        text.0 = format!(
+            "{:.2} s / {:.2} s  x{}  {}\nElevator {:.3} m  Arm {:.1} deg  Clearance {}{}",
+            replay.time,
+            replay.end,
+            replay.speed,
+            if replay.playing { "playing" } else { "paused" },
+            replay.elevator.at(replay.time),
+            replay.arm.at(replay.time).to_degrees(),
+            clearance,
+            last_event
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulations::wpilog::WpiLogWriter;
+
+    #[test]
+    fn replays_a_log_written_by_the_sim() {
+        let path = std::env::temp_dir().join(format!(
+            "frc_2025_arm_sim_replay_{}.wpilog",
+            std::process::id()
+        ));
+        {
+            let mut log = WpiLogWriter::create(&path, "").unwrap();
+            // Not a number, so the suffix search passes over it
+            let text = log.start_entry("/Notes/Arm/Angle", "string", 0).unwrap();
+            let height = log
+                .start_entry("/Sim/Elevator/Position", "double", 0)
+                .unwrap();
+            let angle = log.start_entry("/Sim/Arm/Angle", "double", 0).unwrap();
+            log.append_string(text, 0, "ignored").unwrap();
+            log.append_double(height, 1_000_000, 0.0).unwrap();
+            log.append_double(height, 2_000_000, 0.5).unwrap();
+            log.append_double(angle, 1_000_000, std::f64::consts::PI - 0.1)
+                .unwrap();
+            log.append_double(angle, 3_000_000, 0.1 - std::f64::consts::PI)
+                .unwrap();
+            log.flush().unwrap();
+        }
+        let replay = Replay::load(&ReplayOptions {
+            path: path.clone(),
+            robot_config: String::new(),
+            grid_file: String::new(),
+            elevator_entry: None,
+            arm_entry: None,
+        });
+        let _ = std::fs::remove_file(&path);
+        let replay = replay.unwrap();
+
+        assert_eq!((replay.start, replay.end), (1.0, 3.0));
+        let pose = replay.pose_at(1.5);
+        assert!((pose.height - 0.25 * SIM_UNITS_PER_METER).abs() < 1e-3);
+        // Halfway between the samples is through the back, not the front
+        assert!((replay.pose_at(2.0).arm_angle.abs() - PI).abs() < 1e-3);
+        assert!((replay.pose_at(5.0).height - 0.5 * SIM_UNITS_PER_METER).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sorts_samples_and_keeps_the_last_at_a_timestamp() {
+        let entry = LogEntry {
+            name: "/Sim/Elevator/Position".to_string(),
+            type_name: "double".to_string(),
+            metadata: String::new(),
+            records: [
+                (3_000_000, 3.0),
+                (1_000_000, 1.0),
+                (2_000_000, 0.0),
+                (2_000_000, 2.0),
+            ]
+            .into_iter()
+            .map(|(timestamp, value): (u64, f64)| (timestamp, value.to_le_bytes().to_vec()))
+            .collect(),
+        };
+        let series = Series::from_entry(&entry, false).unwrap();
+
+        assert_eq!(series.samples, [(1.0, 1.0), (2.0, 2.0), (3.0, 3.0)]);
+        assert_eq!(series.at(1.5), 1.5);
+        assert_eq!(series.at(2.0), 2.0);
+        assert_eq!(series.at(2.5), 2.5);
+    }
+}
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+
+use crate::simulations::main::code_control::current_arm_position;
+use crate::simulations::main::components::*;
+use crate::simulations::main::game_pieces::GamePieceKind;
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::robot::RobotConfig;
+use crate::simulations::rules::describe;
+
+/// Reason the last commanded pose was changed or refused, shown on screen
+#[derive(Resource, Default, PartialEq)]
+pub struct SafetyWarning(pub Option<String>);
+
+/// Extension or height limits the arm breaks right now, shown on screen
+#[derive(Resource, Default, PartialEq)]
+pub struct RulesViolation(pub Option<String>);
+
+#[derive(Component)]
+pub struct SafetyWarningText;
+
+pub enum TargetCheck {
+    Safe,
+    Clamped(ArmPosition),
+    Refused,
+}
+
+/// Checks a commanded pose against the collision grid, the extension and
+/// height limits when they are enforced and, while the intake holds a game
+/// piece, the piece against the robot. Without a grid the grid check is
+/// skipped.
+pub fn check_target(
+    grid: Option<&CollisionGrid>,
+    robot: &RobotConfig,
+    target: &ArmPosition,
+    held: Option<GamePieceKind>,
+) -> TargetCheck {
+    let is_safe = |pose: &ArmPosition| {
+        grid.is_none_or(|grid| pose.validate_with_grid(grid, robot))
+            && robot
+                .rules
+                .filter(|rules| rules.enforce)
+                .is_none_or(|rules| {
+                    rules
+                        .check_pose(robot, pose.height, pose.arm_angle)
+                        .is_empty()
+                })
+            && held.is_none_or(|piece| !piece.collides_at(robot, pose))
+    };
+
+    if is_safe(target) {
+        TargetCheck::Safe
+    } else if let Some(clamped) = target.nearest_safe(is_safe) {
+        TargetCheck::Clamped(clamped)
+    } else {
+        TargetCheck::Refused
+    }
+}
+
+pub fn setup_safety_text(mut commands: Commands) {
+    commands.spawn((
+        Text::new(""),
+        TextColor(Color::linear_rgb(1.0, 0.3, 0.1)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        },
+        SafetyWarningText,
+    ));
+}
+
+/// Flags the extension and height limits the arm breaks at its current pose
+pub fn flag_rule_violations(
+    mut violation: ResMut<RulesViolation>,
+    transforms: Query<&Transform>,
+    motor_joints: Option<Res<MotorJoints>>,
+    robot: Res<RobotConfig>,
+) {
+    let (Some(rules), Some(motor_joints)) = (robot.rules, motor_joints) else {
+        return;
+    };
+    let Some(current) = current_arm_position(&transforms, &motor_joints, &robot) else {
+        return;
+    };
+    let violations = rules.check_pose(&robot, current.height, current.arm_angle);
+    let message = (!violations.is_empty()).then(|| format!("Arm is {}", describe(&violations)));
+    if violation.0.is_none() {
+        if let Some(message) = &message {
+            println!("{}", message);
+        }
+    }
+    violation.set_if_neq(RulesViolation(message));
+}
+
+pub fn update_safety_text(
+    warning: Res<SafetyWarning>,
+    violation: Res<RulesViolation>,
+    mut text_query: Query<&mut Text, With<SafetyWarningText>>,
+) {
+    if !warning.is_changed() && !violation.is_changed() {
+        return;
+    }
+
+    let lines: Vec<&str> = [&warning.0, &violation.0]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    for mut text in text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
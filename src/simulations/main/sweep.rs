@@ -0,0 +1,460 @@
+//! Swept collision checks of a move between two arm poses. The move is
+//! sampled densely enough that no point on the robot travels more than
+//! `SWEEP_STEP` between exact shape tests, and the first contact found is
+//! narrowed down by bisection, so a move can be proven clear before it is
+//! commanded.
+
+use bevy::prelude::*;
+use bevy_rapier2d::parry::query;
+use bevy_rapier2d::prelude::*;
+
+use crate::simulations::field::{FieldConfig, FieldPart};
+use crate::simulations::main::components::JointGrid;
+use crate::simulations::main::control::{State, TrapezoidProfile};
+use crate::simulations::main::controllers::ArmControllers;
+use crate::simulations::main::game_pieces::{piece_groups, GamePieceKind};
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::planner::wrap_angle;
+use crate::simulations::robot::{
+    groups_interact, LinkPose, RobotConfig, SIM_UNITS_PER_INCH, SIM_UNITS_PER_METER,
+};
+
+/// Furthest any point on the robot moves between two tests, in sim units
+const SWEEP_STEP: f32 = 0.5;
+/// Halvings of the interval around the first contact
+const BISECTION_STEPS: usize = 16;
+
+/// How the joints get from the start pose to the end pose. The arm takes the
+/// short way round, like the controllers do.
+#[derive(Debug, Clone, Copy)]
+pub enum Profile {
+    /// Both joints move at a constant speed and arrive together. Time runs
+    /// from 0 at the start to 1 at the end.
+    Linear,
+    /// Each joint follows its own trapezoid profile from rest to rest, the
+    /// way the controllers drive it. Time is in seconds, the elevator profile
+    /// in meters and the arm profile in radians.
+    Trapezoid {
+        elevator: TrapezoidProfile,
+        arm: TrapezoidProfile,
+    },
+}
+
+impl Profile {
+    /// The profiles the controllers follow with their current gains
+    pub fn of_controllers(controllers: &ArmControllers) -> Self {
+        Self::Trapezoid {
+            elevator: controllers.elevator.profile(),
+            arm: controllers.arm.profile(),
+        }
+    }
+
+    /// Time the move takes
+    pub fn duration(&self, from: &ArmPosition, to: &ArmPosition) -> f32 {
+        match self {
+            Self::Linear => 1.0,
+            Self::Trapezoid { elevator, arm } => elevator
+                .total_time((to.height - from.height).abs() / SIM_UNITS_PER_METER)
+                .max(arm.total_time(wrap_angle(to.arm_angle - from.arm_angle).abs())),
+        }
+    }
+
+    /// Pose `time` into the move, holding the end pose after it finishes
+    pub fn pose_at(&self, from: &ArmPosition, to: &ArmPosition, time: f32) -> ArmPosition {
+        let turn = wrap_angle(to.arm_angle - from.arm_angle);
+        let (height, arm_angle) = match self {
+            Self::Linear => {
+                let t = time.clamp(0.0, 1.0);
+                (
+                    from.height + (to.height - from.height) * t,
+                    from.arm_angle + turn * t,
+                )
+            }
+            Self::Trapezoid { elevator, arm } => {
+                let at_rest = |position| State {
+                    position,
+                    velocity: 0.0,
+                };
+                let height = elevator
+                    .calculate(
+                        time,
+                        at_rest(from.height / SIM_UNITS_PER_METER),
+                        at_rest(to.height / SIM_UNITS_PER_METER),
+                    )
+                    .position
+                    * SIM_UNITS_PER_METER;
+                let arm_angle = arm
+                    .calculate(
+                        time,
+                        at_rest(from.arm_angle),
+                        at_rest(from.arm_angle + turn),
+                    )
+                    .position;
+                (height, arm_angle)
+            }
+        };
+        ArmPosition {
+            height,
+            arm_angle: wrap_angle(arm_angle),
+        }
+    }
+
+    /// Top speed of the elevator in sim units and of the arm in radians, per
+    /// unit of time
+    fn peak_speeds(&self, from: &ArmPosition, to: &ArmPosition) -> (f32, f32) {
+        match self {
+            Self::Linear => (
+                (to.height - from.height).abs(),
+                wrap_angle(to.arm_angle - from.arm_angle).abs(),
+            ),
+            Self::Trapezoid { elevator, arm } => (
+                elevator.max_velocity * SIM_UNITS_PER_METER,
+                arm.max_velocity,
+            ),
+        }
+    }
+
+    /// Time into a move for a report, e.g. "0.42 s in" or "35% of the way"
+    pub fn describe_time(&self, time: f32) -> String {
+        match self {
+            Self::Linear => format!("{:.0}% of the way", time * 100.0),
+            Self::Trapezoid { .. } => format!("{:.2} s in", time),
+        }
+    }
+}
+
+/// First new contact during a move
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    /// Time into the move, in the profile's unit
+    pub time: f32,
+    pub pose: ArmPosition,
+    /// Bodies that touch, indexed like `ContactCheck::names`
+    pub bodies: (usize, usize),
+}
+
+/// Tests the same pairs as the joint-space sweep: links whose groups
+/// interact, and every moving link against the elevator and the chassis.
+/// Field parts and a carried piece can be added on top. Bodies are the links,
+/// then the chassis and field parts, then the carried piece.
+#[derive(Resource)]
+pub struct ContactCheck {
+    pub names: Vec<String>,
+    shapes: Vec<Collider>,
+    /// Chassis parts followed by field parts
+    fixed_poses: Vec<LinkPose>,
+    /// First field part or carried piece, the bodies the joint grid misses
+    first_added: usize,
+    /// Intake link and where it holds the carried piece in its frame
+    carried: Option<(usize, Vec2)>,
+    pairs: Vec<(usize, usize)>,
+    /// Furthest any link corner is from the arm pivot, which bounds how fast
+    /// a point on the robot moves as the arm turns
+    reach: f32,
+}
+
+impl ContactCheck {
+    pub fn new(robot: &RobotConfig) -> Self {
+        Self::with_field(robot, &[], None)
+    }
+
+    /// Also tests every moving link against the field parts, and the piece
+    /// the intake carries against everything it collides with. The joint
+    /// grid knows nothing of the field, so this is what keeps a planned path
+    /// out of it.
+    pub fn with_field(
+        robot: &RobotConfig,
+        field: &[FieldPart],
+        carried: Option<GamePieceKind>,
+    ) -> Self {
+        let chassis = robot.chassis_parts();
+        let links = robot.links.len();
+        let mut pairs = robot.swept_pairs();
+        let moving = (0..links).filter(|&link| link != robot.base);
+        for part in 0..field.len() {
+            let body = links + chassis.len() + part;
+            pairs.extend(moving.clone().map(|link| (link, body)));
+        }
+
+        let poses = robot.forward_kinematics(0.0, 0.0);
+        let arm = robot.arm_joint();
+        let pivot = poses[arm.child].transform_point(arm.child_anchor);
+        let mut reach = poses
+            .boxes
+            .iter()
+            .flat_map(|obb| obb.corners())
+            .map(|corner| corner.distance(pivot))
+            .fold(0.0, f32::max);
+
+        let mut names: Vec<_> = robot
+            .links
+            .iter()
+            .map(|link| link.name.clone())
+            .chain(chassis.iter().map(|part| part.name.clone()))
+            .chain(field.iter().map(|part| part.name.clone()))
+            .collect();
+        let mut shapes: Vec<_> = robot
+            .links
+            .iter()
+            .map(|link| link.shape.collider())
+            .chain(chassis.iter().map(|part| part.shape.collider()))
+            .chain(field.iter().map(FieldPart::collider))
+            .collect();
+        let mut fixed_poses: Vec<_> = chassis.iter().map(|part| part.pose).collect();
+        fixed_poses.extend(field.iter().map(|part| LinkPose {
+            translation: part.position(),
+            rotation: 0.0,
+        }));
+
+        let carried = carried.map(|piece| {
+            let intake = robot.intake_index();
+            let body = names.len();
+            let link_groups = robot.links.iter().map(|link| link.groups);
+            let fixed_groups = chassis
+                .iter()
+                .map(|part| part.groups)
+                .chain(field.iter().map(|_| FieldConfig::groups()));
+            pairs.extend(
+                link_groups
+                    .chain(fixed_groups)
+                    .enumerate()
+                    .filter(|&(other, groups)| {
+                        other != intake && groups_interact(piece_groups(), groups)
+                    })
+                    .map(|(other, _)| (other, body)),
+            );
+
+            let offset = piece.hold_offset(robot);
+            let collider = piece.collider();
+            let center = poses[intake].transform_point(offset);
+            reach = reach.max(
+                center.distance(pivot) + collider.raw.compute_local_bounding_sphere().radius(),
+            );
+            names.push(piece.name().to_string());
+            shapes.push(collider);
+            (intake, offset)
+        });
+
+        Self {
+            names,
+            shapes,
+            fixed_poses,
+            first_added: links + chassis.len(),
+            carried,
+            pairs,
+            reach,
+        }
+    }
+
+    /// Pose of a body given the link poses
+    fn pose(&self, poses: &[LinkPose], body: usize) -> LinkPose {
+        if let Some(pose) = poses.get(body) {
+            return *pose;
+        }
+        match (self.fixed_poses.get(body - poses.len()), self.carried) {
+            (Some(pose), _) => *pose,
+            (None, Some((intake, offset))) => LinkPose {
+                translation: poses[intake].transform_point(offset),
+                rotation: poses[intake].rotation,
+            },
+            (None, None) => panic!("body {} is out of range", body),
+        }
+    }
+
+    /// Pairs of bodies overlapping at these link poses
+    pub fn touching(&self, poses: &[LinkPose]) -> Vec<(usize, usize)> {
+        self.pairs
+            .iter()
+            .copied()
+            .filter(|&(a, b)| {
+                query::intersection_test(
+                    &self.pose(poses, a).isometry(),
+                    &*self.shapes[a].raw,
+                    &self.pose(poses, b).isometry(),
+                    &*self.shapes[b].raw,
+                )
+                .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// Marks the joint grid cells where a field part or the carried piece
+    /// comes within half a cell of anything as occupied, since the grid only
+    /// knows the robot. Returns how many cells were blocked.
+    pub fn block_added(&self, robot: &RobotConfig, grid: &mut JointGrid) -> usize {
+        // Furthest a point on the robot moves between neighbouring cells
+        let margin = (grid.height_step + self.reach * grid.angle_step.to_radians()) / 2.0;
+        grid.block(|pose| {
+            let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+            self.pairs
+                .iter()
+                .filter(|&&(_, body)| body >= self.first_added)
+                .any(|&(a, b)| {
+                    query::distance(
+                        &self.pose(&poses, a).isometry(),
+                        &*self.shapes[a].raw,
+                        &self.pose(&poses, b).isometry(),
+                        &*self.shapes[b].raw,
+                    )
+                    .is_ok_and(|distance| distance <= margin)
+                })
+        })
+    }
+
+    /// First contact moving from `from` to `to` along `profile`, `None` if
+    /// the move is clear. Bodies already touching at `from` are left out, so
+    /// a move away from a resting contact isn't reported.
+    pub fn sweep(
+        &self,
+        robot: &RobotConfig,
+        from: &ArmPosition,
+        to: &ArmPosition,
+        profile: Profile,
+    ) -> Option<Contact> {
+        let touching_at = |pose: &ArmPosition| {
+            self.touching(&robot.forward_kinematics(pose.height, pose.arm_angle))
+        };
+        let initial = touching_at(from);
+        let new_contact = |time: f32| {
+            touching_at(&profile.pose_at(from, to, time))
+                .into_iter()
+                .find(|pair| !initial.contains(pair))
+        };
+
+        let duration = profile.duration(from, to);
+        let (height_speed, turn_speed) = profile.peak_speeds(from, to);
+        let speed = height_speed + self.reach * turn_speed;
+        let steps = (duration * speed / SWEEP_STEP).ceil().max(1.0) as usize;
+        let mut clear = 0.0;
+        for step in 1..=steps {
+            let time = duration * step as f32 / steps as f32;
+            let Some(mut bodies) = new_contact(time) else {
+                clear = time;
+                continue;
+            };
+            let mut hit = time;
+            for _ in 0..BISECTION_STEPS {
+                let middle = (clear + hit) / 2.0;
+                match new_contact(middle) {
+                    Some(pair) => {
+                        hit = middle;
+                        bodies = pair;
+                    }
+                    None => clear = middle,
+                }
+            }
+            return Some(Contact {
+                time: hit,
+                pose: profile.pose_at(from, to, hit),
+                bodies,
+            });
+        }
+        None
+    }
+
+    /// First contact following waypoints from `from`, stopping at each one.
+    /// The contact time counts from the start of the first leg.
+    pub fn sweep_path(
+        &self,
+        robot: &RobotConfig,
+        from: &ArmPosition,
+        waypoints: &[ArmPosition],
+        profile: Profile,
+    ) -> Option<Contact> {
+        let mut start = *from;
+        let mut elapsed = 0.0;
+        for to in waypoints {
+            if let Some(mut contact) = self.sweep(robot, &start, to, profile) {
+                contact.time += elapsed;
+                return Some(contact);
+            }
+            elapsed += profile.duration(&start, to);
+            start = *to;
+        }
+        None
+    }
+
+    /// Contact for a report, e.g. "the intake hits the frame 0.42 s in at
+    /// height 4.7 in, 35.0 degrees"
+    pub fn describe(&self, contact: &Contact, profile: Profile) -> String {
+        let (a, b) = contact.bodies;
+        format!(
+            "the {} hits the {} {} at height {:.1} in, {:.1} degrees",
+            self.names[a],
+            self.names[b],
+            profile.describe_time(contact.time),
+            contact.pose.height / SIM_UNITS_PER_INCH,
+            contact.pose.arm_angle.to_degrees()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robot() -> RobotConfig {
+        RobotConfig::parse(include_str!("../../../robot.ron")).unwrap()
+    }
+
+    fn pose(height: f32, degrees: f32) -> ArmPosition {
+        ArmPosition {
+            height,
+            arm_angle: degrees.to_radians(),
+        }
+    }
+
+    /// Arm angle turning up from the front where the intake first touches
+    /// the elevator tower, found by stepping a hundredth of a degree at a time
+    fn tower_contact_angle(robot: &RobotConfig, check: &ContactCheck, height: f32) -> f32 {
+        (0..18000)
+            .map(|step| step as f32 / 100.0)
+            .find(|&degrees| {
+                let poses = robot.forward_kinematics(height, degrees.to_radians());
+                check.touching(&poses).contains(&(0, 4))
+            })
+            .expect("the intake reaches the tower")
+    }
+
+    #[test]
+    fn finds_the_first_contact_crossing_the_tower() {
+        let robot = robot();
+        let check = ContactCheck::new(&robot);
+        let (from, to) = (pose(10.0, 0.0), pose(10.0, 179.0));
+        let angle = tower_contact_angle(&robot, &check, 10.0);
+
+        let contact = check.sweep(&robot, &from, &to, Profile::Linear).unwrap();
+        let (a, b) = contact.bodies;
+        assert_eq!((&*check.names[a], &*check.names[b]), ("elevator", "intake"));
+        assert!((contact.time - angle / 179.0).abs() < 1e-3, "{:?}", contact);
+        assert!((contact.pose.arm_angle.to_degrees() - angle).abs() < 0.05);
+
+        // Along a trapezoid the contact comes when the arm profile reaches
+        // the same angle
+        let profile = Profile::Trapezoid {
+            elevator: TrapezoidProfile {
+                max_velocity: 1.0,
+                max_acceleration: 2.0,
+            },
+            arm: TrapezoidProfile {
+                max_velocity: 2.0,
+                max_acceleration: 4.0,
+            },
+        };
+        let expected = (0..)
+            .map(|step| step as f32 * 1e-4)
+            .find(|&time| profile.pose_at(&from, &to, time).arm_angle.to_degrees() >= angle)
+            .unwrap();
+        let contact = check.sweep(&robot, &from, &to, profile).unwrap();
+        assert_eq!(contact.bodies, (0, 4));
+        assert!((contact.time - expected).abs() < 1e-3, "{:?}", contact);
+    }
+
+    #[test]
+    fn clear_move_has_no_contact() {
+        let robot = robot();
+        let check = ContactCheck::new(&robot);
+        let contact = check.sweep(&robot, &pose(0.0, 0.0), &pose(30.0, 40.0), Profile::Linear);
+        assert!(contact.is_none(), "{:?}", contact);
+    }
+}
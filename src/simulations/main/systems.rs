@@ -1,7 +1,13 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
 
+use crate::simulations::main::code_control::current_arm_position;
 use crate::simulations::main::components::*;
+use crate::simulations::main::controllers::ArmControllers;
+use crate::simulations::main::game_pieces::IntakeRollers;
+use crate::simulations::main::kinematics::ArmPosition;
+use crate::simulations::main::safety::*;
+use crate::simulations::main::sweep::{ContactCheck, Profile};
+use crate::simulations::robot::RobotConfig;
 
 pub fn update_mouse_position(
     mut mouse_pos: ResMut<MouseWorldPos>,
@@ -29,20 +35,77 @@ pub fn handle_control_mode(keys: Res<ButtonInput<KeyCode>>, mut control_mode: Re
     }
 }
 
-pub fn apply_intake_force(
+/// Drives the arm towards the cursor with the controllers. Unsafe cursor
+/// targets are clamped to the nearest safe arm angle, and a target the arm
+/// can't move to without a collision is ignored, so the arm holds its last
+/// safe pose.
+#[allow(clippy::too_many_arguments)]
+pub fn follow_cursor(
     control_mode: Res<ControlMode>,
     mouse_pos: Res<MouseWorldPos>,
-    mut intake_query: Query<(&Transform, &mut ExternalForce), With<IntakeMarker>>,
+    transforms: Query<&Transform>,
+    motor_joints: Option<Res<MotorJoints>>,
+    collision_grid: Option<Res<CollisionGrid>>,
+    contact_check: Res<ContactCheck>,
+    rollers: Res<IntakeRollers>,
+    robot: Res<RobotConfig>,
+    mut controllers: ResMut<ArmControllers>,
+    mut warning: ResMut<SafetyWarning>,
 ) {
-    if matches!(*control_mode, ControlMode::CursorFollow) {
-        for (transform, mut ext_force) in intake_query.iter_mut() {
-            let direction = (mouse_pos.0 - transform.translation.truncate()).normalize();
-            ext_force.force = direction * 500000.0;
-        }
-    } else {
-        // Reset forces when not in cursor follow mode
-        for (_, mut ext_force) in intake_query.iter_mut() {
-            ext_force.force = Vec2::ZERO;
+    if !matches!(*control_mode, ControlMode::CursorFollow) {
+        return;
+    }
+    let Some(current) = motor_joints
+        .and_then(|motor_joints| current_arm_position(&transforms, &motor_joints, &robot))
+    else {
+        return;
+    };
+
+    // Prefer the solution closest to where the arm is
+    let grid = collision_grid.as_deref();
+    let (goal, mut message) =
+        match ArmPosition::from_target(mouse_pos.0, &robot, Some(&current), grid) {
+            None => (
+                None,
+                Some("Cursor target is out of reach, holding position".to_string()),
+            ),
+            Some(pose) => match check_target(grid, &robot, &pose, rollers.held()) {
+                TargetCheck::Safe => (Some(pose), None),
+                TargetCheck::Clamped(clamped) => (
+                    Some(clamped),
+                    Some(format!(
+                        "Cursor target is unsafe, clamped arm to {:.1} degrees",
+                        clamped.arm_angle.to_degrees()
+                    )),
+                ),
+                TargetCheck::Refused => (
+                    None,
+                    Some("Cursor target is unsafe, holding position".to_string()),
+                ),
+            },
+        };
+
+    // The controllers take both joints straight to the goal, which can pass
+    // through poses the grid rules out even when both ends are safe
+    let profile = Profile::of_controllers(&controllers);
+    let goal = goal.filter(
+        |goal| match contact_check.sweep(&robot, &current, goal, profile) {
+            Some(contact) => {
+                message = Some(format!(
+                    "Cursor target is blocked, {}, holding position",
+                    contact_check.describe(&contact, profile)
+                ));
+                false
+            }
+            None => true,
+        },
+    );
+    warning.set_if_neq(SafetyWarning(message));
+
+    match goal {
+        Some(goal) => controllers.goal = Some(goal),
+        None => {
+            controllers.goal.get_or_insert(current);
         }
     }
 }
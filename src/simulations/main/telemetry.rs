@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::simulations::main::code_control::{current_arm_position, TargetPosition};
+use crate::simulations::main::components::*;
+use crate::simulations::main::controllers::ArmControllers;
+use crate::simulations::main::game_pieces::IntakeRollers;
+use crate::simulations::main::motors::{Battery, JointMotor};
+use crate::simulations::main::safety::{RulesViolation, SafetyWarning};
+use crate::simulations::robot::{RobotConfig, SIM_UNITS_PER_METER};
+use crate::simulations::wpilog::WpiLogWriter;
+
+/// Log entries and NetworkTables topics live under this prefix
+pub const TELEMETRY_PREFIX: &str = "/Sim/";
+/// 50 Hz, the robot code loop rate
+const LOG_INTERVAL: f64 = 0.02;
+const FLUSH_INTERVAL: f64 = 1.0;
+const COLLISIONS_ENTRY: &str = "Collisions";
+
+/// One joint's state. Positions are meters above the elevator's lowest
+/// point or radians from horizontal, the same units as controllers.ron.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JointTelemetry {
+    pub position: f32,
+    pub velocity: f32,
+    /// Pose the controller is driving towards
+    pub goal: Option<f32>,
+    /// Motion profile position on the way to the goal
+    pub profile_setpoint: Option<f32>,
+    pub applied_voltage: f32,
+    /// Stator current per motor
+    pub current: f32,
+}
+
+/// Sim state sampled every frame, shared by NetworkTables, HALSim and the
+/// telemetry log
+#[derive(Resource, Debug, Clone, Default)]
+pub struct SimState {
+    pub elevator: JointTelemetry,
+    pub arm: JointTelemetry,
+    pub battery_voltage: f32,
+    /// Meters between the closest robot parts, from the grids, `None`
+    /// without clearance values
+    pub clearance: Option<f32>,
+    pub preset: String,
+    pub control_mode: String,
+    pub safety_warning: String,
+    /// Extension or height limits broken, empty when the pose is legal
+    pub rules_violation: String,
+    /// Piece held by the intake, empty when it holds nothing
+    pub game_piece: String,
+}
+
+impl SimState {
+    /// Numeric signals by name, `None` while a signal has no value
+    pub fn numbers(&self) -> [(&'static str, Option<f32>); 14] {
+        let (elevator, arm) = (&self.elevator, &self.arm);
+        [
+            ("Elevator/Position", Some(elevator.position)),
+            ("Elevator/Velocity", Some(elevator.velocity)),
+            ("Elevator/Goal", elevator.goal),
+            ("Elevator/ProfileSetpoint", elevator.profile_setpoint),
+            ("Elevator/AppliedVoltage", Some(elevator.applied_voltage)),
+            ("Elevator/Current", Some(elevator.current)),
+            ("Arm/Angle", Some(arm.position)),
+            ("Arm/Velocity", Some(arm.velocity)),
+            ("Arm/Goal", arm.goal),
+            ("Arm/ProfileSetpoint", arm.profile_setpoint),
+            ("Arm/AppliedVoltage", Some(arm.applied_voltage)),
+            ("Arm/Current", Some(arm.current)),
+            ("BatteryVoltage", Some(self.battery_voltage)),
+            ("Clearance", self.clearance),
+        ]
+    }
+
+    pub fn strings(&self) -> [(&'static str, &str); 5] {
+        [
+            ("Preset", &self.preset),
+            ("ControlMode", &self.control_mode),
+            ("SafetyWarning", &self.safety_warning),
+            ("RulesViolation", &self.rules_violation),
+            ("GamePiece", &self.game_piece),
+        ]
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_sim_state(
+    mut state: ResMut<SimState>,
+    control_mode: Res<ControlMode>,
+    target: Res<TargetPosition>,
+    warning: Res<SafetyWarning>,
+    violation: Res<RulesViolation>,
+    rollers: Res<IntakeRollers>,
+    battery: Res<Battery>,
+    controllers: Res<ArmControllers>,
+    motor_joints: Option<Res<MotorJoints>>,
+    transforms: Query<&Transform>,
+    velocities: Query<&Velocity>,
+    motors: Query<&JointMotor>,
+    collision_grid: Option<Res<CollisionGrid>>,
+    joint_grid: Option<Res<JointGrid>>,
+    robot: Res<RobotConfig>,
+) {
+    state.battery_voltage = battery.voltage;
+    state.preset.clone_from(&target.current.name);
+    state.control_mode = format!("{:?}", *control_mode);
+    state.safety_warning = warning.0.clone().unwrap_or_default();
+    state.rules_violation = violation.0.clone().unwrap_or_default();
+    state.game_piece = rollers
+        .held()
+        .map_or_else(String::new, |piece| piece.name().to_string());
+
+    let goal = controllers.goal;
+    state.elevator.goal = goal.map(|goal| goal.height / SIM_UNITS_PER_METER);
+    state.elevator.profile_setpoint = controllers.elevator.setpoint();
+    state.arm.goal = goal.map(|goal| goal.arm_angle);
+    state.arm.profile_setpoint = controllers.arm.setpoint();
+
+    let Some(motor_joints) = motor_joints else {
+        return;
+    };
+    if let Some(current) = current_arm_position(&transforms, &motor_joints, &robot) {
+        state.elevator.position = current.height / SIM_UNITS_PER_METER;
+        state.arm.position = current.arm_angle;
+        // The joint grid checks every moving link, the collision grid only
+        // the intake
+        let clearance = joint_grid
+            .and_then(|grid| grid.clearance_at(&current))
+            .or_else(|| {
+                collision_grid.and_then(|grid| {
+                    grid.tip_clearance(
+                        robot.arm_tip(current.joint_height(&robot), current.arm_angle),
+                    )
+                })
+            });
+        state.clearance = clearance.map(|clearance| clearance / SIM_UNITS_PER_METER);
+    }
+
+    if let Ok(motor) = motors.get(motor_joints.elevator) {
+        state.elevator.applied_voltage = motor.applied_voltage;
+        state.elevator.current = motor.current;
+        // The elevator base is fixed, so the carriage velocity along the
+        // slide axis is the joint velocity
+        if let (Some(axis), Ok(velocity)) = (motor.axis, velocities.get(motor_joints.elevator_body))
+        {
+            state.elevator.velocity = velocity.linvel.dot(axis.normalize()) / SIM_UNITS_PER_METER;
+        }
+    }
+    if let Ok(motor) = motors.get(motor_joints.arm) {
+        state.arm.applied_voltage = motor.applied_voltage;
+        state.arm.current = motor.current;
+    }
+    if let Ok(velocity) = velocities.get(motor_joints.arm_body) {
+        state.arm.velocity = velocity.angvel;
+    }
+}
+
+/// WPILOG and CSV recording of the sim state, started with `--log <path>`
+#[derive(Resource)]
+pub struct TelemetryLog {
+    wpilog: WpiLogWriter,
+    csv: BufWriter<File>,
+    /// WPILOG entry ids in `SimState::numbers` then `strings` order
+    number_entries: Vec<u32>,
+    string_entries: Vec<u32>,
+    collisions_entry: u32,
+    last_strings: Vec<String>,
+    /// Collisions since the last CSV row
+    pending_collisions: Vec<String>,
+    next_sample: f64,
+    next_flush: f64,
+}
+
+impl TelemetryLog {
+    /// Creates `<path>.wpilog` and `<path>.csv`, replacing any extension on
+    /// `path`
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let wpilog_path = path.with_extension("wpilog");
+        let csv_path = path.with_extension("csv");
+        let mut wpilog = WpiLogWriter::create(&wpilog_path, "frc_2025_arm_sim")?;
+        let mut csv = BufWriter::new(File::create(&csv_path)?);
+
+        let state = SimState::default();
+        let entry_name = |name: &str| format!("{}{}", TELEMETRY_PREFIX, name);
+        let number_entries = state
+            .numbers()
+            .iter()
+            .map(|(name, _)| wpilog.start_entry(&entry_name(name), "double", 0))
+            .collect::<io::Result<Vec<_>>>()?;
+        let string_entries = state
+            .strings()
+            .iter()
+            .map(|(name, _)| wpilog.start_entry(&entry_name(name), "string", 0))
+            .collect::<io::Result<Vec<_>>>()?;
+        let collisions_entry = wpilog.start_entry(&entry_name(COLLISIONS_ENTRY), "string", 0)?;
+
+        let mut header = vec!["Time".to_string()];
+        header.extend(state.numbers().iter().map(|(name, _)| name.to_string()));
+        header.extend(state.strings().iter().map(|(name, _)| name.to_string()));
+        header.push(COLLISIONS_ENTRY.to_string());
+        writeln!(csv, "{}", header.join(","))?;
+
+        println!(
+            "Logging telemetry to {} and {}",
+            wpilog_path.display(),
+            csv_path.display()
+        );
+        Ok(Self {
+            wpilog,
+            csv,
+            number_entries,
+            string_entries,
+            collisions_entry,
+            last_strings: vec![String::new(); state.strings().len()],
+            pending_collisions: Vec::new(),
+            next_sample: 0.0,
+            next_flush: FLUSH_INTERVAL,
+        })
+    }
+
+    fn write_sample(&mut self, seconds: f64, state: &SimState) -> io::Result<()> {
+        let timestamp = (seconds * 1e6) as u64;
+        let mut row = vec![format!("{:.4}", seconds)];
+
+        for ((_, value), &entry) in state.numbers().iter().zip(&self.number_entries) {
+            match value {
+                Some(value) => {
+                    self.wpilog.append_double(entry, timestamp, *value as f64)?;
+                    row.push(value.to_string());
+                }
+                None => row.push(String::new()),
+            }
+        }
+        // Strings only go to the WPILOG when they change
+        for (i, (_, value)) in state.strings().iter().enumerate() {
+            if self.last_strings[i] != *value {
+                self.wpilog
+                    .append_string(self.string_entries[i], timestamp, value)?;
+                self.last_strings[i] = value.to_string();
+            }
+            row.push(csv_field(value));
+        }
+        row.push(csv_field(&self.pending_collisions.join("; ")));
+        self.pending_collisions.clear();
+
+        writeln!(self.csv, "{}", row.join(","))
+    }
+
+    fn log_collision(&mut self, seconds: f64, event: String) -> io::Result<()> {
+        self.wpilog
+            .append_string(self.collisions_entry, (seconds * 1e6) as u64, &event)?;
+        self.pending_collisions.push(event);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.wpilog.flush()?;
+        self.csv.flush()
+    }
+}
+
+/// Quotes a CSV field if it needs it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Opens the telemetry log if `--log` was given
+pub fn open_telemetry_log(path: Option<PathBuf>) -> Option<TelemetryLog> {
+    let path = path?;
+    match TelemetryLog::create(&path) {
+        Ok(log) => Some(log),
+        Err(e) => {
+            println!("Failed to open telemetry log {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Samples the sim state at the log rate and records link collisions as
+/// they start and stop
+pub fn record_telemetry(
+    mut commands: Commands,
+    time: Res<Time>,
+    log: Option<ResMut<TelemetryLog>>,
+    state: Res<SimState>,
+    mut collisions: EventReader<CollisionEvent>,
+    names: Query<&Name>,
+) {
+    let Some(mut log) = log else {
+        collisions.clear();
+        return;
+    };
+    let seconds = time.elapsed_secs_f64();
+    let name = |entity| names.get(entity).map_or("unknown", |name| name.as_str());
+
+    let mut result = Ok(());
+    for event in collisions.read() {
+        let event = match event {
+            CollisionEvent::Started(a, b, _) => format!("{} hit {}", name(*a), name(*b)),
+            CollisionEvent::Stopped(a, b, _) => format!("{} cleared {}", name(*a), name(*b)),
+        };
+        result = result.and(log.log_collision(seconds, event));
+    }
+
+    if seconds >= log.next_sample {
+        // Skip ahead rather than catching up after a long frame
+        log.next_sample = (log.next_sample + LOG_INTERVAL).max(seconds);
+        result = result.and(log.write_sample(seconds, &state));
+    }
+    if seconds >= log.next_flush {
+        log.next_flush = seconds + FLUSH_INTERVAL;
+        result = result.and(log.flush());
+    }
+
+    if let Err(e) = result {
+        println!("Failed to write telemetry, logging stopped: {}", e);
+        commands.remove_resource::<TelemetryLog>();
+    }
+}
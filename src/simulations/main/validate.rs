@@ -0,0 +1,588 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier2d::parry::query;
+use bevy_rapier2d::rapier::math::{Isometry, Vector};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use crate::simulations::field::{FieldConfig, ScoringTarget};
+use crate::simulations::main::components::*;
+use crate::simulations::main::controllers::{ArmControllers, CONTROLLERS_FILE};
+use crate::simulations::main::game_pieces::GamePieceKind;
+use crate::simulations::main::kinematics::{inverse_kinematics, ArmPosition};
+use crate::simulations::main::planner::{plan_path, wrap_angle};
+use crate::simulations::main::presets::Presets;
+use crate::simulations::main::safety::{check_target, TargetCheck};
+use crate::simulations::main::sweep::{ContactCheck, Profile};
+use crate::simulations::robot::{groups_interact, JointKind, RobotConfig, SIM_UNITS_PER_INCH};
+use crate::simulations::rules::describe;
+
+/// Random arm tip positions compared between the grid and an exact shape test,
+/// and random poses round tripped through the kinematics
+const SPOT_CHECKS: usize = 10_000;
+
+pub struct ValidateOptions {
+    pub robot_config: String,
+    pub presets: String,
+    pub grid_file: String,
+    pub joint_grid_file: String,
+    pub field: String,
+    /// Clearance in inches presets and paths must keep
+    pub safety_margin: f32,
+    pub seed: u64,
+}
+
+/// Loads every input without falling back to defaults, checks each preset
+/// against the collision grid and the field, and spot checks the grid at
+/// random positions. Prints a report and returns whether everything passed.
+pub fn validate(options: ValidateOptions) -> bool {
+    let mut passed = true;
+    let mut fail = |message: String| {
+        println!("FAIL {}", message);
+        passed = false;
+    };
+
+    let robot = match RobotConfig::load(&options.robot_config) {
+        Ok(robot) => {
+            println!("ok   robot description {}", options.robot_config);
+            robot
+        }
+        Err(e) => {
+            fail(format!("robot description {}: {}", options.robot_config, e));
+            return false;
+        }
+    };
+
+    let margin = options.safety_margin * SIM_UNITS_PER_INCH;
+    let grid = match CollisionGrid::load_from_file(&options.grid_file, &robot) {
+        Ok(mut grid) => {
+            grid.safety_margin = margin;
+            if margin > 0.0 && grid.clearance.is_none() {
+                println!(
+                    "warn collision grid {} has no clearance values, the safety margin is ignored",
+                    options.grid_file
+                );
+            }
+            println!(
+                "ok   collision grid {} ({}x{})",
+                options.grid_file,
+                grid.grid[0].len(),
+                grid.grid.len()
+            );
+            Some(grid)
+        }
+        Err(e) => {
+            fail(format!("collision grid {}: {}", options.grid_file, e));
+            None
+        }
+    };
+    let joint_grid = match JointGrid::load_from_file(&options.joint_grid_file, &robot) {
+        Ok(mut grid) => {
+            grid.safety_margin = margin;
+            if margin > 0.0 && grid.clearance.is_none() {
+                println!(
+                    "warn joint grid {} has no clearance values, the safety margin is ignored",
+                    options.joint_grid_file
+                );
+            }
+            println!("ok   joint grid {}", options.joint_grid_file);
+            Some(grid)
+        }
+        Err(e) => {
+            fail(format!("joint grid {}: {}", options.joint_grid_file, e));
+            None
+        }
+    };
+    let field = match FieldConfig::load(&options.field, &robot) {
+        Ok(field) => {
+            println!("ok   field {}", options.field);
+            Some(field)
+        }
+        Err(e) => {
+            fail(format!("field {}: {}", options.field, e));
+            None
+        }
+    };
+
+    // Without gains the moves are swept at a constant speed instead
+    let profile = match ArmControllers::load(CONTROLLERS_FILE) {
+        Ok(controllers) => {
+            println!("ok   controller gains {}", CONTROLLERS_FILE);
+            Profile::of_controllers(&controllers)
+        }
+        Err(e) => {
+            println!("warn controller gains {}: {}", CONTROLLERS_FILE, e);
+            Profile::Linear
+        }
+    };
+
+    match Presets::load(&options.presets) {
+        Ok(presets) => {
+            println!(
+                "ok   {} presets in {}",
+                presets.presets.len(),
+                options.presets
+            );
+            let [min, max] = robot.elevator_limits();
+            let travel = max - min;
+            for preset in &presets.presets {
+                if !(0.0..=travel).contains(&preset.height()) {
+                    fail(format!(
+                        "preset {} height {:.2} in is outside the elevator travel 0 to {:.2} in",
+                        preset.name,
+                        preset.height,
+                        travel / SIM_UNITS_PER_INCH
+                    ));
+                    continue;
+                }
+                let pose = ArmPosition {
+                    height: preset.height(),
+                    arm_angle: preset.angle(),
+                };
+                // Enforced limits are part of the safety check below
+                if let Some(rules) = robot.rules.filter(|rules| !rules.enforce) {
+                    let violations = rules.check_pose(&robot, pose.height, pose.arm_angle);
+                    if !violations.is_empty() {
+                        println!("warn preset {} is {}", preset.name, describe(&violations));
+                    }
+                }
+                match check_target(grid.as_ref(), &robot, &pose, None) {
+                    TargetCheck::Safe => match joint_grid
+                        .as_ref()
+                        .and_then(|grid| grid.clearance_at(&pose))
+                    {
+                        Some(clearance) => println!(
+                            "ok   preset {} clears by {:.1} in",
+                            preset.name,
+                            clearance / SIM_UNITS_PER_INCH
+                        ),
+                        None => println!("ok   preset {}", preset.name),
+                    },
+                    TargetCheck::Clamped(clamped) => fail(format!(
+                        "preset {} is unsafe, the sim clamps it to {:.1} degrees",
+                        preset.name,
+                        clamped.arm_angle.to_degrees()
+                    )),
+                    TargetCheck::Refused => fail(format!(
+                        "preset {} is unsafe with no safe arm angle at its height",
+                        preset.name
+                    )),
+                }
+            }
+            // Like the sim, plan and sweep around the shown field elements
+            let shown = field
+                .as_ref()
+                .map(FieldConfig::shown_parts)
+                .unwrap_or_default();
+            let contact_check = ContactCheck::with_field(&robot, &shown, None);
+            let shown_grid = joint_grid
+                .as_ref()
+                .map(|grid| block_contacts(grid, &robot, &contact_check));
+            check_moves(
+                &robot,
+                &presets,
+                shown_grid.as_ref(),
+                &contact_check,
+                profile,
+                &mut fail,
+            );
+            if let Some(field) = &field {
+                check_field(
+                    field,
+                    &robot,
+                    &presets,
+                    joint_grid.as_ref(),
+                    profile,
+                    &mut fail,
+                );
+            }
+        }
+        Err(e) => fail(format!("presets {}: {}", options.presets, e)),
+    }
+
+    check_forward_kinematics(&robot, options.seed, &mut fail);
+    check_inverse_kinematics(&robot, options.seed, &mut fail);
+    if let Some(grid) = &grid {
+        spot_check(grid, &robot, options.seed);
+    }
+    passed
+}
+
+/// Checks the spawn pose and random poses from forward kinematics. Every
+/// joint must hold its links together, the joint values read back from the
+/// carriage and arm the way the main sim reads them, and each link's box must
+/// sit on the link.
+fn check_forward_kinematics(robot: &RobotConfig, seed: u64, fail: &mut impl FnMut(String)) {
+    const TOLERANCE: f32 = 0.01; // Sim units or radians
+
+    let [min, max] = robot.elevator_limits();
+    let carriage = robot.elevator_joint().child;
+    let arm = robot.arm_joint().child;
+    let problem = |pose: &ArmPosition| -> Option<String> {
+        let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+        for joint in &robot.joints {
+            let parent = poses[joint.parent].transform_point(joint.parent_anchor);
+            let child = poses[joint.child].transform_point(joint.child_anchor);
+            let apart = match joint.kind {
+                // Only the slide along the axis may separate the anchors
+                JointKind::Prismatic { axis, .. } => Vec2::from_angle(poses[joint.parent].rotation)
+                    .rotate(axis)
+                    .perp_dot(child - parent)
+                    .abs(),
+                JointKind::Revolute { .. } | JointKind::Fixed => parent.distance(child),
+            };
+            if apart > TOLERANCE {
+                return Some(format!("joint {} is {:.3} apart", joint.name, apart));
+            }
+        }
+        let height = robot.elevator_travel(poses[carriage].translation) - robot.elevator_offset();
+        if (height - pose.height).abs() > TOLERANCE {
+            return Some(format!("the carriage reads back height {:.3}", height));
+        }
+        if wrap_angle(poses[arm].rotation - pose.arm_angle).abs() > TOLERANCE {
+            return Some(format!(
+                "the arm reads back {:.2} degrees",
+                poses[arm].rotation.to_degrees()
+            ));
+        }
+        for ((link, pose), obb) in robot.links.iter().zip(poses.iter()).zip(&poses.boxes) {
+            let radius = obb.half_extents.length();
+            let off = obb
+                .corners()
+                .iter()
+                .any(|corner| (corner.distance(pose.translation) - radius).abs() > TOLERANCE);
+            if off {
+                return Some(format!("the box of {} is off the link", link.name));
+            }
+        }
+        None
+    };
+
+    let spawn = ArmPosition {
+        height: 0.0,
+        arm_angle: 0.0,
+    };
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let random = (0..SPOT_CHECKS).map(|_| ArmPosition {
+        height: rng.gen_range(0.0..=max - min),
+        arm_angle: rng.gen_range(-PI..PI),
+    });
+    let mut failures = 0;
+    for pose in std::iter::once(spawn).chain(random) {
+        if let Some(problem) = problem(&pose) {
+            if failures == 0 {
+                fail(format!(
+                    "forward kinematics for height {:.2}, {:.1} degrees: {}",
+                    pose.height,
+                    pose.arm_angle.to_degrees(),
+                    problem
+                ));
+            }
+            failures += 1;
+        }
+    }
+    if failures == 0 {
+        println!(
+            "ok   forward kinematics holds the joints at the spawn pose and {} random poses (seed {})",
+            SPOT_CHECKS, seed
+        );
+    } else {
+        fail(format!(
+            "forward kinematics broke {} of {} poses (seed {})",
+            failures,
+            SPOT_CHECKS + 1,
+            seed
+        ));
+    }
+}
+
+/// Round trips random poses through forward and inverse kinematics. The
+/// intake pose each one reaches must give back that pose among the
+/// solutions, and every solution must reach the same intake pose.
+fn check_inverse_kinematics(robot: &RobotConfig, seed: u64, fail: &mut impl FnMut(String)) {
+    /// Sim units
+    const POSITION_TOLERANCE: f32 = 0.01;
+    /// Sim units, looser since the height is ill conditioned near a level arm
+    const HEIGHT_TOLERANCE: f32 = 0.1;
+    /// Radians
+    const ANGLE_TOLERANCE: f32 = 0.005;
+
+    let [min, max] = robot.elevator_limits();
+    let intake_pose = |pose: &ArmPosition| {
+        robot.forward_kinematics(pose.height, pose.arm_angle)[robot.intake_index()]
+    };
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut failures = 0;
+    for _ in 0..SPOT_CHECKS {
+        let pose = ArmPosition {
+            height: rng.gen_range(0.0..=max - min),
+            arm_angle: rng.gen_range(-PI..PI),
+        };
+        let target = intake_pose(&pose);
+        let solutions =
+            inverse_kinematics(robot, target.translation, Some(target.rotation), None, None);
+        let found = solutions.iter().any(|solution| {
+            (solution.pose.height - pose.height).abs() < HEIGHT_TOLERANCE
+                && wrap_angle(solution.pose.arm_angle - pose.arm_angle).abs() < ANGLE_TOLERANCE
+        });
+        let all_reach = solutions.iter().all(|solution| {
+            intake_pose(&solution.pose)
+                .translation
+                .distance(target.translation)
+                < POSITION_TOLERANCE
+        });
+        if !(found && all_reach) {
+            if failures == 0 {
+                fail(format!(
+                    "inverse kinematics for height {:.2}, {:.1} degrees gives {:?}",
+                    pose.height,
+                    pose.arm_angle.to_degrees(),
+                    solutions
+                        .iter()
+                        .map(|solution| (
+                            solution.pose.height,
+                            solution.pose.arm_angle.to_degrees()
+                        ))
+                        .collect::<Vec<_>>()
+                ));
+            }
+            failures += 1;
+        }
+    }
+    if failures == 0 {
+        println!(
+            "ok   inverse kinematics round trips {} random poses (seed {})",
+            SPOT_CHECKS, seed
+        );
+    } else {
+        fail(format!(
+            "inverse kinematics missed {} of {} random poses (seed {})",
+            failures, SPOT_CHECKS, seed
+        ));
+    }
+}
+
+/// Sweeps the robot along the move from the starting preset to each other
+/// preset, following the planned path when there is a joint grid. The joint
+/// grid and the contact check include the shown field elements, like the
+/// sim's. A planned path must be clear; a direct move only warns, since the
+/// sim plans around contacts when it has a joint grid.
+fn check_moves(
+    robot: &RobotConfig,
+    presets: &Presets,
+    joint_grid: Option<&JointGrid>,
+    contact_check: &ContactCheck,
+    profile: Profile,
+    fail: &mut impl FnMut(String),
+) {
+    let start = presets.initial();
+    let start_pose = ArmPosition {
+        height: start.height(),
+        arm_angle: start.angle(),
+    };
+    for preset in presets.presets.iter().filter(|preset| *preset != start) {
+        let pose = ArmPosition {
+            height: preset.height(),
+            arm_angle: preset.angle(),
+        };
+        let planned = joint_grid.and_then(|grid| plan_path(grid, &start_pose, &pose));
+        let is_planned = planned.is_some();
+        let waypoints = planned.unwrap_or_else(|| vec![pose]);
+        match contact_check.sweep_path(robot, &start_pose, &waypoints, profile) {
+            None => println!(
+                "ok   moving from {} to {} is clear",
+                start.name, preset.name
+            ),
+            Some(contact) => {
+                let message = format!(
+                    "moving from {} to {} {}",
+                    start.name,
+                    preset.name,
+                    contact_check.describe(&contact, profile)
+                );
+                if is_planned {
+                    fail(message);
+                } else {
+                    println!("warn {}", message);
+                }
+            }
+        }
+    }
+}
+
+/// Checks each scoring preset against the field element it targets. The
+/// preset must not put the robot into the element and must leave the held
+/// piece on its scoring location. The move there from the starting preset,
+/// along the planned path when there is a joint grid, must keep the robot and
+/// the carried piece clear of the element.
+fn check_field(
+    field: &FieldConfig,
+    robot: &RobotConfig,
+    presets: &Presets,
+    joint_grid: Option<&JointGrid>,
+    profile: Profile,
+    fail: &mut impl FnMut(String),
+) {
+    let start = presets.initial();
+    let start_pose = ArmPosition {
+        height: start.height(),
+        arm_angle: start.angle(),
+    };
+    for target in &field.targets {
+        let Some(preset) = presets.get(&target.preset) else {
+            fail(format!(
+                "field target {} names a preset that doesn't exist",
+                target.preset
+            ));
+            continue;
+        };
+        let target = target.target;
+        let (Some(parts), Some((location, tolerance))) = (
+            field.element(target.element()),
+            field.target_location(target),
+        ) else {
+            continue;
+        };
+        let piece = if target.uses_algae() {
+            GamePieceKind::Algae
+        } else {
+            GamePieceKind::Coral
+        };
+        // Coral is picked up at the station, everything else is carried in
+        let carried = (target != ScoringTarget::CoralStation).then_some(piece);
+
+        let pose = ArmPosition {
+            height: preset.height(),
+            arm_angle: preset.angle(),
+        };
+        // The piece itself ends up in the element, on a branch or through an
+        // opening, so only the robot is checked at the preset
+        let robot_check = ContactCheck::with_field(robot, &parts, None);
+        let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+        if let Some(&(a, b)) = robot_check.touching(&poses).first() {
+            fail(format!(
+                "preset {} puts the {} into the {}",
+                preset.name, robot_check.names[a], robot_check.names[b]
+            ));
+            continue;
+        }
+
+        let miss_at = |pose: &ArmPosition| {
+            let poses = robot.forward_kinematics(pose.height, pose.arm_angle);
+            let held = piece.held_pose(robot, &poses[robot.intake_index()]);
+            (Vec2::new(held.translation.x, held.translation.y) - location).length()
+        };
+        let miss = miss_at(&pose);
+        if miss <= tolerance {
+            println!(
+                "ok   preset {} puts the {} on the {} ({:.1} in off)",
+                preset.name,
+                piece.name(),
+                target.name(),
+                miss / SIM_UNITS_PER_INCH
+            );
+        } else {
+            fail(format!(
+                "preset {} leaves the {} {:.1} in from the {}, more than the {:.1} in allowed",
+                preset.name,
+                piece.name(),
+                miss / SIM_UNITS_PER_INCH,
+                target.name(),
+                tolerance / SIM_UNITS_PER_INCH
+            ));
+        }
+
+        // The robot must keep clear of the element all the way there. The
+        // carried piece may only touch it once it is on its scoring location.
+        let waypoints = joint_grid
+            .map(|grid| block_contacts(grid, robot, &robot_check))
+            .and_then(|grid| plan_path(&grid, &start_pose, &pose))
+            .unwrap_or_else(|| vec![pose]);
+        let piece_check = carried.map(|piece| ContactCheck::with_field(robot, &parts, Some(piece)));
+        let contact = robot_check
+            .sweep_path(robot, &start_pose, &waypoints, profile)
+            .map(|contact| (&robot_check, contact))
+            .or_else(|| {
+                let check = piece_check.as_ref()?;
+                check
+                    .sweep_path(robot, &start_pose, &waypoints, profile)
+                    .filter(|contact| miss_at(&contact.pose) > tolerance)
+                    .map(|contact| (check, contact))
+            });
+        match contact {
+            None => println!(
+                "ok   moving from {} to {} clears the {}",
+                start.name,
+                preset.name,
+                target.element().name()
+            ),
+            Some((check, contact)) => fail(format!(
+                "moving from {} to {} {}",
+                start.name,
+                preset.name,
+                check.describe(&contact, profile)
+            )),
+        }
+    }
+}
+
+/// Copy of the joint grid with the poses near the field blocked, the way the
+/// sim plans around the shown field
+fn block_contacts(
+    joint_grid: &JointGrid,
+    robot: &RobotConfig,
+    contact_check: &ContactCheck,
+) -> JointGrid {
+    let mut grid = joint_grid.clone();
+    contact_check.block_added(robot, &mut grid);
+    grid
+}
+
+/// Reports how often the grid disagrees with an exact test of the intake
+/// against the elevator and chassis at random arm tip positions. Some
+/// disagreement along the collision boundary is expected; it shrinks with the
+/// grid resolution.
+fn spot_check(grid: &CollisionGrid, robot: &RobotConfig, seed: u64) {
+    let obstacles: Vec<_> = robot
+        .fixed_parts()
+        .into_iter()
+        .filter(|part| groups_interact(part.groups, robot.intake().groups))
+        .map(|part| (part.shape.collider(), part.pose.isometry()))
+        .collect();
+    let intake = robot.intake().shape.collider();
+    let (mount, rotation) = robot.intake_mount();
+
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let (mut missed, mut conservative) = (0, 0);
+    for _ in 0..SPOT_CHECKS {
+        let tip = Vec2::new(
+            rng.gen_range(grid.min_x..grid.max_x),
+            rng.gen_range(grid.min_y..grid.max_y),
+        );
+        let position = tip + mount;
+        let intake_pose = Isometry::new(Vector::new(position.x, position.y), rotation);
+        let collides = obstacles.iter().any(|(shape, pose)| {
+            query::intersection_test(&intake_pose, &*intake.raw, pose, &*shape.raw).unwrap_or(false)
+        });
+
+        let grid_collides = grid.collides_at(tip).unwrap_or(true);
+        match (collides, grid_collides) {
+            (true, false) => missed += 1,
+            (false, true) => conservative += 1,
+            _ => {}
+        }
+    }
+
+    let percent = |count: usize| count as f32 * 100.0 / SPOT_CHECKS as f32;
+    println!(
+        "info spot check (seed {}): {} random arm tips, {} ({:.2}%) collide where the grid says \
+         safe, {} ({:.2}%) are clear where it says unsafe",
+        seed,
+        SPOT_CHECKS,
+        missed,
+        percent(missed),
+        conservative,
+        percent(conservative)
+    );
+}
@@ -1,2 +1,8 @@
+pub mod field;
 pub mod grid;
+pub mod grid_file;
 pub mod main;
+pub mod nt4;
+pub mod robot;
+pub mod rules;
+pub mod wpilog;
@@ -0,0 +1,15 @@
+//! A minimal NetworkTables 4 server, so WPILib robot code, AdvantageScope
+//! and Shuffleboard can connect to the sim like they would to a robot.
+//!
+//! Control messages are JSON text frames and values are MessagePack binary
+//! frames, as described in the NT4 spec:
+//! https://github.com/wpilibsuite/allwpilib/blob/main/ntcore/doc/networktables4.adoc
+
+mod protocol;
+mod server;
+
+pub use protocol::Value;
+pub use server::NtServer;
+
+/// Default NT4 port
+pub const NT_PORT: u16 = 5810;
@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+
+/// Subprotocols we accept, newest first
+pub const SUBPROTOCOLS: [&str; 2] = [
+    "v4.1.networktables.first.wpi.edu",
+    "networktables.first.wpi.edu",
+];
+
+/// Topic id used for time synchronization in binary frames
+pub const TIME_SYNC_ID: i64 = -1;
+
+pub type Properties = Map<String, serde_json::Value>;
+
+/// Control messages sent by clients in JSON text frames
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "lowercase")]
+pub enum ClientMessage {
+    Publish {
+        name: String,
+        pubuid: i64,
+        #[serde(rename = "type")]
+        type_name: String,
+        #[serde(default)]
+        properties: Properties,
+    },
+    Unpublish {
+        pubuid: i64,
+    },
+    SetProperties {
+        name: String,
+        update: Properties,
+    },
+    Subscribe {
+        topics: Vec<String>,
+        subuid: i64,
+        #[serde(default)]
+        options: SubscribeOptions,
+    },
+    Unsubscribe {
+        subuid: i64,
+    },
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SubscribeOptions {
+    /// Match topic names by prefix instead of exactly
+    pub prefix: bool,
+    /// Only send announcements, no values
+    pub topicsonly: bool,
+}
+
+/// Control messages sent to clients in JSON text frames
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "method", content = "params", rename_all = "lowercase")]
+pub enum ServerMessage {
+    Announce {
+        name: String,
+        id: i64,
+        #[serde(rename = "type")]
+        type_name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pubuid: Option<i64>,
+        properties: Properties,
+    },
+    Unannounce {
+        name: String,
+        id: i64,
+    },
+    Properties {
+        name: String,
+        ack: bool,
+        update: Properties,
+    },
+}
+
+/// A topic value as the sim publishes it. Values from clients are kept as
+/// raw MessagePack so topics of any type can be relayed between clients.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Double(f64),
+    String(String),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Double(_) => "double",
+            Value::String(_) => "string",
+        }
+    }
+
+    pub fn to_msgpack(&self) -> rmpv::Value {
+        match self {
+            Value::Double(value) => rmpv::Value::from(*value),
+            Value::String(value) => rmpv::Value::from(value.as_str()),
+        }
+    }
+}
+
+/// Numeric type id sent with each value in binary frames
+pub fn type_id(type_name: &str) -> u8 {
+    match type_name {
+        "boolean" => 0,
+        "double" => 1,
+        "int" => 2,
+        "float" => 3,
+        "string" | "json" => 4,
+        "boolean[]" => 16,
+        "double[]" => 17,
+        "int[]" => 18,
+        "float[]" => 19,
+        "string[]" => 20,
+        // raw, rpc, msgpack, protobuf and struct types are all bytes
+        _ => 5,
+    }
+}
+
+/// Reads a number regardless of whether it was sent as a double, float or
+/// int, since clients don't always match the topic type
+pub fn as_f64(value: &rmpv::Value) -> Option<f64> {
+    match value {
+        rmpv::Value::F64(v) => Some(*v),
+        rmpv::Value::F32(v) => Some(*v as f64),
+        rmpv::Value::Integer(v) => v.as_f64(),
+        _ => None,
+    }
+}
+
+/// One `[id, timestamp, type, value]` entry of a binary frame
+#[derive(Debug, Clone)]
+pub struct ValueMessage {
+    pub id: i64,
+    pub timestamp: i64,
+    pub type_id: u8,
+    pub value: rmpv::Value,
+}
+
+impl ValueMessage {
+    pub fn encode(&self, buffer: &mut Vec<u8>) {
+        let entry = rmpv::Value::Array(vec![
+            rmpv::Value::from(self.id),
+            rmpv::Value::from(self.timestamp),
+            rmpv::Value::from(self.type_id),
+            self.value.clone(),
+        ]);
+        // Writing to a Vec can't fail
+        rmpv::encode::write_value(buffer, &entry).expect("MessagePack encoding failed");
+    }
+
+    /// Decodes every entry in a binary frame
+    pub fn decode_all(mut bytes: &[u8]) -> Result<Vec<Self>, String> {
+        let mut messages = Vec::new();
+        while !bytes.is_empty() {
+            let entry = rmpv::decode::read_value(&mut bytes).map_err(|e| e.to_string())?;
+            let rmpv::Value::Array(fields) = entry else {
+                return Err("value message is not an array".to_string());
+            };
+            let [id, timestamp, type_id, value] = <[rmpv::Value; 4]>::try_from(fields)
+                .map_err(|_| "value message must have 4 fields".to_string())?;
+            messages.push(Self {
+                id: id.as_i64().ok_or("invalid topic id")?,
+                timestamp: timestamp.as_i64().ok_or("invalid timestamp")?,
+                type_id: type_id
+                    .as_u64()
+                    .and_then(|t| u8::try_from(t).ok())
+                    .ok_or("invalid type id")?,
+                value,
+            });
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_all(messages: &[ValueMessage]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for message in messages {
+            message.encode(&mut buffer);
+        }
+        buffer
+    }
+
+    #[test]
+    fn value_messages_round_trip() {
+        let messages = [
+            ValueMessage {
+                id: 3,
+                timestamp: 1_234_567,
+                type_id: type_id("double"),
+                value: Value::Double(-2.5).to_msgpack(),
+            },
+            ValueMessage {
+                id: TIME_SYNC_ID,
+                timestamp: 0,
+                type_id: type_id("int"),
+                value: rmpv::Value::from(42),
+            },
+            ValueMessage {
+                id: 12,
+                timestamp: i64::MAX,
+                type_id: type_id("string"),
+                value: Value::String("Stow".to_string()).to_msgpack(),
+            },
+        ];
+
+        let decoded = ValueMessage::decode_all(&encode_all(&messages)).expect("valid frame");
+        assert_eq!(decoded.len(), messages.len());
+        for (decoded, message) in decoded.iter().zip(&messages) {
+            assert_eq!(decoded.id, message.id);
+            assert_eq!(decoded.timestamp, message.timestamp);
+            assert_eq!(decoded.type_id, message.type_id);
+            assert_eq!(decoded.value, message.value);
+        }
+        assert!(ValueMessage::decode_all(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_value_messages() {
+        let encode = |value: rmpv::Value| {
+            let mut buffer = Vec::new();
+            rmpv::encode::write_value(&mut buffer, &value).unwrap();
+            buffer
+        };
+        let entry = |fields: Vec<rmpv::Value>| encode(rmpv::Value::Array(fields));
+
+        assert!(ValueMessage::decode_all(&encode(rmpv::Value::from(1))).is_err());
+        assert!(ValueMessage::decode_all(&entry(vec![1.into(), 0.into(), 1.into()])).is_err());
+        assert!(ValueMessage::decode_all(&entry(vec![
+            "id".into(),
+            0.into(),
+            1.into(),
+            1.0.into()
+        ]))
+        .is_err());
+        assert!(
+            ValueMessage::decode_all(&entry(vec![1.into(), 0.into(), 256.into(), 1.0.into()]))
+                .is_err()
+        );
+
+        let frame = encode_all(&[ValueMessage {
+            id: 1,
+            timestamp: 0,
+            type_id: 1,
+            value: rmpv::Value::from(1.0),
+        }]);
+        assert!(ValueMessage::decode_all(&frame[..frame.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn reads_numbers_of_any_type() {
+        assert_eq!(as_f64(&rmpv::Value::F64(1.5)), Some(1.5));
+        assert_eq!(as_f64(&rmpv::Value::F32(1.5)), Some(1.5));
+        assert_eq!(as_f64(&rmpv::Value::from(-3)), Some(-3.0));
+        assert_eq!(as_f64(&rmpv::Value::from("1.5")), None);
+    }
+}
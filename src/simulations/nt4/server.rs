@@ -0,0 +1,778 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tungstenite::http::{HeaderValue, StatusCode};
+use tungstenite::{Message, WebSocket};
+
+use crate::simulations::nt4::protocol::*;
+
+/// How long a client thread waits for incoming frames before flushing its
+/// outgoing queue
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+struct Topic {
+    id: i64,
+    type_name: String,
+    properties: Properties,
+    value: Option<(i64, rmpv::Value)>,
+    /// Client publishers plus one if the sim publishes the topic
+    publishers: usize,
+}
+
+impl Topic {
+    /// Retained and persistent topics outlive their last publisher
+    fn retained(&self) -> bool {
+        ["retained", "persistent"]
+            .iter()
+            .any(|key| self.properties.get(*key) == Some(&serde_json::Value::Bool(true)))
+    }
+
+    fn value_message(&self) -> Option<ValueMessage> {
+        self.value.as_ref().map(|(timestamp, value)| ValueMessage {
+            id: self.id,
+            timestamp: *timestamp,
+            type_id: type_id(&self.type_name),
+            value: value.clone(),
+        })
+    }
+}
+
+struct Subscription {
+    topics: Vec<String>,
+    options: SubscribeOptions,
+}
+
+impl Subscription {
+    fn matches(&self, name: &str) -> bool {
+        self.topics.iter().any(|topic| {
+            if self.options.prefix {
+                name.starts_with(topic.as_str())
+            } else {
+                name == topic
+            }
+        })
+    }
+}
+
+enum Outgoing {
+    Control(ServerMessage),
+    Value(ValueMessage),
+}
+
+struct Client {
+    subscriptions: HashMap<i64, Subscription>,
+    /// Topic names by publisher id
+    publishers: HashMap<i64, String>,
+    announced: HashSet<String>,
+    outbox: Sender<Outgoing>,
+}
+
+impl Client {
+    /// Whether any subscription wants values, not just announcements
+    fn wants_values(&self, name: &str) -> bool {
+        self.subscriptions
+            .values()
+            .any(|sub| !sub.options.topicsonly && sub.matches(name))
+    }
+
+    fn subscribed(&self, name: &str) -> bool {
+        self.subscriptions.values().any(|sub| sub.matches(name))
+    }
+
+    fn send(&self, message: Outgoing) {
+        // The client thread drops the receiver when it disconnects, and is
+        // removed right after, so a failed send can be ignored
+        let _ = self.outbox.send(message);
+    }
+
+    fn announce(&mut self, name: &str, topic: &Topic, pubuid: Option<i64>) {
+        self.announced.insert(name.to_string());
+        self.send(Outgoing::Control(ServerMessage::Announce {
+            name: name.to_string(),
+            id: topic.id,
+            type_name: topic.type_name.clone(),
+            pubuid,
+            properties: topic.properties.clone(),
+        }));
+    }
+}
+
+struct ServerState {
+    start: Instant,
+    topics: HashMap<String, Topic>,
+    next_topic_id: i64,
+    clients: HashMap<u64, Client>,
+    next_client_id: u64,
+}
+
+impl ServerState {
+    /// Server time in microseconds, which clients sync their clocks to
+    fn now(&self) -> i64 {
+        self.start.elapsed().as_micros() as i64
+    }
+
+    fn connect(&mut self, outbox: Sender<Outgoing>) -> u64 {
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+        self.clients.insert(
+            id,
+            Client {
+                subscriptions: HashMap::new(),
+                publishers: HashMap::new(),
+                announced: HashSet::new(),
+                outbox,
+            },
+        );
+        id
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        if let Some(client) = self.clients.remove(&client_id) {
+            for name in client.publishers.into_values() {
+                self.remove_publisher(&name);
+            }
+        }
+    }
+
+    /// Adds a publisher to a topic, creating and announcing it if needed.
+    /// Returns false if the topic exists with a different type.
+    fn add_publisher(&mut self, name: &str, type_name: &str, properties: Properties) -> bool {
+        if let Some(topic) = self.topics.get_mut(name) {
+            if topic.type_name != type_name {
+                return false;
+            }
+            topic.publishers += 1;
+            return true;
+        }
+
+        let topic = Topic {
+            id: self.next_topic_id,
+            type_name: type_name.to_string(),
+            properties,
+            value: None,
+            publishers: 1,
+        };
+        self.next_topic_id += 1;
+        for client in self.clients.values_mut() {
+            if client.subscribed(name) {
+                client.announce(name, &topic, None);
+            }
+        }
+        self.topics.insert(name.to_string(), topic);
+        true
+    }
+
+    fn remove_publisher(&mut self, name: &str) {
+        let Some(topic) = self.topics.get_mut(name) else {
+            return;
+        };
+        topic.publishers = topic.publishers.saturating_sub(1);
+        if topic.publishers > 0 || topic.retained() {
+            return;
+        }
+
+        let id = topic.id;
+        self.topics.remove(name);
+        for client in self.clients.values_mut() {
+            if client.announced.remove(name) {
+                client.send(Outgoing::Control(ServerMessage::Unannounce {
+                    name: name.to_string(),
+                    id,
+                }));
+            }
+        }
+    }
+
+    /// Stores a value and forwards it to subscribers other than its source
+    fn set_value(&mut self, name: &str, timestamp: i64, value: rmpv::Value, source: Option<u64>) {
+        let Some(topic) = self.topics.get_mut(name) else {
+            return;
+        };
+        topic.value = Some((timestamp, value));
+        let Some(message) = topic.value_message() else {
+            return;
+        };
+        for (&client_id, client) in &self.clients {
+            if Some(client_id) != source
+                && client.announced.contains(name)
+                && client.wants_values(name)
+            {
+                client.send(Outgoing::Value(message.clone()));
+            }
+        }
+    }
+
+    fn handle_control(&mut self, client_id: u64, message: ClientMessage) {
+        match message {
+            ClientMessage::Publish {
+                name,
+                pubuid,
+                type_name,
+                properties,
+            } => {
+                if !self.add_publisher(&name, &type_name, properties) {
+                    println!(
+                        "NetworkTables: ignoring publish of {} as {}, it already has another type",
+                        name, type_name
+                    );
+                    return;
+                }
+                let Some(client) = self.clients.get_mut(&client_id) else {
+                    return;
+                };
+                client.publishers.insert(pubuid, name.clone());
+                // Publishers are always told the topic id, even if they
+                // don't subscribe to it
+                client.announce(&name, &self.topics[&name], Some(pubuid));
+            }
+            ClientMessage::Unpublish { pubuid } => {
+                let name = self
+                    .clients
+                    .get_mut(&client_id)
+                    .and_then(|client| client.publishers.remove(&pubuid));
+                if let Some(name) = name {
+                    self.remove_publisher(&name);
+                }
+            }
+            ClientMessage::SetProperties { name, update } => {
+                let Some(topic) = self.topics.get_mut(&name) else {
+                    return;
+                };
+                for (key, value) in &update {
+                    if value.is_null() {
+                        topic.properties.remove(key);
+                    } else {
+                        topic.properties.insert(key.clone(), value.clone());
+                    }
+                }
+                for (&id, client) in &self.clients {
+                    if client.announced.contains(&name) {
+                        client.send(Outgoing::Control(ServerMessage::Properties {
+                            name: name.clone(),
+                            ack: id == client_id,
+                            update: update.clone(),
+                        }));
+                    }
+                }
+            }
+            ClientMessage::Subscribe {
+                topics,
+                subuid,
+                options,
+            } => {
+                let Some(client) = self.clients.get_mut(&client_id) else {
+                    return;
+                };
+                let subscription = Subscription { topics, options };
+                for (name, topic) in &self.topics {
+                    if !subscription.matches(name) {
+                        continue;
+                    }
+                    if !client.announced.contains(name) {
+                        client.announce(name, topic, None);
+                    }
+                    if !subscription.options.topicsonly {
+                        if let Some(message) = topic.value_message() {
+                            client.send(Outgoing::Value(message));
+                        }
+                    }
+                }
+                client.subscriptions.insert(subuid, subscription);
+            }
+            ClientMessage::Unsubscribe { subuid } => {
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.subscriptions.remove(&subuid);
+                }
+            }
+        }
+    }
+
+    fn handle_value(&mut self, client_id: u64, message: ValueMessage) {
+        if message.id == TIME_SYNC_ID {
+            // Echo the client's time back with ours so it can compute the
+            // offset between the clocks
+            if let Some(client) = self.clients.get(&client_id) {
+                client.send(Outgoing::Value(ValueMessage {
+                    id: TIME_SYNC_ID,
+                    timestamp: self.now(),
+                    type_id: message.type_id,
+                    value: message.value,
+                }));
+            }
+            return;
+        }
+
+        let Some(name) = self
+            .clients
+            .get(&client_id)
+            .and_then(|client| client.publishers.get(&message.id))
+            .cloned()
+        else {
+            return;
+        };
+        let timestamp = if message.timestamp == 0 {
+            self.now()
+        } else {
+            message.timestamp
+        };
+        self.set_value(&name, timestamp, message.value, Some(client_id));
+    }
+}
+
+/// NetworkTables 4 server. Clients are served on background threads; the sim
+/// publishes and reads topics through this handle.
+#[derive(Resource, Clone)]
+pub struct NtServer {
+    state: Arc<Mutex<ServerState>>,
+    port: u16,
+}
+
+impl NtServer {
+    /// Starts listening for clients on all interfaces. Port 0 picks any free
+    /// port.
+    pub fn start(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let server = Self {
+            port: listener.local_addr()?.port(),
+            state: Arc::new(Mutex::new(ServerState {
+                start: Instant::now(),
+                topics: HashMap::new(),
+                next_topic_id: 1,
+                clients: HashMap::new(),
+                next_client_id: 0,
+            })),
+        };
+
+        let accept_server = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let client_server = accept_server.clone();
+                thread::spawn(move || {
+                    if let Err(e) = client_server.serve(stream) {
+                        println!("NetworkTables client error: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    /// Port the server is listening on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ServerState> {
+        // A panicking client thread shouldn't take the sim down with it
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets a sim-owned topic, creating it on first use. Unchanged values
+    /// aren't resent.
+    pub fn publish(&self, name: &str, value: Value) {
+        let mut state = self.lock();
+        let type_name = value.type_name();
+        let value = value.to_msgpack();
+        match state.topics.get(name) {
+            Some(topic) if topic.value.as_ref().is_some_and(|(_, v)| *v == value) => return,
+            Some(_) => {}
+            None => {
+                state.add_publisher(name, type_name, Properties::new());
+            }
+        }
+        let now = state.now();
+        state.set_value(name, now, value, None);
+    }
+
+    /// Latest number on a topic, as long as a client is publishing it
+    pub fn get_f64(&self, name: &str) -> Option<f64> {
+        let state = self.lock();
+        let topic = state.topics.get(name)?;
+        if topic.publishers == 0 {
+            return None;
+        }
+        topic.value.as_ref().and_then(|(_, value)| as_f64(value))
+    }
+
+    // The handshake callback's error type is set by tungstenite
+    #[allow(clippy::result_large_err)]
+    fn serve(&self, stream: TcpStream) -> Result<(), String> {
+        let address = stream.peer_addr().map_err(|e| e.to_string())?;
+        let mut client_name = String::new();
+        let mut socket = tungstenite::accept_hdr(stream, |request: &Request, response| {
+            accept_subprotocol(request, response, &mut client_name)
+        })
+        .map_err(|e| e.to_string())?;
+        socket
+            .get_ref()
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|e| e.to_string())?;
+
+        println!(
+            "NetworkTables client {} connected from {}",
+            client_name, address
+        );
+        let (outbox, inbox) = mpsc::channel();
+        let client_id = self.lock().connect(outbox);
+        let result = self.run_client(client_id, &mut socket, &inbox);
+        self.lock().disconnect(client_id);
+        println!("NetworkTables client {} disconnected", client_name);
+        result
+    }
+
+    fn run_client(
+        &self,
+        client_id: u64,
+        socket: &mut WebSocket<TcpStream>,
+        inbox: &Receiver<Outgoing>,
+    ) -> Result<(), String> {
+        loop {
+            match socket.read() {
+                // Malformed messages are skipped rather than dropping the
+                // client, like ntcore does
+                Ok(Message::Text(text)) => match parse_control(text.as_str()) {
+                    Ok(messages) => {
+                        let mut state = self.lock();
+                        for message in messages {
+                            state.handle_control(client_id, message);
+                        }
+                    }
+                    Err(e) => println!("NetworkTables: ignoring control message: {}", e),
+                },
+                Ok(Message::Binary(bytes)) => match ValueMessage::decode_all(&bytes) {
+                    Ok(messages) => {
+                        let mut state = self.lock();
+                        for message in messages {
+                            state.handle_value(client_id, message);
+                        }
+                    }
+                    Err(e) => println!("NetworkTables: ignoring value message: {}", e),
+                },
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+
+            // Batch everything queued since the last poll into one text and
+            // one binary frame. Announcements go first so clients know the
+            // topic ids used in the values.
+            let mut control = Vec::new();
+            let mut values = Vec::new();
+            for message in inbox.try_iter() {
+                match message {
+                    Outgoing::Control(message) => control.push(message),
+                    Outgoing::Value(message) => message.encode(&mut values),
+                }
+            }
+            if !control.is_empty() {
+                let text = serde_json::to_string(&control).map_err(|e| e.to_string())?;
+                socket
+                    .write(Message::text(text))
+                    .map_err(|e| e.to_string())?;
+            }
+            if !values.is_empty() {
+                socket
+                    .write(Message::binary(values))
+                    .map_err(|e| e.to_string())?;
+            }
+            socket.flush().map_err(|e| e.to_string())?;
+        }
+    }
+}
+
+/// Parses a text frame, skipping messages we don't understand
+fn parse_control(text: &str) -> Result<Vec<ClientMessage>, String> {
+    let messages: Vec<serde_json::Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    Ok(messages
+        .into_iter()
+        .filter_map(|message| match serde_json::from_value(message) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                println!("NetworkTables: ignoring control message: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Picks the newest NT4 subprotocol the client offers and reads the client
+/// name from the `/nt/<name>` path
+#[allow(clippy::result_large_err)]
+fn accept_subprotocol(
+    request: &Request,
+    mut response: Response,
+    client_name: &mut String,
+) -> Result<Response, ErrorResponse> {
+    let offered = request
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let Some(protocol) = SUBPROTOCOLS
+        .iter()
+        .find(|protocol| offered.contains(protocol))
+    else {
+        let mut error = ErrorResponse::new(Some("unsupported NetworkTables version".to_string()));
+        *error.status_mut() = StatusCode::BAD_REQUEST;
+        return Err(error);
+    };
+
+    response
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocol));
+    *client_name = request
+        .uri()
+        .path()
+        .strip_prefix("/nt/")
+        .unwrap_or_default()
+        .to_string();
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tungstenite::client::IntoClientRequest;
+
+    /// How long a test waits for the server before giving up
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    type ClientSocket = WebSocket<TcpStream>;
+
+    fn connect(server: &NtServer, protocols: &str) -> Result<(ClientSocket, String), String> {
+        let mut request = format!("ws://127.0.0.1:{}/nt/test", server.port())
+            .into_client_request()
+            .map_err(|e| e.to_string())?;
+        request.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_str(protocols).map_err(|e| e.to_string())?,
+        );
+        let stream = TcpStream::connect(("127.0.0.1", server.port())).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|e| e.to_string())?;
+        let (socket, response) = tungstenite::client(request, stream).map_err(|e| e.to_string())?;
+        let protocol = response
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        Ok((socket, protocol))
+    }
+
+    fn send_control(socket: &mut ClientSocket, messages: serde_json::Value) {
+        socket.send(Message::text(messages.to_string())).unwrap();
+    }
+
+    fn send_values(socket: &mut ClientSocket, messages: &[ValueMessage]) {
+        let mut bytes = Vec::new();
+        for message in messages {
+            message.encode(&mut bytes);
+        }
+        socket.send(Message::binary(bytes)).unwrap();
+    }
+
+    /// Reads frames until `found` picks something out of one
+    fn read_until<T>(socket: &mut ClientSocket, mut found: impl FnMut(Message) -> Option<T>) -> T {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            match socket.read() {
+                Ok(message) => {
+                    if let Some(result) = found(message) {
+                        return result;
+                    }
+                }
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(e) => panic!("connection failed: {}", e),
+            }
+        }
+        panic!("timed out waiting for the server");
+    }
+
+    /// Waits for the announcement of `name` and returns its JSON params
+    fn read_announce(socket: &mut ClientSocket, name: &str) -> serde_json::Value {
+        read_until(socket, |message| {
+            let Message::Text(text) = message else {
+                return None;
+            };
+            let messages: Vec<serde_json::Value> = serde_json::from_str(text.as_str()).unwrap();
+            messages.into_iter().find_map(|message| {
+                (message["method"] == "announce" && message["params"]["name"] == name)
+                    .then(|| message["params"].clone())
+            })
+        })
+    }
+
+    fn read_value(socket: &mut ClientSocket, id: i64) -> ValueMessage {
+        read_until(socket, |message| {
+            let Message::Binary(bytes) = message else {
+                return None;
+            };
+            ValueMessage::decode_all(&bytes)
+                .unwrap()
+                .into_iter()
+                .find(|message| message.id == id)
+        })
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for the server"
+            );
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn parses_control_messages_and_skips_unknown_ones() {
+        let text = serde_json::json!([
+            {"method": "publish", "params": {"name": "/Arm/Voltage", "pubuid": 3, "type": "double"}},
+            {"method": "subscribe", "params": {"topics": ["/Sim/"], "subuid": 1, "options": {"prefix": true}}},
+            {"method": "announce", "params": {"name": "/Arm/Voltage"}},
+            {"method": "unpublish", "params": {}},
+        ])
+        .to_string();
+        let messages = parse_control(&text).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(
+            &messages[0],
+            ClientMessage::Publish { name, pubuid: 3, type_name, .. }
+                if name == "/Arm/Voltage" && type_name == "double"
+        ));
+        assert!(matches!(
+            &messages[1],
+            ClientMessage::Subscribe { topics, subuid: 1, options }
+                if topics == &["/Sim/"] && options.prefix && !options.topicsonly
+        ));
+
+        assert!(parse_control("not json").is_err());
+        assert!(parse_control("{\"method\": \"publish\"}").is_err());
+    }
+
+    #[test]
+    fn negotiates_the_newest_subprotocol() {
+        let server = NtServer::start(0).unwrap();
+        let both = SUBPROTOCOLS
+            .iter()
+            .rev()
+            .copied()
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(connect(&server, &both).unwrap().1, SUBPROTOCOLS[0]);
+        assert_eq!(
+            connect(&server, SUBPROTOCOLS[1]).unwrap().1,
+            SUBPROTOCOLS[1]
+        );
+        assert!(connect(&server, "v3.networktables.first.wpi.edu").is_err());
+    }
+
+    #[test]
+    fn announces_and_sends_sim_values_to_subscribers() {
+        let server = NtServer::start(0).unwrap();
+        server.publish("/Sim/Height", Value::Double(12.5));
+        server.publish("/Other", Value::Double(1.0));
+
+        let (mut socket, _) = connect(&server, SUBPROTOCOLS[0]).unwrap();
+        send_control(
+            &mut socket,
+            serde_json::json!([{"method": "subscribe", "params": {
+                "topics": ["/Sim/"], "subuid": 1, "options": {"prefix": true}
+            }}]),
+        );
+        let announce = read_announce(&mut socket, "/Sim/Height");
+        assert_eq!(announce["type"], "double");
+        assert!(announce.get("pubuid").is_none());
+        let id = announce["id"].as_i64().unwrap();
+        assert_eq!(read_value(&mut socket, id).value, rmpv::Value::from(12.5));
+
+        // Topics created after the subscription are announced too
+        server.publish("/Sim/Mode", Value::String("Stow".to_string()));
+        let mode = read_announce(&mut socket, "/Sim/Mode")["id"]
+            .as_i64()
+            .unwrap();
+        assert_eq!(
+            read_value(&mut socket, mode).value,
+            rmpv::Value::from("Stow")
+        );
+
+        server.publish("/Sim/Height", Value::Double(20.0));
+        assert_eq!(read_value(&mut socket, id).value, rmpv::Value::from(20.0));
+    }
+
+    #[test]
+    fn reads_values_published_by_clients() {
+        let server = NtServer::start(0).unwrap();
+        let (mut socket, _) = connect(&server, SUBPROTOCOLS[0]).unwrap();
+        send_control(
+            &mut socket,
+            serde_json::json!([{"method": "publish", "params": {
+                "name": "/Robot/Voltage", "pubuid": 7, "type": "double"
+            }}]),
+        );
+        // Publishers are told the topic id without subscribing
+        let announce = read_announce(&mut socket, "/Robot/Voltage");
+        assert_eq!(announce["pubuid"], 7);
+
+        send_values(
+            &mut socket,
+            &[ValueMessage {
+                id: 7,
+                timestamp: 0,
+                type_id: type_id("double"),
+                value: rmpv::Value::from(3.25),
+            }],
+        );
+        wait_for(|| server.get_f64("/Robot/Voltage") == Some(3.25));
+
+        send_control(
+            &mut socket,
+            serde_json::json!([{"method": "unpublish", "params": {"pubuid": 7}}]),
+        );
+        wait_for(|| server.get_f64("/Robot/Voltage").is_none());
+    }
+
+    #[test]
+    fn echoes_time_sync_with_server_time() {
+        let server = NtServer::start(0).unwrap();
+        let (mut socket, _) = connect(&server, SUBPROTOCOLS[0]).unwrap();
+        send_values(
+            &mut socket,
+            &[ValueMessage {
+                id: TIME_SYNC_ID,
+                timestamp: 0,
+                type_id: type_id("int"),
+                value: rmpv::Value::from(1234),
+            }],
+        );
+        let reply = read_value(&mut socket, TIME_SYNC_ID);
+        assert_eq!(reply.value, rmpv::Value::from(1234));
+        assert!(reply.timestamp > 0);
+    }
+}
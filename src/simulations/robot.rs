@@ -0,0 +1,1180 @@
+use std::fs;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::math::{Isometry, Vector};
+use serde::Deserialize;
+
+use crate::simulations::grid_file::GeometryHasher;
+use crate::simulations::rules::{Rules, RulesDescription};
+
+pub const ROBOT_FILE: &str = "robot.ron";
+const BUILTIN_ROBOT: &str = include_str!("../../robot.ron");
+pub const SIM_UNITS_PER_INCH: f32 = 2.54;
+pub const SIM_UNITS_PER_METER: f32 = 100.0;
+const METERS_PER_INCH: f32 = 0.0254;
+const KILOGRAMS_PER_POUND: f32 = 0.453_592;
+const NEWTONS_PER_POUND_FORCE: f32 = 4.448_222;
+/// Matches Rapier's default gravity, in m/s^2
+pub const GRAVITY: f32 = 9.81;
+
+pub const ELEVATOR: Group = Group::GROUP_1;
+pub const INTAKE: Group = Group::GROUP_2;
+/// Coral and algae, which hit the robot's obstacles, the intake and each other
+pub const GAME_PIECE: Group = Group::GROUP_3;
+
+/// Half width of the ground plane, in inches. Wider than anything the arm
+/// can reach.
+const FLOOR_HALF_WIDTH: f32 = 200.0;
+const FLOOR_THICKNESS: f32 = 2.0;
+
+/// Link geometry as written in the description file (inches)
+#[derive(Debug, Clone, Deserialize)]
+pub enum ShapeDescription {
+    Cuboid { width: f32, height: f32 },
+    Ball { radius: f32 },
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum CollisionLayer {
+    #[default]
+    None,
+    Elevator,
+    Intake,
+}
+
+impl CollisionLayer {
+    pub fn groups(self) -> CollisionGroups {
+        match self {
+            Self::None => CollisionGroups::new(Group::NONE, Group::NONE),
+            Self::Elevator => CollisionGroups::new(ELEVATOR, INTAKE | GAME_PIECE),
+            Self::Intake => CollisionGroups::new(INTAKE, ELEVATOR | GAME_PIECE),
+        }
+    }
+}
+
+/// Whether Rapier lets colliders in these groups touch
+pub fn groups_interact(a: CollisionGroups, b: CollisionGroups) -> bool {
+    a.memberships.intersects(b.filters) && b.memberships.intersects(a.filters)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkDescription {
+    pub name: String,
+    pub shape: ShapeDescription,
+    #[serde(default)]
+    pub layer: CollisionLayer,
+    #[serde(default)]
+    pub lock_rotation: bool,
+    /// Mounted to the side of the base link, so it swings past it. The
+    /// sweeps still test it against the chassis.
+    #[serde(default)]
+    pub beside_base: bool,
+    /// Pounds. Links without a mass get Rapier's default density.
+    #[serde(default)]
+    pub mass: Option<f32>,
+    /// Inches from the shape center, in the link frame
+    #[serde(default)]
+    pub center_of_mass: (f32, f32),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum JointKindDescription {
+    Prismatic {
+        axis: (f32, f32),
+        limits: (f32, f32),
+    },
+    Revolute {
+        #[serde(default)]
+        initial_angle: f32,
+    },
+    Fixed,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JointDescription {
+    pub name: String,
+    pub parent: String,
+    pub child: String,
+    pub kind: JointKindDescription,
+    pub parent_anchor: (f32, f32),
+    pub child_anchor: (f32, f32),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum MotorType {
+    KrakenX60,
+    Neo,
+}
+
+/// Motors driving a joint through a gearbox. Prismatic joints are driven by a
+/// spool, so they need its diameter (inches).
+#[derive(Debug, Clone, Deserialize)]
+pub struct MotorDescription {
+    pub joint: String,
+    pub motor: MotorType,
+    #[serde(default = "default_motor_count")]
+    pub count: u32,
+    pub gear_ratio: f32,
+    #[serde(default)]
+    pub spool_diameter: Option<f32>,
+    /// Stator current limit per motor in amps
+    pub current_limit: f32,
+}
+
+fn default_motor_count() -> u32 {
+    1
+}
+
+/// Passive support for the elevator carriage
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ElevatorAssist {
+    /// Constant upward force in pounds-force
+    ConstantForceSpring { force: f32 },
+    /// Counterweight in pounds riding a pulley opposite the carriage
+    Counterweight { mass: f32 },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BatteryDescription {
+    pub voltage: f32,
+    /// Internal resistance plus wiring in ohms
+    pub resistance: f32,
+}
+
+impl Default for BatteryDescription {
+    fn default() -> Self {
+        Self {
+            voltage: 12.6,
+            resistance: 0.015,
+        }
+    }
+}
+
+/// Frame rails around the elevator
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct FrameDescription {
+    /// Frame perimeter in front of the elevator centerline
+    pub front: f32,
+    /// Frame perimeter behind the elevator centerline
+    pub back: f32,
+    /// Bottom of the rails above the carpet
+    pub bottom: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BumperDescription {
+    pub thickness: f32,
+    /// Bottom of the bumpers above the carpet
+    pub bottom: f32,
+    pub height: f32,
+}
+
+/// Battery standing on the belly pan
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BatteryBoxDescription {
+    /// Center along the robot from the elevator centerline
+    pub position: f32,
+    pub length: f32,
+    pub height: f32,
+}
+
+/// A module sits inside each end of the frame, standing on the carpet
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SwerveModuleDescription {
+    pub length: f32,
+    pub height: f32,
+}
+
+/// Drivetrain seen from the side, in inches. Lengths run along the robot
+/// from the elevator centerline, forwards positive; heights run up from the
+/// carpet.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ChassisDescription {
+    /// Elevator center above the carpet
+    pub elevator_height: f32,
+    pub frame: FrameDescription,
+    pub bumper: BumperDescription,
+    pub battery: BatteryBoxDescription,
+    pub swerve_module: SwerveModuleDescription,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RobotDescription {
+    pub links: Vec<LinkDescription>,
+    pub joints: Vec<JointDescription>,
+    pub motors: Vec<MotorDescription>,
+    #[serde(default)]
+    pub battery: BatteryDescription,
+    #[serde(default)]
+    pub elevator_assist: Option<ElevatorAssist>,
+    /// Drivetrain and ground plane, fixed obstacles like the elevator
+    #[serde(default)]
+    pub chassis: Option<ChassisDescription>,
+    /// Extension and height limits, measured from the chassis
+    #[serde(default)]
+    pub rules: Option<RulesDescription>,
+}
+
+/// Link shape in sim units
+#[derive(Debug, Clone, Copy)]
+pub enum LinkShape {
+    Cuboid { half_extents: Vec2 },
+    Ball { radius: f32 },
+}
+
+impl LinkShape {
+    pub fn collider(&self) -> Collider {
+        match *self {
+            Self::Cuboid { half_extents } => Collider::cuboid(half_extents.x, half_extents.y),
+            Self::Ball { radius } => Collider::ball(radius),
+        }
+    }
+
+    /// Tightest box around the shape at a pose. A ball's box stays axis
+    /// aligned whatever the rotation.
+    pub fn bounding_box(&self, pose: &LinkPose) -> Obb {
+        let (half_extents, rotation) = match *self {
+            Self::Cuboid { half_extents } => (half_extents, pose.rotation),
+            Self::Ball { radius } => (Vec2::splat(radius), 0.0),
+        };
+        Obb {
+            center: pose.translation,
+            half_extents,
+            rotation,
+        }
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        match *self {
+            Self::Cuboid { half_extents } => Rectangle::from_size(half_extents * 2.0).into(),
+            Self::Ball { radius } => Circle::new(radius).into(),
+        }
+    }
+
+    /// Moment of inertia about the shape center for a uniform mass
+    pub fn inertia(&self, mass: f32) -> f32 {
+        match *self {
+            Self::Cuboid { half_extents } => mass * half_extents.length_squared() / 3.0,
+            Self::Ball { radius } => mass * radius * radius / 2.0,
+        }
+    }
+}
+
+pub struct Link {
+    pub name: String,
+    pub shape: LinkShape,
+    pub groups: CollisionGroups,
+    pub lock_rotation: bool,
+    pub beside_base: bool,
+    /// Kilograms
+    pub mass: Option<f32>,
+    /// Sim units in the link frame
+    pub center_of_mass: Vec2,
+}
+
+impl Link {
+    /// Mass properties for the collider, or `None` to use the default density
+    pub fn mass_properties(&self) -> Option<ColliderMassProperties> {
+        let mass = self.mass?;
+        Some(ColliderMassProperties::MassProperties(MassProperties {
+            local_center_of_mass: self.center_of_mass,
+            mass,
+            principal_inertia: self.shape.inertia(mass),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum JointKind {
+    Prismatic { axis: Vec2, limits: [f32; 2] },
+    Revolute { initial_angle: f32 },
+    Fixed,
+}
+
+pub struct Joint {
+    pub name: String,
+    pub parent: usize,
+    pub child: usize,
+    pub kind: JointKind,
+    pub parent_anchor: Vec2,
+    pub child_anchor: Vec2,
+}
+
+/// Motors on a joint with the spool converted to meters
+#[derive(Debug, Clone, Copy)]
+pub struct Motor {
+    pub joint: usize,
+    pub motor: MotorType,
+    pub count: u32,
+    pub gear_ratio: f32,
+    pub spool_radius: Option<f32>,
+    pub current_limit: f32,
+}
+
+/// World placement of a link
+#[derive(Debug, Clone, Copy)]
+pub struct LinkPose {
+    pub translation: Vec2,
+    pub rotation: f32,
+}
+
+impl LinkPose {
+    pub fn transform_point(&self, local: Vec2) -> Vec2 {
+        self.translation + Vec2::from_angle(self.rotation).rotate(local)
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_xyz(self.translation.x, self.translation.y, 0.0)
+            .with_rotation(Quat::from_rotation_z(self.rotation))
+    }
+
+    /// Same pose as a parry isometry for direct shape queries
+    pub fn isometry(&self) -> Isometry<f32> {
+        Isometry::new(
+            Vector::new(self.translation.x, self.translation.y),
+            self.rotation,
+        )
+    }
+}
+
+/// Oriented bounding box of a link in the world
+#[derive(Debug, Clone, Copy)]
+pub struct Obb {
+    pub center: Vec2,
+    pub half_extents: Vec2,
+    pub rotation: f32,
+}
+
+impl Obb {
+    pub fn corners(&self) -> [Vec2; 4] {
+        let axes = Vec2::from_angle(self.rotation);
+        let Vec2 { x, y } = self.half_extents;
+        [(-x, -y), (x, -y), (x, y), (-x, y)]
+            .map(|(x, y)| self.center + axes.rotate(Vec2::new(x, y)))
+    }
+}
+
+/// World pose and bounding box of every link at one arm pose, indexed like
+/// `RobotConfig::links`. Derefs to the poses.
+#[derive(Debug, Clone)]
+pub struct LinkPoses {
+    pub poses: Vec<LinkPose>,
+    pub boxes: Vec<Obb>,
+}
+
+impl std::ops::Deref for LinkPoses {
+    type Target = [LinkPose];
+
+    fn deref(&self) -> &Self::Target {
+        &self.poses
+    }
+}
+
+/// Obstacle that never moves, placed in the world
+#[derive(Debug, Clone)]
+pub struct FixedPart {
+    pub name: String,
+    pub shape: LinkShape,
+    pub pose: LinkPose,
+    pub groups: CollisionGroups,
+}
+
+/// Drivetrain and ground plane in sim units, in the elevator's frame
+#[derive(Debug, Clone)]
+pub struct Chassis {
+    /// Carpet height
+    pub floor: f32,
+    /// Frame perimeter in front of and behind the elevator centerline
+    pub frame_front: f32,
+    pub frame_back: f32,
+    pub bumper_thickness: f32,
+    /// Frame, bumpers, battery, swerve modules and floor
+    pub parts: Vec<FixedPart>,
+}
+
+impl Chassis {
+    fn new(description: ChassisDescription) -> Self {
+        let ChassisDescription {
+            elevator_height,
+            frame,
+            bumper,
+            battery,
+            swerve_module: module,
+            ..
+        } = description;
+        // Corners in inches, x along the robot and y above the carpet
+        let part = |name: &str, min: Vec2, max: Vec2| {
+            let center = (min + max) / 2.0 - Vec2::new(0.0, elevator_height);
+            FixedPart {
+                name: name.to_string(),
+                shape: LinkShape::Cuboid {
+                    half_extents: (max - min) * SIM_UNITS_PER_INCH / 2.0,
+                },
+                pose: LinkPose {
+                    translation: center * SIM_UNITS_PER_INCH,
+                    rotation: 0.0,
+                },
+                groups: CollisionLayer::Elevator.groups(),
+            }
+        };
+        let bumper_y = [bumper.bottom, bumper.bottom + bumper.height];
+        let battery_x = battery.position - battery.length / 2.0;
+
+        let parts = vec![
+            part(
+                "frame",
+                Vec2::new(-frame.back, frame.bottom),
+                Vec2::new(frame.front, frame.bottom + frame.height),
+            ),
+            part(
+                "front bumper",
+                Vec2::new(frame.front, bumper_y[0]),
+                Vec2::new(frame.front + bumper.thickness, bumper_y[1]),
+            ),
+            part(
+                "back bumper",
+                Vec2::new(-frame.back - bumper.thickness, bumper_y[0]),
+                Vec2::new(-frame.back, bumper_y[1]),
+            ),
+            part(
+                "battery",
+                Vec2::new(battery_x, frame.bottom),
+                Vec2::new(battery_x + battery.length, frame.bottom + battery.height),
+            ),
+            part(
+                "front swerve module",
+                Vec2::new(frame.front - module.length, 0.0),
+                Vec2::new(frame.front, module.height),
+            ),
+            part(
+                "back swerve module",
+                Vec2::new(-frame.back, 0.0),
+                Vec2::new(-frame.back + module.length, module.height),
+            ),
+            part(
+                "floor",
+                Vec2::new(-FLOOR_HALF_WIDTH, -FLOOR_THICKNESS),
+                Vec2::new(FLOOR_HALF_WIDTH, 0.0),
+            ),
+        ];
+
+        Self {
+            floor: -elevator_height * SIM_UNITS_PER_INCH,
+            frame_front: frame.front * SIM_UNITS_PER_INCH,
+            frame_back: frame.back * SIM_UNITS_PER_INCH,
+            bumper_thickness: bumper.thickness * SIM_UNITS_PER_INCH,
+            parts,
+        }
+    }
+}
+
+/// Robot geometry in sim units, shared by the main sim and the grid generators
+#[derive(Resource)]
+pub struct RobotConfig {
+    pub links: Vec<Link>,
+    /// Joints ordered so every parent is placed before its child
+    pub joints: Vec<Joint>,
+    pub motors: Vec<Motor>,
+    pub battery: BatteryDescription,
+    /// Converted to newtons and kilograms
+    pub elevator_assist: Option<ElevatorAssist>,
+    /// Identifies this geometry in generated grid files
+    pub geometry_hash: u64,
+    pub base: usize,
+    elevator_joint: usize,
+    arm_joint: usize,
+    tip_joint: usize,
+    intake: usize,
+    initial_poses: Vec<LinkPose>,
+    pub chassis: Option<Chassis>,
+    pub rules: Option<Rules>,
+}
+
+impl RobotConfig {
+    /// Loads the description from disk, falling back to the copy built into
+    /// the binary if the file is missing or invalid
+    pub fn load_or_default(path: &str) -> Self {
+        match Self::load(path) {
+            Ok(robot) => {
+                println!("Loaded robot description from {}", path);
+                robot
+            }
+            Err(e) => {
+                println!("Failed to load robot description from {}: {}", path, e);
+                Self::parse(BUILTIN_ROBOT).expect("built-in robot description is valid")
+            }
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let description: RobotDescription = ron::from_str(contents).map_err(|e| e.to_string())?;
+        Self::from_description(&description)
+    }
+
+    pub fn from_description(description: &RobotDescription) -> Result<Self, String> {
+        let links: Vec<Link> = description
+            .links
+            .iter()
+            .map(|link| Link {
+                name: link.name.clone(),
+                shape: match link.shape {
+                    ShapeDescription::Cuboid { width, height } => LinkShape::Cuboid {
+                        half_extents: Vec2::new(width, height) * SIM_UNITS_PER_INCH * 0.5,
+                    },
+                    ShapeDescription::Ball { radius } => LinkShape::Ball {
+                        radius: radius * SIM_UNITS_PER_INCH,
+                    },
+                },
+                groups: link.layer.groups(),
+                lock_rotation: link.lock_rotation,
+                beside_base: link.beside_base,
+                mass: link.mass.map(|mass| mass * KILOGRAMS_PER_POUND),
+                center_of_mass: Vec2::new(link.center_of_mass.0, link.center_of_mass.1)
+                    * SIM_UNITS_PER_INCH,
+            })
+            .collect();
+
+        let link_index = |name: &str| {
+            links
+                .iter()
+                .position(|link| link.name == name)
+                .ok_or_else(|| format!("unknown link {:?}", name))
+        };
+        for (i, link) in links.iter().enumerate() {
+            if link_index(&link.name)? != i {
+                return Err(format!("duplicate link {:?}", link.name));
+            }
+        }
+
+        let mut joints = Vec::new();
+        for joint in &description.joints {
+            let to_vec = |(x, y): (f32, f32)| Vec2::new(x, y) * SIM_UNITS_PER_INCH;
+            joints.push(Joint {
+                name: joint.name.clone(),
+                parent: link_index(&joint.parent)?,
+                child: link_index(&joint.child)?,
+                kind: match joint.kind {
+                    JointKindDescription::Prismatic { axis, limits } => JointKind::Prismatic {
+                        axis: Vec2::new(axis.0, axis.1).normalize_or_zero(),
+                        limits: [limits.0 * SIM_UNITS_PER_INCH, limits.1 * SIM_UNITS_PER_INCH],
+                    },
+                    JointKindDescription::Revolute { initial_angle } => JointKind::Revolute {
+                        initial_angle: initial_angle.to_radians(),
+                    },
+                    JointKindDescription::Fixed => JointKind::Fixed,
+                },
+                parent_anchor: to_vec(joint.parent_anchor),
+                child_anchor: to_vec(joint.child_anchor),
+            });
+        }
+
+        // Every link but the base is the child of exactly one joint
+        let mut parents = vec![None; links.len()];
+        for (i, joint) in joints.iter().enumerate() {
+            if parents[joint.child].replace(i).is_some() {
+                return Err(format!(
+                    "link {:?} has two parents",
+                    links[joint.child].name
+                ));
+            }
+        }
+        let roots: Vec<usize> = (0..links.len()).filter(|&i| parents[i].is_none()).collect();
+        let [base] = roots[..] else {
+            return Err(format!("expected one base link, found {}", roots.len()));
+        };
+
+        // Order joints from the base outwards
+        let mut ordered = Vec::with_capacity(joints.len());
+        let mut placed = vec![false; links.len()];
+        placed[base] = true;
+        let mut remaining: Vec<Joint> = joints;
+        while !remaining.is_empty() {
+            let Some(next) = remaining.iter().position(|joint| placed[joint.parent]) else {
+                return Err("joints do not form a tree from the base link".to_string());
+            };
+            let joint = remaining.remove(next);
+            placed[joint.child] = true;
+            ordered.push(joint);
+        }
+
+        let joint_index = |name: &str| {
+            ordered
+                .iter()
+                .position(|joint| joint.name == name)
+                .ok_or_else(|| format!("missing joint {:?}", name))
+        };
+        let elevator_joint = joint_index("elevator")?;
+        let arm_joint = joint_index("arm")?;
+        if !matches!(ordered[elevator_joint].kind, JointKind::Prismatic { .. }) {
+            return Err("the elevator joint must be prismatic".to_string());
+        }
+        if !matches!(ordered[arm_joint].kind, JointKind::Revolute { .. }) {
+            return Err("the arm joint must be revolute".to_string());
+        }
+        let arm_link = ordered[arm_joint].child;
+        let tip_joint = ordered
+            .iter()
+            .position(|joint| joint.parent == arm_link)
+            .ok_or("nothing is mounted on the arm")?;
+        let intake = link_index("intake")?;
+
+        let mut motors = Vec::new();
+        for motor in &description.motors {
+            let joint = joint_index(&motor.joint)?;
+            let spool_radius = motor
+                .spool_diameter
+                .map(|diameter| diameter * METERS_PER_INCH * 0.5);
+            match (ordered[joint].kind, spool_radius) {
+                (JointKind::Fixed, _) => {
+                    return Err(format!("fixed joint {:?} cannot have a motor", motor.joint))
+                }
+                (JointKind::Prismatic { .. }, None) => {
+                    return Err(format!("motor on {:?} needs a spool_diameter", motor.joint))
+                }
+                _ => {}
+            }
+            if motor.count == 0 || motor.gear_ratio <= 0.0 || motor.current_limit <= 0.0 {
+                return Err(format!("invalid motor parameters for {:?}", motor.joint));
+            }
+            motors.push(Motor {
+                joint,
+                motor: motor.motor,
+                count: motor.count,
+                gear_ratio: motor.gear_ratio,
+                spool_radius,
+                current_limit: motor.current_limit,
+            });
+        }
+        for (name, joint) in [("elevator", elevator_joint), ("arm", arm_joint)] {
+            if !motors.iter().any(|motor| motor.joint == joint) {
+                return Err(format!("the {} joint needs a motor", name));
+            }
+        }
+
+        let geometry_hash = hash_geometry(description);
+
+        let chassis = description.chassis.map(Chassis::new);
+        let rules = match (&description.rules, &chassis) {
+            (Some(rules), Some(chassis)) => Some(Rules::new(rules, chassis)),
+            (Some(_), None) => return Err("rules need a chassis to measure from".to_string()),
+            (None, _) => None,
+        };
+
+        let elevator_assist = description.elevator_assist.map(|assist| match assist {
+            ElevatorAssist::ConstantForceSpring { force } => ElevatorAssist::ConstantForceSpring {
+                force: force * NEWTONS_PER_POUND_FORCE,
+            },
+            ElevatorAssist::Counterweight { mass } => ElevatorAssist::Counterweight {
+                mass: mass * KILOGRAMS_PER_POUND,
+            },
+        });
+
+        let mut robot = Self {
+            links,
+            joints: ordered,
+            motors,
+            battery: description.battery,
+            elevator_assist,
+            geometry_hash,
+            base,
+            elevator_joint,
+            arm_joint,
+            tip_joint,
+            intake,
+            initial_poses: Vec::new(),
+            chassis,
+            rules,
+        };
+        robot.initial_poses = robot.place_links(robot.elevator_limits()[0], 0.0, None);
+        Ok(robot)
+    }
+
+    pub fn elevator_joint(&self) -> &Joint {
+        &self.joints[self.elevator_joint]
+    }
+
+    pub fn elevator_joint_index(&self) -> usize {
+        self.elevator_joint
+    }
+
+    pub fn arm_joint(&self) -> &Joint {
+        &self.joints[self.arm_joint]
+    }
+
+    pub fn arm_joint_index(&self) -> usize {
+        self.arm_joint
+    }
+
+    pub fn intake(&self) -> &Link {
+        &self.links[self.intake]
+    }
+
+    pub fn intake_index(&self) -> usize {
+        self.intake
+    }
+
+    /// Elevator joint travel limits in sim units
+    pub fn elevator_limits(&self) -> [f32; 2] {
+        match self.elevator_joint().kind {
+            JointKind::Prismatic { limits, .. } => limits,
+            _ => unreachable!("validated when loading"),
+        }
+    }
+
+    /// Joint travel at the bottom of the elevator. Preset heights are measured
+    /// from here.
+    pub fn elevator_offset(&self) -> f32 {
+        self.elevator_limits()[0]
+    }
+
+    /// Distance from the arm pivot to the joint at the end of the arm
+    pub fn arm_length(&self) -> f32 {
+        let arm = self.arm_joint();
+        let tip = &self.joints[self.tip_joint];
+        (tip.parent_anchor - arm.child_anchor).length()
+    }
+
+    /// World poses of every link (indexed like `links`) for an elevator joint
+    /// travel and arm angle. Other revolute joints stay at their initial
+    /// angle and rotation-locked links keep their initial orientation.
+    fn link_poses(&self, elevator: f32, arm_angle: f32) -> Vec<LinkPose> {
+        self.place_links(elevator, arm_angle, Some(&self.initial_poses))
+    }
+
+    /// Pairs of links whose collision groups interact, using the same pairing
+    /// rule Rapier applies to `CollisionGroups`
+    pub fn interacting_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for a in 0..self.links.len() {
+            for b in a + 1..self.links.len() {
+                if groups_interact(self.links[a].groups, self.links[b].groups) {
+                    pairs.push((a, b));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Pairs the collision sweeps test, indexing the links followed by the
+    /// chassis parts. On top of the links whose groups interact, every link
+    /// that moves is tested against the base and each chassis part whatever
+    /// its layer, except against the base when it rides on it or is mounted
+    /// beside it.
+    pub fn swept_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = self.interacting_pairs();
+        for (link, description) in self.links.iter().enumerate() {
+            if link == self.base {
+                continue;
+            }
+            let rides_on_base = self
+                .joints
+                .iter()
+                .any(|joint| joint.parent == self.base && joint.child == link);
+            let pair = (link.min(self.base), link.max(self.base));
+            if !rides_on_base && !description.beside_base && !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+            for part in 0..self.chassis_parts().len() {
+                pairs.push((link, self.links.len() + part));
+            }
+        }
+        pairs
+    }
+
+    /// Chassis obstacles, empty for a robot without a chassis
+    pub fn chassis_parts(&self) -> &[FixedPart] {
+        self.chassis
+            .as_ref()
+            .map_or(&[], |chassis| chassis.parts.as_slice())
+    }
+
+    /// Everything that never moves: the base link where it is spawned and
+    /// the chassis
+    pub fn fixed_parts(&self) -> Vec<FixedPart> {
+        let base = &self.links[self.base];
+        let mut parts = vec![FixedPart {
+            name: base.name.clone(),
+            shape: base.shape,
+            pose: self.initial_poses[self.base],
+            groups: base.groups,
+        }];
+        parts.extend_from_slice(self.chassis_parts());
+        parts
+    }
+
+    /// Forward kinematics: every link placed for an elevator height above
+    /// its lowest point and an arm angle. The bodies are spawned at
+    /// `forward_kinematics(0.0, 0.0)`, elevator at the bottom and arm level.
+    pub fn forward_kinematics(&self, height: f32, arm_angle: f32) -> LinkPoses {
+        let poses = self.link_poses(height + self.elevator_offset(), arm_angle);
+        let boxes = self
+            .links
+            .iter()
+            .zip(&poses)
+            .map(|(link, pose)| link.shape.bounding_box(pose))
+            .collect();
+        LinkPoses { poses, boxes }
+    }
+
+    /// End of the arm, where the next link is mounted
+    pub fn arm_tip(&self, elevator: f32, arm_angle: f32) -> Vec2 {
+        let poses = self.link_poses(elevator, arm_angle);
+        let tip = &self.joints[self.tip_joint];
+        poses[tip.parent].transform_point(tip.parent_anchor)
+    }
+
+    /// Offset from the arm tip to the intake center and the intake rotation.
+    /// Both are independent of the arm angle because the intake pivot is
+    /// rotation locked.
+    pub fn intake_mount(&self) -> (Vec2, f32) {
+        let [min, _] = self.elevator_limits();
+        let intake = self.link_poses(min, 0.0)[self.intake];
+        (intake.translation - self.arm_tip(min, 0.0), intake.rotation)
+    }
+
+    /// Links carried by a joint: its child and everything mounted on it
+    fn carried_links(&self, joint: usize) -> Vec<usize> {
+        let mut carried = vec![self.joints[joint].child];
+        // Joints are ordered from the base, so one pass reaches every descendant
+        for other in &self.joints[joint + 1..] {
+            if carried.contains(&other.parent) {
+                carried.push(other.child);
+            }
+        }
+        carried
+    }
+
+    fn carried_mass(&self, joint: usize) -> f32 {
+        self.carried_links(joint)
+            .iter()
+            .filter_map(|&link| self.links[link].mass)
+            .sum()
+    }
+
+    /// Net weight in newtons the elevator has to hold, after the assist
+    pub fn elevator_gravity_load(&self) -> f32 {
+        let weight = self.carried_mass(self.elevator_joint) * GRAVITY;
+        match self.elevator_assist {
+            Some(ElevatorAssist::ConstantForceSpring { force }) => weight - force,
+            Some(ElevatorAssist::Counterweight { mass }) => weight - mass * GRAVITY,
+            None => weight,
+        }
+    }
+
+    /// Torque in N*m the arm joint has to hold against gravity with the arm
+    /// horizontal
+    pub fn arm_gravity_torque(&self) -> f32 {
+        let [min, _] = self.elevator_limits();
+        let poses = self.link_poses(min, 0.0);
+        let arm = self.arm_joint();
+        let pivot = poses[arm.child].transform_point(arm.child_anchor);
+        self.carried_links(self.arm_joint)
+            .iter()
+            .filter_map(|&link| {
+                let mass = self.links[link].mass?;
+                let mut point = poses[link].transform_point(self.links[link].center_of_mass);
+                // A rotation-locked link takes the moment of everything it
+                // carries, so the arm only sees the weight where it is mounted
+                let mut current = link;
+                while current != arm.child {
+                    let joint = self.joints.iter().find(|joint| joint.child == current)?;
+                    if self.links[current].lock_rotation {
+                        point = poses[joint.parent].transform_point(joint.parent_anchor);
+                    }
+                    current = joint.parent;
+                }
+                Some(mass * GRAVITY * (point.x - pivot.x) / SIM_UNITS_PER_METER)
+            })
+            .sum()
+    }
+
+    /// Elevator joint travel for a carriage position
+    pub fn elevator_travel(&self, carriage: Vec2) -> f32 {
+        let joint = self.elevator_joint();
+        let JointKind::Prismatic { axis, .. } = joint.kind else {
+            unreachable!("validated when loading")
+        };
+        let base = self.initial_poses[joint.parent];
+        let origin = base.transform_point(joint.parent_anchor)
+            - Vec2::from_angle(base.rotation).rotate(joint.child_anchor);
+        (carriage - origin).dot(Vec2::from_angle(base.rotation).rotate(axis))
+    }
+
+    fn place_links(
+        &self,
+        elevator: f32,
+        arm_angle: f32,
+        locked: Option<&[LinkPose]>,
+    ) -> Vec<LinkPose> {
+        let mut poses = vec![
+            LinkPose {
+                translation: Vec2::ZERO,
+                rotation: 0.0,
+            };
+            self.links.len()
+        ];
+
+        for (i, joint) in self.joints.iter().enumerate() {
+            let parent = poses[joint.parent];
+            let mut anchor = joint.parent_anchor;
+            let mut rotation = parent.rotation;
+            match joint.kind {
+                JointKind::Prismatic { axis, .. } => {
+                    let travel = if i == self.elevator_joint {
+                        elevator
+                    } else {
+                        0.0
+                    };
+                    anchor += axis * travel;
+                }
+                JointKind::Revolute { initial_angle } => {
+                    rotation += if i == self.arm_joint {
+                        arm_angle
+                    } else {
+                        initial_angle
+                    };
+                }
+                JointKind::Fixed => {}
+            }
+            if let Some(locked) = locked {
+                if self.links[joint.child].lock_rotation {
+                    rotation = locked[joint.child].rotation;
+                }
+            }
+
+            let translation = parent.transform_point(anchor)
+                - Vec2::from_angle(rotation).rotate(joint.child_anchor);
+            poses[joint.child] = LinkPose {
+                translation,
+                rotation,
+            };
+        }
+
+        poses
+    }
+}
+
+/// Hash of everything in the description that shapes the generated grids:
+/// the links' shapes and collision settings, the joints, the chassis and the
+/// rule limits. Masses, motors and `enforce` only matter at run time.
+fn hash_geometry(description: &RobotDescription) -> u64 {
+    let mut hasher = GeometryHasher::new();
+    let point = |hasher: &mut GeometryHasher, (x, y): (f32, f32)| {
+        hasher.f32(x).f32(y);
+    };
+
+    hasher.u8(description.links.len() as u8);
+    for link in &description.links {
+        hasher.str(&link.name);
+        match link.shape {
+            ShapeDescription::Cuboid { width, height } => hasher.u8(0).f32(width).f32(height),
+            ShapeDescription::Ball { radius } => hasher.u8(1).f32(radius),
+        };
+        hasher
+            .u8(link.layer as u8)
+            .bool(link.lock_rotation)
+            .bool(link.beside_base);
+    }
+
+    hasher.u8(description.joints.len() as u8);
+    for joint in &description.joints {
+        hasher.str(&joint.name).str(&joint.parent).str(&joint.child);
+        match joint.kind {
+            JointKindDescription::Prismatic { axis, limits } => {
+                hasher.u8(0);
+                point(&mut hasher, axis);
+                point(&mut hasher, limits);
+            }
+            JointKindDescription::Revolute { initial_angle } => {
+                hasher.u8(1).f32(initial_angle);
+            }
+            JointKindDescription::Fixed => {
+                hasher.u8(2);
+            }
+        }
+        point(&mut hasher, joint.parent_anchor);
+        point(&mut hasher, joint.child_anchor);
+    }
+
+    hasher.bool(description.chassis.is_some());
+    if let Some(chassis) = &description.chassis {
+        let ChassisDescription {
+            elevator_height,
+            frame,
+            bumper,
+            battery,
+            swerve_module,
+        } = chassis;
+        for value in [
+            *elevator_height,
+            frame.front,
+            frame.back,
+            frame.bottom,
+            frame.height,
+            bumper.thickness,
+            bumper.bottom,
+            bumper.height,
+            battery.position,
+            battery.length,
+            battery.height,
+            swerve_module.length,
+            swerve_module.height,
+        ] {
+            hasher.f32(value);
+        }
+    }
+
+    hasher.bool(description.rules.is_some());
+    if let Some(rules) = &description.rules {
+        hasher.f32(rules.extension).f32(rules.height);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+
+    /// Transforms the bodies were spawned at before the robot was described
+    /// in robot.ron, in sim units and radians
+    const BASELINE_SPAWN: [(&str, Vec2, f32); 5] = [
+        ("elevator", Vec2::new(0.0, 0.0), 0.0),
+        ("carriage", Vec2::new(5.08, -13.65), 0.0),
+        ("arm", Vec2::new(24.765, -13.4), 0.0),
+        ("intake_pivot", Vec2::new(44.45, -13.4), FRAC_PI_4),
+        ("intake", Vec2::new(35.258, -3.808), FRAC_PI_4),
+    ];
+    /// The hand-placed baseline sat up to 0.65 units off its own joint
+    /// anchors, which Rapier pulled together on the first step
+    const BASELINE_TOLERANCE: f32 = 0.7;
+    const TOLERANCE: f32 = 1e-3;
+
+    fn robot() -> RobotConfig {
+        RobotConfig::parse(BUILTIN_ROBOT).expect("built-in robot description is valid")
+    }
+
+    fn link(robot: &RobotConfig, name: &str) -> usize {
+        robot
+            .links
+            .iter()
+            .position(|link| link.name == name)
+            .expect("link exists")
+    }
+
+    #[test]
+    fn spawn_pose_matches_baseline() {
+        let robot = robot();
+        let poses = robot.forward_kinematics(0.0, 0.0);
+        for (name, translation, rotation) in BASELINE_SPAWN {
+            let pose = poses[link(&robot, name)];
+            assert!(
+                pose.translation.distance(translation) < BASELINE_TOLERANCE,
+                "{} at {:?}, baseline {:?}",
+                name,
+                pose.translation,
+                translation
+            );
+            assert!((pose.rotation - rotation).abs() < TOLERANCE, "{}", name);
+        }
+    }
+
+    #[test]
+    fn spawn_pose_matches_joint_anchors() {
+        // Exact values the baseline anchors give from the elevator at the
+        // origin, so the tolerance above can't hide a drift
+        let robot = robot();
+        let poses = robot.forward_kinematics(0.0, 0.0);
+        let expected = [
+            ("carriage", Vec2::new(5.08, -13.65)),
+            ("arm", Vec2::new(24.765, -13.65)),
+            ("intake_pivot", Vec2::new(44.45, -13.65)),
+            ("intake", Vec2::new(35.258, -4.458)),
+        ];
+        for (name, translation) in expected {
+            let pose = poses[link(&robot, name)];
+            assert!(
+                pose.translation.distance(translation) < TOLERANCE,
+                "{} at {:?}, expected {:?}",
+                name,
+                pose.translation,
+                translation
+            );
+        }
+    }
+
+    #[test]
+    fn height_raises_every_carried_link() {
+        let robot = robot();
+        let bottom = robot.forward_kinematics(0.0, 0.0);
+        let raised = robot.forward_kinematics(20.0, 0.0);
+        for (i, link) in robot.links.iter().enumerate() {
+            let rise = if i == robot.base { 0.0 } else { 20.0 };
+            let offset = raised[i].translation - bottom[i].translation;
+            assert!(
+                offset.distance(Vec2::new(0.0, rise)) < TOLERANCE,
+                "{} moved by {:?}",
+                link.name,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn arm_angle_turns_the_arm_and_keeps_the_intake_level() {
+        let robot = robot();
+        let level = robot.forward_kinematics(0.0, 0.0);
+        let raised = robot.forward_kinematics(0.0, 90.0f32.to_radians());
+        let arm = link(&robot, "arm");
+        let intake = robot.intake_index();
+
+        // The arm turns about the carriage, so its center swings up by its
+        // distance from the pivot
+        let carriage = level[link(&robot, "carriage")].translation;
+        let reach = level[arm].translation - carriage;
+        let swung = raised[arm].translation - carriage;
+        assert!(swung.distance(reach.perp()) < TOLERANCE);
+        assert!((raised[arm].rotation - 90.0f32.to_radians()).abs() < TOLERANCE);
+
+        // The pivot is rotation locked, so the intake hangs the same way
+        assert!((raised[intake].rotation - level[intake].rotation).abs() < TOLERANCE);
+        let tip = robot.arm_tip(robot.elevator_offset(), 90.0f32.to_radians());
+        let (mount, _) = robot.intake_mount();
+        assert!(raised[intake].translation.distance(tip + mount) < TOLERANCE);
+    }
+
+    #[test]
+    fn enforcing_the_rules_keeps_the_geometry_hash() {
+        let enforced = BUILTIN_ROBOT.replace("enforce: false", "enforce: true");
+        assert_ne!(enforced, BUILTIN_ROBOT);
+        let enforced = RobotConfig::parse(&enforced).unwrap();
+        assert!(enforced.rules.is_some_and(|rules| rules.enforce));
+        assert_eq!(enforced.geometry_hash, robot().geometry_hash);
+
+        let taller = BUILTIN_ROBOT.replace("height: 78.0", "height: 80.0");
+        assert_ne!(
+            RobotConfig::parse(&taller).unwrap().geometry_hash,
+            robot().geometry_hash
+        );
+    }
+
+    #[test]
+    fn committed_grids_match_the_robot() {
+        use crate::simulations::grid_file::GridFile;
+
+        let robot = robot();
+        for name in ["collision_grid.bin", "cspace_grid.bin"] {
+            let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name);
+            let file = GridFile::load(&path).unwrap();
+            assert_eq!(file.geometry_hash, robot.geometry_hash, "{}", name);
+        }
+    }
+}
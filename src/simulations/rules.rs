@@ -0,0 +1,251 @@
+//! Reach limits from the 2025 game rules. A robot may not extend more than a
+//! set distance beyond its frame perimeter or stand taller than a set height
+//! above the carpet, so both are measured from the robot's chassis.
+
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::simulations::robot::{Chassis, LinkPose, Obb, RobotConfig, SIM_UNITS_PER_INCH};
+
+/// Limits in inches. Missing fields take the 2025 game manual values.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct RulesDescription {
+    /// Reach beyond the frame perimeter, front or back
+    pub extension: f32,
+    /// Robot height above the carpet
+    pub height: f32,
+    /// Block poses that break the limits instead of only flagging them
+    pub enforce: bool,
+}
+
+impl Default for RulesDescription {
+    fn default() -> Self {
+        Self {
+            extension: 18.0,
+            height: 78.0,
+            enforce: false,
+        }
+    }
+}
+
+/// Limits in sim units, in the same world frame as the links
+#[derive(Debug, Clone, Copy)]
+pub struct Rules {
+    /// Furthest the robot may reach forwards and backwards, as world x
+    pub front: f32,
+    pub back: f32,
+    /// Highest the robot may reach, as world y
+    pub top: f32,
+    pub enforce: bool,
+}
+
+/// Box around the robot's links in the world frame
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Extent {
+    /// Box around the corners of the boxes, `None` without any
+    pub fn of(boxes: impl IntoIterator<Item = Obb>) -> Option<Self> {
+        boxes
+            .into_iter()
+            .flat_map(|obb| obb.corners())
+            .map(|corner| Self {
+                min: corner,
+                max: corner,
+            })
+            .reduce(|a, b| Self {
+                min: a.min.min(b.min),
+                max: a.max.max(b.max),
+            })
+    }
+
+    /// Every link of the robot at an elevator height above its lowest point
+    /// and an arm angle
+    pub fn of_pose(robot: &RobotConfig, height: f32, arm_angle: f32) -> Self {
+        Self::of(robot.forward_kinematics(height, arm_angle).boxes)
+            .expect("a robot has at least one link")
+    }
+}
+
+/// A limit the robot breaks, with how far past it in sim units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    Front(f32),
+    Back(f32),
+    Height(f32),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, beyond) = match *self {
+            Self::Front(beyond) => ("past the front extension limit", beyond),
+            Self::Back(beyond) => ("past the back extension limit", beyond),
+            Self::Height(beyond) => ("over the height limit", beyond),
+        };
+        write!(f, "{:.1} in {}", beyond / SIM_UNITS_PER_INCH, what)
+    }
+}
+
+impl Rules {
+    pub fn new(description: &RulesDescription, chassis: &Chassis) -> Self {
+        let extension = description.extension * SIM_UNITS_PER_INCH;
+        Self {
+            front: chassis.frame_front + extension,
+            back: -(chassis.frame_back + extension),
+            top: chassis.floor + description.height * SIM_UNITS_PER_INCH,
+            enforce: description.enforce,
+        }
+    }
+
+    /// Limits the extent breaks, empty if it is legal
+    pub fn violations(&self, extent: &Extent) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        if extent.max.x > self.front {
+            violations.push(Violation::Front(extent.max.x - self.front));
+        }
+        if extent.min.x < self.back {
+            violations.push(Violation::Back(self.back - extent.min.x));
+        }
+        if extent.max.y > self.top {
+            violations.push(Violation::Height(extent.max.y - self.top));
+        }
+        violations
+    }
+
+    /// Limits the robot breaks at an elevator height above its lowest point
+    /// and an arm angle
+    pub fn check_pose(&self, robot: &RobotConfig, height: f32, arm_angle: f32) -> Vec<Violation> {
+        self.violations(&Extent::of_pose(robot, height, arm_angle))
+    }
+
+    /// Whether the intake alone breaks a limit with its center at `position`.
+    /// The intake reaches furthest of all the links, so the collision grid
+    /// records this for each arm tip position.
+    pub fn intake_breaks(&self, robot: &RobotConfig, position: Vec2, rotation: f32) -> bool {
+        let pose = LinkPose {
+            translation: position,
+            rotation,
+        };
+        Extent::of([robot.intake().shape.bounding_box(&pose)])
+            .is_some_and(|extent| !self.violations(&extent).is_empty())
+    }
+}
+
+/// Describes violations for a warning, e.g. "2.5 in over the height limit"
+pub fn describe(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(Violation::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 1e-3;
+
+    fn inches(value: f32) -> f32 {
+        value * SIM_UNITS_PER_INCH
+    }
+
+    /// Frame 12 in ahead of the elevator and 10 in behind it, on a carpet
+    /// 20 in below it
+    fn rules() -> Rules {
+        let chassis = Chassis {
+            floor: inches(-20.0),
+            frame_front: inches(12.0),
+            frame_back: inches(10.0),
+            bumper_thickness: inches(3.25),
+            parts: Vec::new(),
+        };
+        Rules::new(&RulesDescription::default(), &chassis)
+    }
+
+    fn extent(min: (f32, f32), max: (f32, f32)) -> Extent {
+        Extent {
+            min: Vec2::new(inches(min.0), inches(min.1)),
+            max: Vec2::new(inches(max.0), inches(max.1)),
+        }
+    }
+
+    fn assert_overshoot(violation: Violation, expected: Violation) {
+        let value = |violation| match violation {
+            Violation::Front(beyond) | Violation::Back(beyond) | Violation::Height(beyond) => {
+                beyond
+            }
+        };
+        assert_eq!(
+            std::mem::discriminant(&violation),
+            std::mem::discriminant(&expected)
+        );
+        assert!(
+            (value(violation) - value(expected)).abs() < TOLERANCE,
+            "{:?} != {:?}",
+            violation,
+            expected
+        );
+    }
+
+    #[test]
+    fn measures_limits_from_the_chassis() {
+        let rules = rules();
+        assert!((rules.front - inches(30.0)).abs() < TOLERANCE);
+        assert!((rules.back - inches(-28.0)).abs() < TOLERANCE);
+        assert!((rules.top - inches(58.0)).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn reports_each_limit_with_its_overshoot() {
+        let rules = rules();
+        assert!(rules
+            .violations(&extent((-27.0, -20.0), (29.0, 57.0)))
+            .is_empty());
+
+        let front = rules.violations(&extent((-27.0, -20.0), (32.5, 57.0)));
+        assert_eq!(front.len(), 1);
+        assert_overshoot(front[0], Violation::Front(inches(2.5)));
+
+        let back = rules.violations(&extent((-29.0, -20.0), (29.0, 57.0)));
+        assert_eq!(back.len(), 1);
+        assert_overshoot(back[0], Violation::Back(inches(1.0)));
+
+        let height = rules.violations(&extent((-27.0, -20.0), (29.0, 62.0)));
+        assert_eq!(height.len(), 1);
+        assert_overshoot(height[0], Violation::Height(inches(4.0)));
+
+        let all = rules.violations(&extent((-30.0, -20.0), (31.0, 60.0)));
+        assert_eq!(all.len(), 3);
+        assert_overshoot(all[0], Violation::Front(inches(1.0)));
+        assert_overshoot(all[1], Violation::Back(inches(2.0)));
+        assert_overshoot(all[2], Violation::Height(inches(2.0)));
+        assert_eq!(describe(&all[2..]), "2.0 in over the height limit");
+    }
+
+    #[test]
+    fn intake_breaks_by_its_own_box() {
+        // The intake is 15 in long and 5.256 in tall
+        let robot = RobotConfig::parse(include_str!("../../robot.ron")).unwrap();
+        let rules = rules();
+        let at = |x: f32, y: f32| Vec2::new(inches(x), inches(y));
+
+        assert!(!rules.intake_breaks(&robot, at(22.0, 0.0), 0.0));
+        assert!(rules.intake_breaks(&robot, at(23.0, 0.0), 0.0));
+        assert!(!rules.intake_breaks(&robot, at(-20.0, 0.0), 0.0));
+        assert!(rules.intake_breaks(&robot, at(-21.0, 0.0), 0.0));
+        assert!(!rules.intake_breaks(&robot, at(0.0, 55.0), 0.0));
+        assert!(rules.intake_breaks(&robot, at(0.0, 56.0), 0.0));
+
+        // Standing on end it is narrower and taller
+        let upright = std::f32::consts::FRAC_PI_2;
+        assert!(!rules.intake_breaks(&robot, at(27.0, 0.0), upright));
+        assert!(rules.intake_breaks(&robot, at(0.0, 51.0), upright));
+    }
+}
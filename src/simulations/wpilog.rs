@@ -0,0 +1,388 @@
+//! WPILib data log files (`.wpilog`), as read by AdvantageScope and written
+//! by WPILib's `DataLogManager`.
+//!
+//! Layout (all values little endian):
+//!
+//! | field          | type                                      |
+//! |----------------|-------------------------------------------|
+//! | magic          | `b"WPILOG"`                               |
+//! | version        | u16 (0x0100)                              |
+//! | extra header   | u32 length + UTF-8                        |
+//! | records        | repeated until the end of the file        |
+//!
+//! Each record starts with a bitfield byte giving the byte lengths (minus
+//! one) of the fields that follow: entry id in bits 0-1, payload size in bits
+//! 2-3 and timestamp in bits 4-6. Then come the entry id, payload size,
+//! timestamp in microseconds and payload.
+//!
+//! Entry 0 carries control records. A start record (payload byte 0) assigns
+//! an entry id a name, type and metadata, each a u32 length + UTF-8 string.
+//! A finish record (1) frees the id for reuse and a set metadata record (2)
+//! replaces an entry's metadata.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 6] = b"WPILOG";
+const VERSION: u16 = 0x0100;
+const CONTROL_ENTRY: u32 = 0;
+const CONTROL_START: u8 = 0;
+const CONTROL_FINISH: u8 = 1;
+const CONTROL_SET_METADATA: u8 = 2;
+
+pub struct WpiLogWriter {
+    out: BufWriter<File>,
+    next_entry: u32,
+}
+
+impl WpiLogWriter {
+    pub fn create(path: &Path, extra_header: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        write_string(&mut out, extra_header)?;
+        Ok(Self { out, next_entry: 1 })
+    }
+
+    /// Declares an entry, e.g. `("/Sim/Arm/Angle", "double")`, and returns the
+    /// id to append its values with
+    pub fn start_entry(&mut self, name: &str, type_name: &str, timestamp: u64) -> io::Result<u32> {
+        let entry = self.next_entry;
+        self.next_entry += 1;
+
+        let mut payload = vec![CONTROL_START];
+        payload.extend_from_slice(&entry.to_le_bytes());
+        write_string(&mut payload, name)?;
+        write_string(&mut payload, type_name)?;
+        write_string(&mut payload, "")?;
+        self.write_record(CONTROL_ENTRY, timestamp, &payload)?;
+        Ok(entry)
+    }
+
+    pub fn append_double(&mut self, entry: u32, timestamp: u64, value: f64) -> io::Result<()> {
+        self.write_record(entry, timestamp, &value.to_le_bytes())
+    }
+
+    pub fn append_string(&mut self, entry: u32, timestamp: u64, value: &str) -> io::Result<()> {
+        self.write_record(entry, timestamp, value.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn write_record(&mut self, entry: u32, timestamp: u64, payload: &[u8]) -> io::Result<()> {
+        let entry_bytes = &entry.to_le_bytes()[..min_length(entry as u64, 4)];
+        let size = payload.len() as u32;
+        let size_bytes = &size.to_le_bytes()[..min_length(size as u64, 4)];
+        let timestamp_bytes = &timestamp.to_le_bytes()[..min_length(timestamp, 8)];
+
+        let bitfield = (entry_bytes.len() - 1)
+            | (size_bytes.len() - 1) << 2
+            | (timestamp_bytes.len() - 1) << 4;
+        self.out.write_all(&[bitfield as u8])?;
+        self.out.write_all(entry_bytes)?;
+        self.out.write_all(size_bytes)?;
+        self.out.write_all(timestamp_bytes)?;
+        self.out.write_all(payload)
+    }
+}
+
+/// Fewest bytes (at least one) that hold `value`
+fn min_length(value: u64, max: usize) -> usize {
+    let bytes = (64 - value.leading_zeros() as usize).div_ceil(8);
+    bytes.clamp(1, max)
+}
+
+fn write_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    out.write_all(&(value.len() as u32).to_le_bytes())?;
+    out.write_all(value.as_bytes())
+}
+
+/// One entry of a log read back from disk, with every value recorded for it
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub name: String,
+    pub type_name: String,
+    pub metadata: String,
+    /// Timestamp in microseconds and raw payload of each record
+    pub records: Vec<(u64, Vec<u8>)>,
+}
+
+impl LogEntry {
+    /// Values of a numeric entry in seconds, or `None` for other types
+    pub fn numbers(&self) -> Option<Vec<(f64, f64)>> {
+        let decode = |payload: &[u8]| -> Option<f64> {
+            match self.type_name.as_str() {
+                "double" => Some(f64::from_le_bytes(payload.try_into().ok()?)),
+                "float" => Some(f32::from_le_bytes(payload.try_into().ok()?) as f64),
+                "int64" => Some(i64::from_le_bytes(payload.try_into().ok()?) as f64),
+                _ => None,
+            }
+        };
+        self.records
+            .iter()
+            .map(|(timestamp, payload)| Some((*timestamp as f64 / 1e6, decode(payload)?)))
+            .collect()
+    }
+}
+
+/// A whole log file read into memory
+#[derive(Debug, Clone)]
+pub struct WpiLog {
+    /// In the order they were started
+    pub entries: Vec<LogEntry>,
+}
+
+impl WpiLog {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a WPILOG file"));
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version >> 8 != VERSION >> 8 {
+            return Err(invalid(&format!("unsupported version {:#06x}", version)));
+        }
+        // The extra header is free-form text we have no use for
+        reader.string()?;
+
+        let mut entries: Vec<LogEntry> = Vec::new();
+        // Entry id to index in `entries` for the entries currently started
+        let mut active: HashMap<u32, usize> = HashMap::new();
+        while !reader.bytes.is_empty() {
+            // A robot that loses power can leave a partial record at the end
+            let Ok((entry, timestamp, payload)) = reader.record() else {
+                break;
+            };
+
+            if entry != CONTROL_ENTRY {
+                // Records for entries that were never started are skipped,
+                // like WPILib's reader does
+                if let Some(&index) = active.get(&entry) {
+                    entries[index].records.push((timestamp, payload.to_vec()));
+                }
+                continue;
+            }
+
+            let mut control = Reader { bytes: payload };
+            let kind = control.take(1)?[0];
+            let id = u32::from_le_bytes(control.array()?);
+            match kind {
+                CONTROL_START => {
+                    active.insert(id, entries.len());
+                    entries.push(LogEntry {
+                        name: control.string()?,
+                        type_name: control.string()?,
+                        metadata: control.string()?,
+                        records: Vec::new(),
+                    });
+                }
+                CONTROL_FINISH => {
+                    active.remove(&id);
+                }
+                CONTROL_SET_METADATA => {
+                    if let Some(&index) = active.get(&id) {
+                        entries[index].metadata = control.string()?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// First entry with exactly this name
+    pub fn entry(&self, name: &str) -> Option<&LogEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if length > self.bytes.len() {
+            return Err(invalid("unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    /// Entry id, timestamp and payload of the next record
+    fn record(&mut self) -> io::Result<(u32, u64, &'a [u8])> {
+        let bitfield = self.take(1)?[0];
+        let entry = self.uint(1 + (bitfield & 0x3) as usize)? as u32;
+        let size = self.uint(1 + (bitfield >> 2 & 0x3) as usize)? as usize;
+        let timestamp = self.uint(1 + (bitfield >> 4 & 0x7) as usize)?;
+        Ok((entry, timestamp, self.take(size)?))
+    }
+
+    /// Little endian unsigned integer of `length` bytes
+    fn uint(&mut self, length: usize) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes[..length].copy_from_slice(self.take(length)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = u32::from_le_bytes(self.array()?) as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| invalid("invalid UTF-8"))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Log file in the temp directory, removed when dropped
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "frc_2025_arm_sim_{}_{}.wpilog",
+                name,
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let file = TempLog::new("round_trip");
+        // Timestamps, ids and payloads that need more than one byte
+        let long_text = "x".repeat(300);
+        let timestamps = [0, 255, 256, 70_000, 1 << 40];
+        {
+            let mut log = WpiLogWriter::create(&file.0, "test header").unwrap();
+            let height = log
+                .start_entry("/Sim/Elevator/Position", "double", 0)
+                .unwrap();
+            let mode = log.start_entry("/Sim/Mode", "string", 10).unwrap();
+            for (i, &timestamp) in timestamps.iter().enumerate() {
+                log.append_double(height, timestamp, i as f64 * 0.25 - 1.0)
+                    .unwrap();
+            }
+            log.append_string(mode, 20, "Stow").unwrap();
+            log.append_string(mode, 30, &long_text).unwrap();
+            let mut last = mode;
+            for i in 0..300 {
+                last = log
+                    .start_entry(&format!("/Filler/{}", i), "double", 40)
+                    .unwrap();
+            }
+            assert!(last > 255);
+            log.append_double(last, 50, 7.5).unwrap();
+            log.flush().unwrap();
+        }
+
+        let log = WpiLog::read(&file.0).unwrap();
+        assert_eq!(log.entries.len(), 302);
+
+        let height = log.entry("/Sim/Elevator/Position").unwrap();
+        assert_eq!(height.type_name, "double");
+        assert_eq!(height.metadata, "");
+        let expected: Vec<(f64, f64)> = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, &timestamp)| (timestamp as f64 / 1e6, i as f64 * 0.25 - 1.0))
+            .collect();
+        assert_eq!(height.numbers(), Some(expected));
+
+        let mode = log.entry("/Sim/Mode").unwrap();
+        assert_eq!(mode.numbers(), None);
+        assert_eq!(
+            mode.records,
+            vec![(20, b"Stow".to_vec()), (30, long_text.as_bytes().to_vec())]
+        );
+
+        let last = log.entry("/Filler/299").unwrap();
+        assert_eq!(last.numbers(), Some(vec![(50e-6, 7.5)]));
+    }
+
+    #[test]
+    fn keeps_records_before_a_partial_one() {
+        let file = TempLog::new("partial");
+        {
+            let mut log = WpiLogWriter::create(&file.0, "").unwrap();
+            let angle = log.start_entry("/Sim/Arm/Angle", "double", 0).unwrap();
+            log.append_double(angle, 1, 0.5).unwrap();
+            log.append_double(angle, 2, 1.5).unwrap();
+            log.flush().unwrap();
+        }
+        let bytes = fs::read(&file.0).unwrap();
+
+        let log = WpiLog::parse(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(
+            log.entry("/Sim/Arm/Angle").unwrap().numbers(),
+            Some(vec![(1e-6, 0.5)])
+        );
+    }
+
+    #[test]
+    fn reads_finish_and_metadata_records() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        write_string(&mut bytes, "").unwrap();
+        let mut record = |entry: u8, payload: &[u8]| {
+            bytes.extend_from_slice(&[0, entry, payload.len() as u8, 0]);
+            bytes.extend_from_slice(payload);
+        };
+        let control = |kind: u8, entry: u32, strings: &[&str]| {
+            let mut payload = vec![kind];
+            payload.extend_from_slice(&entry.to_le_bytes());
+            for string in strings {
+                write_string(&mut payload, string).unwrap();
+            }
+            payload
+        };
+
+        record(0, &control(CONTROL_START, 1, &["/A", "int64", ""]));
+        record(1, &3i64.to_le_bytes());
+        record(0, &control(CONTROL_SET_METADATA, 1, &["{\"unit\": \"m\"}"]));
+        record(0, &control(CONTROL_FINISH, 1, &[]));
+        // Dropped, the entry was finished
+        record(1, &4i64.to_le_bytes());
+        record(0, &control(CONTROL_START, 1, &["/B", "double", ""]));
+        record(1, &2.5f64.to_le_bytes());
+
+        let log = WpiLog::parse(&bytes).unwrap();
+        let a = log.entry("/A").unwrap();
+        assert_eq!(a.metadata, "{\"unit\": \"m\"}");
+        assert_eq!(a.numbers(), Some(vec![(0.0, 3.0)]));
+        assert_eq!(log.entry("/B").unwrap().numbers(), Some(vec![(0.0, 2.5)]));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(WpiLog::parse(b"NOTLOG\x00\x01\x00\x00\x00\x00").is_err());
+        assert!(WpiLog::parse(b"WPILOG\x00\x02\x00\x00\x00\x00").is_err());
+        assert!(WpiLog::parse(b"WPILOG\x00\x01\x05\x00\x00\x00").is_err());
+    }
+}